@@ -40,7 +40,7 @@ fn main() -> anyhow::Result<()> {
     println!("Balance (pending): {}", wallet.balance());
 
     let (tip_height, tip_time) = wallet.tip_info(env.rpc_client())?;
-    let longterm_feerate = FeeRate::from_sat_per_vb_unchecked(1);
+    let longterm_feerate = FeeRate::from_sat_per_vb_u32(1);
 
     let recipient_addr = env
         .rpc_client()
@@ -55,7 +55,7 @@ fn main() -> anyhow::Result<()> {
         .into_selection(
             selection_algorithm_lowest_fee_bnb(longterm_feerate, 100_000),
             SelectorParams::new(
-                FeeRate::from_sat_per_vb_unchecked(10),
+                FeeRate::from_sat_per_vb_u32(10),
                 vec![Output::with_script(
                     recipient_addr.script_pubkey(),
                     Amount::from_sat(21_000_000),
@@ -126,22 +126,24 @@ fn main() -> anyhow::Result<()> {
             .into_selection(
                 // Coin selection algorithm.
                 selection_algorithm_lowest_fee_bnb(longterm_feerate, 100_000),
-                SelectorParams {
-                    // This is just a lower-bound feerate. The actual result will be much higher to
-                    // satisfy mempool-replacement policy.
-                    target_feerate: FeeRate::from_sat_per_vb_unchecked(1),
-                    // We cancel the tx by specifying no target outputs. This way, all excess returns
-                    // to our change output (unless if the prevouts picked are so small that it will
-                    // be less wasteful to have no output, however that will not be a valid tx).
-                    // If you only want to fee bump, put the original txs' recipients here.
-                    target_outputs: vec![],
-                    change_script: ScriptSource::Descriptor(Box::new(
-                        internal.at_derivation_index(1)?,
-                    )),
-                    change_policy: ChangePolicyType::NoDustAndLeastWaste { longterm_feerate },
-                    change_weight: wallet.change_weight(),
+                {
+                    let mut params = SelectorParams::new(
+                        // This is just a lower-bound feerate. The actual result will be much
+                        // higher to satisfy mempool-replacement policy.
+                        FeeRate::from_sat_per_vb_u32(1),
+                        // We cancel the tx by specifying no target outputs. This way, all excess
+                        // returns to our change output (unless if the prevouts picked are so small
+                        // that it will be less wasteful to have no output, however that will not
+                        // be a valid tx). If you only want to fee bump, put the original txs'
+                        // recipients here.
+                        vec![],
+                        ScriptSource::Descriptor(Box::new(internal.at_derivation_index(1)?)),
+                        ChangePolicyType::NoDustAndLeastWaste { longterm_feerate },
+                        wallet.change_weight(),
+                    );
                     // This ensures that we satisfy mempool-replacement policy rules 4 and 6.
-                    replace: Some(rbf_params),
+                    params.replace = Some(rbf_params);
+                    params
                 },
             )?;
 
@@ -0,0 +1,574 @@
+use alloc::vec::Vec;
+use core::fmt::Display;
+
+use bdk_coin_select::{TXIN_BASE_WEIGHT, TX_FIXED_FIELD_WEIGHT};
+use bitcoin::{Amount, FeeRate, OutPoint, Psbt, VarInt, Weight};
+use miniscript::bitcoin;
+
+use crate::Selection;
+
+/// Parameters for [`Selection::analyze`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnalyzeParams {
+    /// Warn if the absolute fee exceeds this amount, default is 0.1 BTC.
+    pub max_fee: Amount,
+    /// Warn if the effective feerate exceeds this feerate, default is 10,000 sat/vB.
+    pub max_feerate: FeeRate,
+}
+
+impl Default for AnalyzeParams {
+    fn default() -> Self {
+        Self {
+            max_fee: Amount::from_sat(10_000_000),
+            max_feerate: FeeRate::from_sat_per_vb_u32(10_000),
+        }
+    }
+}
+
+/// A structured report of a [`Selection`], intended to be inspected before signing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectionReport {
+    /// Sum of all input values.
+    pub input_value: Amount,
+    /// Sum of all output values (including change).
+    pub output_value: Amount,
+    /// Sum of all change output values.
+    pub change_value: Amount,
+    /// Exact fee paid by the transaction.
+    pub fee: Amount,
+    /// Predicted weight of the finalized transaction.
+    pub weight: Weight,
+    /// Effective feerate from the predicted weight.
+    pub feerate: FeeRate,
+    /// Indices of outputs that are below the dust threshold.
+    pub dust_outputs: Vec<usize>,
+    /// Percentage of the input value which is returned as change.
+    pub change_percentage: f32,
+    /// Warnings about the selection.
+    pub warnings: Vec<SelectionWarning>,
+}
+
+impl SelectionReport {
+    /// Whether the report contains any warnings.
+    pub fn has_warnings(&self) -> bool {
+        !self.warnings.is_empty()
+    }
+}
+
+/// Warning raised by [`Selection::analyze`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionWarning {
+    /// The absolute fee exceeds [`AnalyzeParams::max_fee`].
+    InsaneFee {
+        /// The fee of the selection.
+        fee: Amount,
+        /// The maximum fee allowed.
+        max_fee: Amount,
+    },
+    /// The effective feerate exceeds [`AnalyzeParams::max_feerate`].
+    InsaneFeerate {
+        /// The effective feerate of the selection.
+        feerate: FeeRate,
+        /// The maximum feerate allowed.
+        max_feerate: FeeRate,
+    },
+}
+
+impl Display for SelectionWarning {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SelectionWarning::InsaneFee { fee, max_fee } => {
+                write!(f, "insane fee: {} exceeds {}", fee, max_fee)
+            }
+            SelectionWarning::InsaneFeerate {
+                feerate,
+                max_feerate,
+            } => write!(
+                f,
+                "insane feerate: {} sat/vB exceeds {} sat/vB",
+                feerate.to_sat_per_vb_floor(),
+                max_feerate.to_sat_per_vb_floor()
+            ),
+        }
+    }
+}
+
+/// Error returned by [`Selection::analyze`] when outputs exceed inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NegativeFee {
+    /// Sum of all input values.
+    pub input_value: Amount,
+    /// Sum of all output values.
+    pub output_value: Amount,
+}
+
+impl Display for NegativeFee {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "negative fee: outputs ({}) exceed inputs ({})",
+            self.output_value, self.input_value
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for NegativeFee {}
+
+fn effective_feerate(fee: Amount, weight: Weight) -> FeeRate {
+    FeeRate::from_sat_per_kwu(fee.to_sat() * 1000 / weight.to_wu().max(1))
+}
+
+impl Selection {
+    /// Predicted weight of the finalized transaction.
+    ///
+    /// This uses the same estimate as coin selection, which is based on the satisfaction weight of
+    /// each input.
+    pub fn predicted_weight(&self) -> Weight {
+        let is_segwit = self.inputs.iter().any(|input| input.is_segwit());
+        let input_weight = self
+            .inputs
+            .iter()
+            .map(|input| {
+                let mut weight = TXIN_BASE_WEIGHT + input.satisfaction_weight();
+                if is_segwit && !input.is_segwit() {
+                    // empty witness stack
+                    weight += 1;
+                }
+                weight
+            })
+            .sum::<u64>();
+        let output_weight = self
+            .outputs
+            .iter()
+            .map(|output| output.txout().weight().to_wu())
+            .sum::<u64>();
+        Weight::from_wu(
+            TX_FIXED_FIELD_WEIGHT
+                + VarInt::from(self.inputs.len()).size() as u64 * 4
+                + input_weight
+                + if is_segwit { 2 } else { 0 }
+                + VarInt::from(self.outputs.len()).size() as u64 * 4
+                + output_weight,
+        )
+    }
+
+    /// Analyze this selection, returning a [`SelectionReport`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if the output value exceeds the input value.
+    pub fn analyze(&self, params: AnalyzeParams) -> Result<SelectionReport, NegativeFee> {
+        let input_value = self
+            .inputs
+            .iter()
+            .map(|input| input.prev_txout().value)
+            .sum::<Amount>();
+        let output_value = self
+            .outputs
+            .iter()
+            .map(|output| output.value)
+            .sum::<Amount>();
+        let change_value = self
+            .change_indices
+            .iter()
+            .filter_map(|&i| self.outputs.get(i))
+            .map(|output| output.value)
+            .sum::<Amount>();
+        let fee = input_value.checked_sub(output_value).ok_or(NegativeFee {
+            input_value,
+            output_value,
+        })?;
+
+        let weight = self.predicted_weight();
+        let feerate = effective_feerate(fee, weight);
+
+        let dust_outputs = self
+            .outputs
+            .iter()
+            .enumerate()
+            .filter(|(_, output)| output.value < output.script_pubkey().minimal_non_dust())
+            .map(|(i, _)| i)
+            .collect();
+
+        let change_percentage = if input_value == Amount::ZERO {
+            0.0
+        } else {
+            (change_value.to_sat() as f64 * 100.0 / input_value.to_sat() as f64) as f32
+        };
+
+        let mut warnings = Vec::new();
+        if fee > params.max_fee {
+            warnings.push(SelectionWarning::InsaneFee {
+                fee,
+                max_fee: params.max_fee,
+            });
+        }
+        if feerate > params.max_feerate {
+            warnings.push(SelectionWarning::InsaneFeerate {
+                feerate,
+                max_feerate: params.max_feerate,
+            });
+        }
+
+        Ok(SelectionReport {
+            input_value,
+            output_value,
+            change_value,
+            fee,
+            weight,
+            feerate,
+            dust_outputs,
+            change_percentage,
+            warnings,
+        })
+    }
+}
+
+/// Predicted vs. actual satisfaction weight of a single input, see [`Selection::reconcile`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputWeightReport {
+    /// Outpoint spent by the input.
+    pub prev_outpoint: OutPoint,
+    /// Satisfaction weight predicted by the plan (or provided for a psbt input).
+    pub predicted: Weight,
+    /// Actual weight of the finalized scriptSig (including its length prefix).
+    pub actual_script_sig: Weight,
+    /// Actual weight of the finalized witness (including the item count).
+    pub actual_witness: Weight,
+}
+
+impl InputWeightReport {
+    /// Actual satisfaction weight of the finalized input.
+    pub fn actual(&self) -> Weight {
+        self.actual_script_sig + self.actual_witness
+    }
+
+    /// Whether the actual satisfaction weight exceeds the predicted one.
+    pub fn is_underestimated(&self) -> bool {
+        self.actual() > self.predicted
+    }
+}
+
+/// Reconciliation of the predicted weight of a [`Selection`] against its finalized [`Psbt`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WeightReconciliation {
+    /// Per input report, in the order of the psbt inputs.
+    pub inputs: Vec<InputWeightReport>,
+    /// Predicted weight of the transaction, see [`Selection::predicted_weight`].
+    pub predicted_weight: Weight,
+    /// Actual weight of the finalized transaction.
+    pub actual_weight: Weight,
+    /// Fee paid by the transaction.
+    pub fee: Amount,
+    /// Real feerate of the finalized transaction.
+    pub feerate: FeeRate,
+    /// The feerate we were aiming for.
+    pub target_feerate: FeeRate,
+}
+
+impl WeightReconciliation {
+    /// Whether the real feerate falls below the target feerate.
+    ///
+    /// Such transactions may get stuck and require fee-bumping.
+    pub fn is_underpaying(&self) -> bool {
+        self.feerate < self.target_feerate
+    }
+}
+
+/// Error returned by [`Selection::reconcile`].
+#[derive(Debug)]
+pub enum ReconcileError {
+    /// A selected input is not spent by the psbt.
+    MissingInput(OutPoint),
+    /// The psbt spends an input which is not in the selection.
+    UnknownInput(OutPoint),
+    /// The psbt input at the given index is not finalized.
+    NotFinalized(usize),
+    /// Psbt error.
+    Psbt(bitcoin::psbt::Error),
+}
+
+impl Display for ReconcileError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ReconcileError::MissingInput(outpoint) => {
+                write!(f, "psbt does not spend selected input {}", outpoint)
+            }
+            ReconcileError::UnknownInput(outpoint) => {
+                write!(f, "psbt spends input {} which is not selected", outpoint)
+            }
+            ReconcileError::NotFinalized(index) => {
+                write!(f, "psbt input {} is not finalized", index)
+            }
+            ReconcileError::Psbt(error) => Display::fmt(&error, f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ReconcileError {}
+
+impl Selection {
+    /// Compare the predicted weights of this selection with the finalized `psbt`.
+    ///
+    /// The `target_feerate` should be the [`SelectorParams::target_feerate`] used to create this
+    /// selection.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the `psbt` does not spend exactly the selected inputs, if any psbt input is not
+    /// finalized or if the fee cannot be computed.
+    ///
+    /// [`SelectorParams::target_feerate`]: crate::SelectorParams::target_feerate
+    pub fn reconcile(
+        &self,
+        psbt: &Psbt,
+        target_feerate: FeeRate,
+    ) -> Result<WeightReconciliation, ReconcileError> {
+        let tx = &psbt.unsigned_tx;
+        let is_segwit = psbt
+            .inputs
+            .iter()
+            .any(|input| input.final_script_witness.is_some());
+
+        let mut inputs = Vec::with_capacity(tx.input.len());
+        for (index, (txin, psbt_input)) in tx.input.iter().zip(&psbt.inputs).enumerate() {
+            if psbt_input.final_script_sig.is_none() && psbt_input.final_script_witness.is_none() {
+                return Err(ReconcileError::NotFinalized(index));
+            }
+            let predicted = self
+                .inputs
+                .iter()
+                .find(|input| input.prev_outpoint() == txin.previous_output)
+                .ok_or(ReconcileError::UnknownInput(txin.previous_output))?
+                .satisfaction_weight();
+            let actual_script_sig = psbt_input
+                .final_script_sig
+                .as_ref()
+                .map_or(0, |s| (VarInt::from(s.len()).size() + s.len()) as u64 * 4);
+            let actual_witness = match &psbt_input.final_script_witness {
+                Some(witness) => witness.size() as u64,
+                // empty witness stack
+                None if is_segwit => 1,
+                None => 0,
+            };
+            inputs.push(InputWeightReport {
+                prev_outpoint: txin.previous_output,
+                predicted: Weight::from_wu(predicted),
+                actual_script_sig: Weight::from_wu(actual_script_sig),
+                actual_witness: Weight::from_wu(actual_witness),
+            });
+        }
+        if let Some(input) = self.inputs.iter().find(|input| {
+            !tx.input
+                .iter()
+                .any(|txin| txin.previous_output == input.prev_outpoint())
+        }) {
+            return Err(ReconcileError::MissingInput(input.prev_outpoint()));
+        }
+
+        let fee = psbt.fee().map_err(ReconcileError::Psbt)?;
+        let actual_weight = psbt.clone().extract_tx_unchecked_fee_rate().weight();
+        let feerate = effective_feerate(fee, actual_weight);
+
+        Ok(WeightReconciliation {
+            inputs,
+            predicted_weight: self.predicted_weight(),
+            actual_weight,
+            fee,
+            feerate,
+            target_feerate,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::{psbt, OutPoint, ScriptBuf, Sequence, TxOut, Txid, WPubkeyHash, Witness};
+
+    use crate::{Input, Output};
+
+    fn spk(n: u8) -> ScriptBuf {
+        ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([n; 20]))
+    }
+
+    fn input(vout: u32, value: u64) -> Input {
+        let psbt_input = psbt::Input {
+            witness_utxo: Some(TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: spk(0),
+            }),
+            final_script_witness: Some(Witness::new()),
+            ..Default::default()
+        };
+        Input::from_psbt_input(
+            OutPoint::new(Txid::all_zeros(), vout),
+            Sequence::ENABLE_RBF_NO_LOCKTIME,
+            psbt_input,
+            108,
+            None,
+            false,
+        )
+        .unwrap()
+    }
+
+    fn selection(inputs: &[u64], outputs: &[u64], change_indices: Vec<usize>) -> Selection {
+        Selection {
+            inputs: inputs
+                .iter()
+                .enumerate()
+                .map(|(vout, &value)| input(vout as u32, value))
+                .collect(),
+            outputs: outputs
+                .iter()
+                .enumerate()
+                .map(|(i, &value)| Output::with_script(spk(i as u8 + 1), Amount::from_sat(value)))
+                .collect(),
+            change_indices,
+            truc: false,
+        }
+    }
+
+    #[test]
+    fn analyze_report() {
+        let selection = selection(&[60_000, 40_000], &[70_000, 25_000], vec![1]);
+        let report = selection.analyze(AnalyzeParams::default()).unwrap();
+
+        assert_eq!(report.input_value, Amount::from_sat(100_000));
+        assert_eq!(report.output_value, Amount::from_sat(95_000));
+        assert_eq!(report.change_value, Amount::from_sat(25_000));
+        assert_eq!(report.fee, Amount::from_sat(5_000));
+        // 32 fixed + 4 in-count + 2 * (164 + 108) + 2 segwit header + 4 out-count + 2 * 124
+        assert_eq!(report.weight, Weight::from_wu(834));
+        assert_eq!(
+            report.feerate,
+            FeeRate::from_sat_per_kwu(5_000 * 1000 / 834)
+        );
+        assert!(report.dust_outputs.is_empty());
+        assert_eq!(report.change_percentage, 25.0);
+        assert!(!report.has_warnings());
+    }
+
+    #[test]
+    fn analyze_dust_outputs() {
+        let selection = selection(&[100_000], &[90_000, 200], vec![]);
+        let report = selection.analyze(AnalyzeParams::default()).unwrap();
+        assert_eq!(report.dust_outputs, vec![1]);
+    }
+
+    #[test]
+    fn analyze_insane_fee() {
+        let selection = selection(&[1_000_000; 3], &[1_000_000], vec![]);
+        let report = selection.analyze(AnalyzeParams::default()).unwrap();
+        assert_eq!(report.input_value, Amount::from_btc(0.03).unwrap());
+        assert_eq!(report.fee, Amount::from_btc(0.02).unwrap());
+        assert!(!report.has_warnings());
+
+        let report = selection
+            .analyze(AnalyzeParams {
+                max_fee: Amount::from_btc(0.01).unwrap(),
+                max_feerate: FeeRate::from_sat_per_vb_u32(5_000),
+            })
+            .unwrap();
+        assert!(report
+            .warnings
+            .iter()
+            .any(|w| matches!(w, SelectionWarning::InsaneFee { .. })));
+        assert!(report
+            .warnings
+            .iter()
+            .any(|w| matches!(w, SelectionWarning::InsaneFeerate { .. })));
+    }
+
+    #[test]
+    fn analyze_negative_fee() {
+        let selection = selection(&[1_000_000], &[2_000_000], vec![]);
+        assert_eq!(
+            selection.analyze(AnalyzeParams::default()),
+            Err(NegativeFee {
+                input_value: Amount::from_sat(1_000_000),
+                output_value: Amount::from_sat(2_000_000),
+            })
+        );
+    }
+
+    fn finalized_psbt(selection: &Selection) -> Psbt {
+        let mut psbt = selection
+            .create_psbt(crate::PsbtParams {
+                mandate_full_tx_for_segwit_v0: false,
+                ..Default::default()
+            })
+            .unwrap();
+        for psbt_input in &mut psbt.inputs {
+            // 72 byte signature and 33 byte pubkey
+            psbt_input.final_script_witness =
+                Some(Witness::from_slice(&[vec![0u8; 72], vec![0u8; 33]]));
+        }
+        psbt
+    }
+
+    #[test]
+    fn reconcile_weights() {
+        let selection = selection(&[60_000, 40_000], &[70_000, 25_000], vec![1]);
+        let psbt = finalized_psbt(&selection);
+        let target_feerate = FeeRate::from_sat_per_vb_u32(20);
+        let rec = selection.reconcile(&psbt, target_feerate).unwrap();
+
+        assert_eq!(rec.inputs.len(), 2);
+        for input in &rec.inputs {
+            assert_eq!(input.predicted, Weight::from_wu(108));
+            assert_eq!(input.actual(), Weight::from_wu(108));
+            assert!(!input.is_underestimated());
+        }
+        assert_eq!(rec.fee, Amount::from_sat(5_000));
+        assert_eq!(rec.actual_weight, psbt.extract_tx().unwrap().weight());
+        // the coin selection estimate includes the scriptSig length byte twice
+        assert!(rec.predicted_weight >= rec.actual_weight);
+        assert!(!rec.is_underpaying());
+
+        let rec = selection
+            .reconcile(
+                &finalized_psbt(&selection),
+                FeeRate::from_sat_per_vb_u32(50),
+            )
+            .unwrap();
+        assert!(rec.is_underpaying());
+    }
+
+    #[test]
+    fn reconcile_underestimated_input() {
+        let selection = selection(&[100_000], &[90_000], vec![]);
+        let mut psbt = finalized_psbt(&selection);
+        psbt.inputs[0].final_script_witness =
+            Some(Witness::from_slice(&[vec![0u8; 73], vec![0u8; 65]]));
+        let rec = selection
+            .reconcile(&psbt, FeeRate::from_sat_per_vb_u32(1))
+            .unwrap();
+        assert!(rec.inputs[0].is_underestimated());
+    }
+
+    #[test]
+    fn reconcile_requires_finalized_psbt() {
+        let other = selection(&[100_000, 50_000], &[90_000], vec![]);
+        let selection = selection(&[100_000], &[90_000], vec![]);
+        let mut psbt = finalized_psbt(&selection);
+        psbt.inputs[0].final_script_witness = None;
+        assert!(matches!(
+            selection.reconcile(&psbt, FeeRate::BROADCAST_MIN),
+            Err(ReconcileError::NotFinalized(0))
+        ));
+
+        assert!(matches!(
+            other.reconcile(&finalized_psbt(&selection), FeeRate::BROADCAST_MIN),
+            Err(ReconcileError::MissingInput(_))
+        ));
+        assert!(matches!(
+            selection.reconcile(&finalized_psbt(&other), FeeRate::BROADCAST_MIN),
+            Err(ReconcileError::UnknownInput(op)) if op.vout == 1
+        ));
+    }
+}
@@ -0,0 +1,299 @@
+use bitcoin::{Amount, Script};
+use miniscript::bitcoin;
+
+use crate::{
+    AnalyzeParams, InputCandidates, IntoSelectionError, RbfSet, Selection, Selector, SelectorParams,
+};
+
+/// Fees of merging new payments into the original txs vs. paying them with a separate tx.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchCost {
+    /// Total fee of the txs evicted by the replacement.
+    pub original_fee: Amount,
+    /// Fee of the replacement which pays both the original and new recipients.
+    pub replacement_fee: Amount,
+    /// Fee of a separate tx which only pays the new recipients.
+    pub separate_fee: Amount,
+}
+
+impl BatchCost {
+    /// The additional fee of merging, on top of what the original txs already pay.
+    pub fn merge_fee(&self) -> Amount {
+        self.replacement_fee
+            .checked_sub(self.original_fee)
+            .unwrap_or(Amount::ZERO)
+    }
+
+    /// Whether merging the new payments into the original txs is cheaper than a separate tx.
+    pub fn is_merge_cheaper(&self) -> bool {
+        self.merge_fee() < self.separate_fee
+    }
+}
+
+/// Result of [`RbfSet::batch`].
+#[derive(Debug, Clone)]
+pub struct Batch {
+    /// Replacement of the original txs which pays the original and new recipients.
+    pub replacement: Selection,
+    /// Separate tx which only pays the new recipients.
+    pub separate: Selection,
+    /// Cost comparison of the two.
+    pub cost: BatchCost,
+}
+
+impl RbfSet {
+    /// Selector params to merge the target outputs of `params` into the original txs.
+    ///
+    /// The replacement pays every recipient of the original txs followed by the target outputs of
+    /// `params`, and meets the RBF fee rules. The original change outputs are dropped, see
+    /// [`bump_fee_params`](Self::bump_fee_params).
+    ///
+    /// The [`subtract_fee`](SelectorParams::subtract_fee) indices of `params` keep pointing at
+    /// the same target outputs, so the original recipients never pay the fee. If `params` already
+    /// replace other txs, the original txs of this set are added to them.
+    pub fn batch_params<F>(&self, mut params: SelectorParams, is_change: F) -> SelectorParams
+    where
+        F: FnMut(&Script) -> bool,
+    {
+        let mut target_outputs = self.original_outputs(is_change).recipients;
+        if let Some(subtract_fee) = &mut params.subtract_fee {
+            for index in &mut subtract_fee.outputs {
+                *index += target_outputs.len();
+            }
+        }
+        target_outputs.extend(core::mem::take(&mut params.target_outputs));
+        params.target_outputs = target_outputs;
+        let rbf_params = self.selector_rbf_params();
+        params.replace = Some(match params.replace.take() {
+            Some(mut replace) => {
+                replace.original_txs.extend(rbf_params.original_txs);
+                replace.descendants.extend(rbf_params.descendants);
+                replace.incremental_relay_feerate = replace
+                    .incremental_relay_feerate
+                    .max(rbf_params.incremental_relay_feerate);
+                replace
+            }
+            None => rbf_params,
+        });
+        params
+    }
+
+    /// Merge the target outputs of `params` into the original txs, and compare the cost against
+    /// paying them with a separate tx.
+    ///
+    /// `replacement_candidates` are the candidates for the replacement, see
+    /// [`must_select_all_inputs`](Self::must_select_all_inputs) and
+    /// [`candidate_filter`](Self::candidate_filter). `separate_candidates` are the candidates for
+    /// a separate tx, which may spend the unconfirmed change of the original txs (i.e. a chained
+    /// tx). Both are selected with `algorithm`.
+    pub fn batch<A, E, F>(
+        &self,
+        replacement_candidates: InputCandidates,
+        separate_candidates: InputCandidates,
+        mut algorithm: A,
+        params: SelectorParams,
+        is_change: F,
+    ) -> Result<Batch, IntoSelectionError<E>>
+    where
+        A: FnMut(&mut Selector) -> Result<(), E>,
+        F: FnMut(&Script) -> bool,
+    {
+        let rbf_params = self.selector_rbf_params();
+        let original_fee = rbf_params
+            .original_txs
+            .iter()
+            .chain(&rbf_params.descendants)
+            .map(|otx| otx.fee)
+            .sum::<Amount>();
+        let replacement = replacement_candidates
+            .into_selection(&mut algorithm, self.batch_params(params.clone(), is_change))?;
+        let separate = separate_candidates.into_selection(&mut algorithm, params)?;
+        // Selections never pay a negative fee.
+        let fee = |selection: &Selection| {
+            selection
+                .analyze(AnalyzeParams::default())
+                .map_or(Amount::ZERO, |report| report.fee)
+        };
+        let cost = BatchCost {
+            original_fee,
+            replacement_fee: fee(&replacement),
+            separate_fee: fee(&separate),
+        };
+        Ok(Batch {
+            replacement,
+            separate,
+            cost,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use alloc::vec::Vec;
+    use bdk_coin_select::DrainWeights;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{
+        absolute, transaction, FeeRate, OutPoint, Sequence, Transaction, TxIn, TxOut, Txid, Weight,
+    };
+
+    use crate::test_utils::{confirmed, descriptor, plan, spk, tx};
+    use crate::{
+        CanonicalUnspents, ChangePolicyType, FeeSplit, Output, RbfParams, ScriptSource, SubtractFee,
+    };
+
+    #[test]
+    fn batch_merges_new_recipient() {
+        let ours = descriptor().script_pubkey();
+        let funding = tx(
+            &[OutPoint::new(Txid::from_byte_array([1; 32]), 0)],
+            &[(ours.clone(), 100_000), (ours.clone(), 50_000)],
+        );
+        let original = tx(
+            &[OutPoint::new(funding.compute_txid(), 0)],
+            &[(spk(1), 60_000), (ours.clone(), 39_000)],
+        );
+        let status = confirmed();
+        let canon = CanonicalUnspents::new([(funding.clone(), status), (original.clone(), None)]);
+        let plan = plan();
+
+        // a separate tx would spend the unconfirmed change of the original
+        let separate_candidates = InputCandidates::new(
+            [],
+            canon.try_get_unspents([(OutPoint::new(original.compute_txid(), 1), plan.clone())]),
+        );
+
+        let mut rbf_canon = canon.clone();
+        let rbf_set = rbf_canon
+            .extract_replacements([original.compute_txid()])
+            .unwrap();
+        let must_select = rbf_set.must_select_all_inputs(&rbf_canon);
+        let (must_select, can_select): (Vec<_>, Vec<_>) = rbf_canon
+            .try_get_unspents(
+                (0..2).map(|vout| (OutPoint::new(funding.compute_txid(), vout), plan.clone())),
+            )
+            .partition(|input| must_select.contains(&input.prev_outpoint()));
+
+        let params = SelectorParams::new(
+            FeeRate::from_sat_per_vb_u32(10),
+            vec![Output::with_script(spk(2), Amount::from_sat(10_000))],
+            ScriptSource::from_descriptor(descriptor()),
+            ChangePolicyType::NoDust,
+            DrainWeights::TR_KEYSPEND,
+        );
+        let batch = rbf_set
+            .batch(
+                InputCandidates::new(must_select, can_select),
+                separate_candidates,
+                |s: &mut Selector| s.select_until_target_met(),
+                params,
+                |spk| spk == ours.as_script(),
+            )
+            .unwrap();
+
+        let recipients = batch.replacement.outputs[..2]
+            .iter()
+            .map(Output::txout)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            recipients,
+            [
+                TxOut {
+                    value: Amount::from_sat(60_000),
+                    script_pubkey: spk(1),
+                },
+                TxOut {
+                    value: Amount::from_sat(10_000),
+                    script_pubkey: spk(2),
+                },
+            ]
+        );
+        assert_eq!(batch.separate.outputs[0].script_pubkey(), spk(2));
+        assert_eq!(batch.cost.original_fee, Amount::from_sat(1_000));
+        assert!(batch.cost.replacement_fee > batch.cost.original_fee);
+        assert!(batch.cost.is_merge_cheaper(), "{:?}", batch.cost);
+
+        let replacement = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: batch
+                .replacement
+                .inputs
+                .iter()
+                .map(|input| TxIn {
+                    previous_output: input.prev_outpoint(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    ..Default::default()
+                })
+                .collect(),
+            output: batch
+                .replacement
+                .outputs
+                .iter()
+                .map(Output::txout)
+                .collect(),
+        };
+        let report = rbf_set
+            .validate_replacement(
+                &replacement,
+                batch.replacement.predicted_weight(),
+                &rbf_canon,
+            )
+            .unwrap();
+        assert!(report.is_valid(), "{:?}", report);
+    }
+
+    #[test]
+    fn batch_subtracts_fee_from_new_recipients() {
+        let ours = descriptor().script_pubkey();
+        let funding = tx(
+            &[OutPoint::new(Txid::from_byte_array([1; 32]), 0)],
+            &[(ours.clone(), 100_000)],
+        );
+        let original = tx(
+            &[OutPoint::new(funding.compute_txid(), 0)],
+            &[(spk(1), 60_000), (ours.clone(), 39_000)],
+        );
+        let mut canon =
+            CanonicalUnspents::new([(funding.clone(), confirmed()), (original.clone(), None)]);
+        let rbf_set = canon
+            .extract_replacements([original.compute_txid()])
+            .unwrap();
+        let is_change = |spk: &Script| spk == ours.as_script();
+
+        let mut params = SelectorParams::new(
+            FeeRate::from_sat_per_vb_u32(10),
+            vec![Output::with_script(spk(2), Amount::from_sat(10_000))],
+            ScriptSource::from_descriptor(descriptor()),
+            ChangePolicyType::NoDust,
+            DrainWeights::TR_KEYSPEND,
+        );
+        params.subtract_fee = Some(SubtractFee::new([0], FeeSplit::Equal));
+
+        // the replace params of the caller are kept
+        let mut other_params = params.clone();
+        other_params.replace = Some(RbfParams::new([(
+            Weight::from_wu(800),
+            Amount::from_sat(500),
+        )]));
+        let other_params = rbf_set.batch_params(other_params, is_change);
+        assert_eq!(other_params.replace.unwrap().original_txs.len(), 2);
+
+        let params = rbf_set.batch_params(params, is_change);
+        assert_eq!(params.subtract_fee.as_ref().unwrap().outputs, [1]);
+        let candidates = InputCandidates::new(
+            canon.try_get_unspents([(OutPoint::new(funding.compute_txid(), 0), plan())]),
+            [],
+        );
+        let selection = candidates
+            .into_selection(|s: &mut Selector| s.select_until_target_met(), params)
+            .unwrap();
+
+        // the original recipient is paid in full, the new recipient pays the fee
+        assert_eq!(selection.outputs[0].script_pubkey(), spk(1));
+        assert_eq!(selection.outputs[0].value, Amount::from_sat(60_000));
+        assert_eq!(selection.outputs[1].script_pubkey(), spk(2));
+        assert!(selection.outputs[1].value < Amount::from_sat(10_000));
+    }
+}
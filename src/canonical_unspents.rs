@@ -2,11 +2,14 @@ use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::fmt;
 
-use bitcoin::{psbt, OutPoint, Sequence, Transaction, TxOut, Txid};
+use bitcoin::{psbt, Amount, OutPoint, Script, ScriptBuf, Sequence, Transaction, TxOut, Txid};
 use miniscript::{bitcoin, plan::Plan};
 
 use crate::{
-    collections::HashMap, input::CoinbaseMismatch, FromPsbtInputError, Input, RbfSet, TxStatus,
+    collections::{HashMap, HashSet},
+    input::CoinbaseMismatch,
+    output::is_p2a,
+    AncestorPackage, CpfpSet, FromPsbtInputError, Input, RbfSet, TxStatus,
 };
 
 /// Tx with confirmation status.
@@ -18,6 +21,10 @@ pub struct CanonicalUnspents {
     txs: HashMap<Txid, Arc<Transaction>>,
     statuses: HashMap<Txid, TxStatus>,
     spends: HashMap<OutPoint, Txid>,
+    /// Number of known spends of outputs of each script pubkey.
+    spent_spks: HashMap<ScriptBuf, usize>,
+    /// Ancestor packages supplied by the caller, see [`with_ancestors`](Self::with_ancestors).
+    supplied_ancestors: HashMap<Txid, AncestorPackage>,
 }
 
 impl CanonicalUnspents {
@@ -38,15 +45,39 @@ impl CanonicalUnspents {
                 statuses.insert(txid, status);
             }
         }
+        let mut spent_spks = HashMap::<ScriptBuf, usize>::new();
+        for op in spends.keys() {
+            if let Some(txout) = txs
+                .get(&op.txid)
+                .and_then(|tx| tx.output.get(op.vout as usize))
+            {
+                *spent_spks.entry(txout.script_pubkey.clone()).or_default() += 1;
+            }
+        }
         Self {
             txs,
             statuses,
             spends,
+            spent_spks,
+            supplied_ancestors: HashMap::new(),
         }
     }
 
+    /// Supply the [`AncestorPackage`] of the unconfirmed tx `txid`, including the tx itself.
+    ///
+    /// Use this for unconfirmed txs whose ancestors are not part of the canonical txs, e.g. a
+    /// foreign tx whose unconfirmed parents are only known to the mempool of a node. The supplied
+    /// package is used instead of traversing the ancestors of `txid`.
+    pub fn with_ancestors(mut self, txid: Txid, ancestors: AncestorPackage) -> Self {
+        self.supplied_ancestors.insert(txid, ancestors);
+        self
+    }
+
     /// Extract txs in the set of `replace` from the canonical view of unspents.
     ///
+    /// The unconfirmed descendants of the `replace` txs are evicted by the replacement, so they
+    /// are also extracted and recorded as [`RbfSet::descendants`].
+    ///
     /// Returns the [`RbfSet`] if the replacements are valid and succesfully extracted.
     /// Errors if the replacements cannot be extracted (e.g. due to missing data).
     pub fn extract_replacements(
@@ -68,30 +99,54 @@ impl CanonicalUnspents {
             })
             .collect::<Result<HashMap<_, _>, _>>()?;
 
-        // Remove txs in this set which have ancestors of other members of this set.
-        let mut to_remove_from_rbf_txs = Vec::<Txid>::new();
-        let mut to_remove_stack = rbf_txs
+        // Collect the descendants of the rbf txs. Txs in this set which are descendants of other
+        // members of this set are removed from it.
+        let mut visited = HashSet::<Txid>::new();
+        let mut descendant_txids = Vec::<Txid>::new();
+        let mut is_descendant = HashSet::<Txid>::new();
+        let mut stack = rbf_txs
             .iter()
             .map(|(txid, tx)| (*txid, tx.clone()))
             .collect::<Vec<_>>();
-        while let Some((txid, tx)) = to_remove_stack.pop() {
-            if to_remove_from_rbf_txs.contains(&txid) {
+        while let Some((txid, tx)) = stack.pop() {
+            if !visited.insert(txid) {
                 continue;
             }
             for vout in 0..tx.output.len() as u32 {
                 let op = OutPoint::new(txid, vout);
-                if let Some(next_txid) = self.spends.get(&op) {
-                    if let Some(next_tx) = self.txs.get(next_txid) {
-                        to_remove_from_rbf_txs.push(*next_txid);
-                        to_remove_stack.push((*next_txid, next_tx.clone()));
+                if let Some(&next_txid) = self.spends.get(&op) {
+                    if let Some(next_tx) = self.txs.get(&next_txid) {
+                        if is_descendant.insert(next_txid) {
+                            descendant_txids.push(next_txid);
+                        }
+                        stack.push((next_txid, next_tx.clone()));
                     }
                 }
             }
         }
-        for txid in &to_remove_from_rbf_txs {
+        for txid in &descendant_txids {
             rbf_txs.remove(txid);
         }
 
+        // Determine the fees of the descendants which are evicted alongside the rbf txs.
+        let descendants = descendant_txids
+            .iter()
+            .filter_map(|txid| self.txs.get(txid))
+            .map(|tx| -> Result<(Arc<Transaction>, Amount), _> {
+                let mut input_sum = Amount::ZERO;
+                for txin in &tx.input {
+                    let op = txin.previous_output;
+                    input_sum += self
+                        .get_txout(op)
+                        .ok_or(ExtractReplacementsError::PreviousOutputNotFound(op))?
+                        .value;
+                }
+                let output_sum = tx.output.iter().map(|txout| txout.value).sum::<Amount>();
+                let fee = input_sum.checked_sub(output_sum).unwrap_or(Amount::ZERO);
+                Ok((tx.clone(), fee))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
         // Find prev outputs of all txs in the set.
         // Fail when a prev output is not found. We need to use the prevouts to determine fee for RBF!
         let prev_txouts = rbf_txs
@@ -110,8 +165,28 @@ impl CanonicalUnspents {
             .collect::<Result<HashMap<_, _>, _>>()?;
 
         // Remove rbf txs (and their descendants) from canonical unspents.
-        let to_remove_from_canonical_unspents = rbf_txs.keys().chain(&to_remove_from_rbf_txs);
-        for txid in to_remove_from_canonical_unspents {
+        // Each tx is counted once, so that the spends of its inputs are only released once.
+        let to_remove_from_canonical_unspents = rbf_txs
+            .keys()
+            .chain(&descendant_txids)
+            .copied()
+            .collect::<HashSet<Txid>>();
+        let unspent_spks = to_remove_from_canonical_unspents
+            .iter()
+            .filter_map(|txid| self.txs.get(txid))
+            .flat_map(|tx| &tx.input)
+            .filter_map(|txin| self.get_txout(txin.previous_output))
+            .map(|txout| txout.script_pubkey.clone())
+            .collect::<Vec<_>>();
+        for spk in unspent_spks {
+            if let Some(count) = self.spent_spks.get_mut(&spk) {
+                *count -= 1;
+                if *count == 0 {
+                    self.spent_spks.remove(&spk);
+                }
+            }
+        }
+        for txid in &to_remove_from_canonical_unspents {
             if let Some(tx) = self.txs.remove(txid) {
                 self.statuses.remove(txid);
                 for txin in &tx.input {
@@ -120,10 +195,99 @@ impl CanonicalUnspents {
             }
         }
 
-        Ok(
-            RbfSet::new(rbf_txs.into_values(), prev_txouts)
-                .expect("must not have missing prevouts"),
-        )
+        Ok(RbfSet::new(rbf_txs.into_values(), prev_txouts)
+            .expect("must not have missing prevouts")
+            .with_descendants(descendants))
+    }
+
+    /// Compute the [`AncestorPackage`] of the given `txids`.
+    ///
+    /// The package contains every unconfirmed tx of `txids` and all of their unconfirmed
+    /// ancestors. Confirmed txs are skipped. Packages supplied with
+    /// [`with_ancestors`](Self::with_ancestors) are merged in as they are.
+    ///
+    /// Errors if a tx is missing, or if a prev output of an unconfirmed tx is missing (we need the
+    /// prev outputs to determine the fee).
+    pub fn ancestor_package(
+        &self,
+        txids: impl IntoIterator<Item = Txid>,
+    ) -> Result<AncestorPackage, AncestorPackageError> {
+        let mut package = AncestorPackage::default();
+        let mut stack = txids.into_iter().collect::<Vec<_>>();
+        while let Some(txid) = stack.pop() {
+            if package.contains(txid) || self.statuses.contains_key(&txid) {
+                continue;
+            }
+            if let Some(supplied) = self.supplied_ancestors.get(&txid) {
+                package.merge(supplied);
+                continue;
+            }
+            let tx = self
+                .txs
+                .get(&txid)
+                .ok_or(AncestorPackageError::TransactionNotFound(txid))?;
+            if tx.is_coinbase() {
+                continue;
+            }
+            let mut input_sum = Amount::ZERO;
+            for txin in &tx.input {
+                let op = txin.previous_output;
+                let txout = self
+                    .txs
+                    .get(&op.txid)
+                    .and_then(|tx| tx.output.get(op.vout as usize))
+                    .ok_or(AncestorPackageError::PreviousOutputNotFound(op))?;
+                input_sum += txout.value;
+                stack.push(op.txid);
+            }
+            let output_sum = tx.output.iter().map(|txout| txout.value).sum::<Amount>();
+            let fee = input_sum.checked_sub(output_sum).unwrap_or(Amount::ZERO);
+            package.insert(txid, tx.weight(), fee);
+        }
+        Ok(package)
+    }
+
+    /// Prepare to bump the unconfirmed `parents` with a child tx (CPFP).
+    ///
+    /// Returns the [`CpfpSet`] which contains the ancestor package of the `parents`.
+    pub fn cpfp_set(
+        &self,
+        parents: impl IntoIterator<Item = Txid>,
+    ) -> Result<CpfpSet, CpfpSetError> {
+        let parents = parents
+            .into_iter()
+            .map(|txid| -> Result<(Txid, Arc<Transaction>), _> {
+                if self.statuses.contains_key(&txid) {
+                    return Err(CpfpSetError::ParentIsConfirmed(txid));
+                }
+                let tx = self.txs.get(&txid).cloned().ok_or(CpfpSetError::Package(
+                    AncestorPackageError::TransactionNotFound(txid),
+                ))?;
+                Ok((txid, tx))
+            })
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        let package = self
+            .ancestor_package(parents.keys().copied())
+            .map_err(CpfpSetError::Package)?;
+        Ok(CpfpSet::new(parents, package))
+    }
+
+    /// Get the output of `outpoint`, if the tx is known.
+    pub(crate) fn get_txout(&self, outpoint: OutPoint) -> Option<&TxOut> {
+        self.txs
+            .get(&outpoint.txid)
+            .and_then(|tx| tx.output.get(outpoint.vout as usize))
+    }
+
+    /// Whether `txid` is a known tx which is not confirmed.
+    pub(crate) fn is_unconfirmed(&self, txid: Txid) -> bool {
+        self.txs.contains_key(&txid) && !self.statuses.contains_key(&txid)
+    }
+
+    /// Whether an output of `spk` has been spent, i.e. whether new outputs to `spk` reuse an
+    /// address which was spent from.
+    pub fn is_spk_spent(&self, spk: &Script) -> bool {
+        self.spent_spks.contains_key(spk)
     }
 
     /// Whether outpoint is a leaf (unspent).
@@ -141,18 +305,46 @@ impl CanonicalUnspents {
     }
 
     /// Try get leaf (unspent) of given `outpoint`.
+    ///
+    /// If the leaf is unconfirmed, the returned [`Input`] carries its [`AncestorPackage`], which is
+    /// incomplete if the ancestors cannot be determined.
     pub fn try_get_unspent(&self, outpoint: OutPoint, plan: Plan) -> Option<Input> {
         if self.spends.contains_key(&outpoint) {
             return None;
         }
         let prev_tx = Arc::clone(self.txs.get(&outpoint.txid)?);
-        Input::from_prev_tx(
+        let input = Input::from_prev_tx(
             plan,
             prev_tx,
             outpoint.vout.try_into().expect("vout must fit into usize"),
             self.statuses.get(&outpoint.txid).cloned(),
         )
-        .ok()
+        .ok()?;
+        Some(input.with_ancestors(self.unconfirmed_ancestors(outpoint.txid)))
+    }
+
+    /// Ancestor package of `txid`, or an incomplete package if it cannot be determined.
+    fn unconfirmed_ancestors(&self, txid: Txid) -> AncestorPackage {
+        self.ancestor_package([txid])
+            .unwrap_or_else(|_| AncestorPackage::unknown())
+    }
+
+    /// Try get leaf (unspent) pay-to-anchor (P2A) output of given `outpoint`.
+    ///
+    /// Returns `None` if the output is spent, missing or not a P2A output.
+    pub fn try_get_p2a_unspent(&self, outpoint: OutPoint) -> Option<Input> {
+        if self.spends.contains_key(&outpoint) {
+            return None;
+        }
+        let prev_tx = Arc::clone(self.txs.get(&outpoint.txid)?);
+        let vout: usize = outpoint.vout.try_into().expect("vout must fit into usize");
+        if !is_p2a(&prev_tx.output.get(vout)?.script_pubkey) {
+            return None;
+        }
+        let input =
+            Input::from_p2a_output(prev_tx, vout, self.statuses.get(&outpoint.txid).cloned())
+                .ok()?;
+        Some(input.with_ancestors(self.unconfirmed_ancestors(outpoint.txid)))
     }
 
     /// Try get leaves of given `outpoints`.
@@ -198,7 +390,7 @@ impl CanonicalUnspents {
             }
         }
         let status = self.statuses.get(&outpoint.txid).cloned();
-        Input::from_psbt_input(
+        let input = Input::from_psbt_input(
             outpoint,
             sequence,
             psbt_input,
@@ -206,7 +398,8 @@ impl CanonicalUnspents {
             status,
             is_coinbase,
         )
-        .map_err(GetForeignUnspentError::FromPsbtInput)
+        .map_err(GetForeignUnspentError::FromPsbtInput)?;
+        Ok(input.with_ancestors(self.unconfirmed_ancestors(outpoint.txid)))
     }
 
     /// Try get foreign leaves (unspent).
@@ -278,3 +471,45 @@ impl fmt::Display for ExtractReplacementsError {
 
 #[cfg(feature = "std")]
 impl std::error::Error for ExtractReplacementsError {}
+
+/// Error when attempting to compute the [`ancestor_package`](CanonicalUnspents::ancestor_package).
+#[derive(Debug)]
+pub enum AncestorPackageError {
+    /// Transaction not found in canonical unspents
+    TransactionNotFound(Txid),
+    /// Previous output not found for input of an unconfirmed tx
+    PreviousOutputNotFound(OutPoint),
+}
+
+impl fmt::Display for AncestorPackageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TransactionNotFound(txid) => write!(f, "transaction not found: {txid}"),
+            Self::PreviousOutputNotFound(op) => write!(f, "previous output not found: {op}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AncestorPackageError {}
+
+/// Error when attempting to create a [`cpfp_set`](CanonicalUnspents::cpfp_set).
+#[derive(Debug)]
+pub enum CpfpSetError {
+    /// Parent tx is already confirmed
+    ParentIsConfirmed(Txid),
+    /// Cannot compute the ancestor package
+    Package(AncestorPackageError),
+}
+
+impl fmt::Display for CpfpSetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ParentIsConfirmed(txid) => write!(f, "parent tx is already confirmed: {txid}"),
+            Self::Package(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CpfpSetError {}
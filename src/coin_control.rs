@@ -0,0 +1,313 @@
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use bitcoin::OutPoint;
+use miniscript::bitcoin;
+use miniscript::plan::Plan;
+
+use crate::collections::{BTreeMap, HashSet};
+use crate::{CanonicalUnspents, Input, InputCandidates};
+
+/// Coin control status of an outpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CoinStatus {
+    /// Never selected.
+    Frozen,
+    /// Held by a pending psbt, and not selected until the unix timestamp `until`.
+    Reserved {
+        /// Unix timestamp (in seconds) at which the reservation expires.
+        until: u64,
+    },
+    /// Selected before outpoints without a status.
+    Preferred,
+}
+
+impl CoinStatus {
+    /// Whether the outpoint may be selected at unix timestamp `now`.
+    pub fn is_available(&self, now: u64) -> bool {
+        match self {
+            CoinStatus::Frozen => false,
+            CoinStatus::Reserved { until } => *until <= now,
+            CoinStatus::Preferred => true,
+        }
+    }
+}
+
+/// Persistent coin control of outpoints.
+///
+/// Outpoints without a [`CoinStatus`] are selected as usual. Use
+/// [`input_candidates`](Self::input_candidates) to construct [`InputCandidates`] which respect it.
+///
+/// The [`Display`](fmt::Display) and [`FromStr`] implementations use a simple line based format,
+/// one `<outpoint> <status>` per line, where status is `frozen`, `reserved <until>` or
+/// `preferred`. Empty lines and lines starting with `#` are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CoinControl {
+    coins: BTreeMap<OutPoint, CoinStatus>,
+}
+
+impl CoinControl {
+    /// New empty coin control.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the `status` of `outpoint`, returning the previous status.
+    pub fn set(&mut self, outpoint: OutPoint, status: CoinStatus) -> Option<CoinStatus> {
+        self.coins.insert(outpoint, status)
+    }
+
+    /// Freeze `outpoint` so that it is never selected.
+    pub fn freeze(&mut self, outpoint: OutPoint) -> Option<CoinStatus> {
+        self.set(outpoint, CoinStatus::Frozen)
+    }
+
+    /// Reserve `outpoints` until the unix timestamp `until`, e.g. the inputs of a pending psbt.
+    ///
+    /// Frozen outpoints stay frozen.
+    pub fn reserve(&mut self, outpoints: impl IntoIterator<Item = OutPoint>, until: u64) {
+        for outpoint in outpoints {
+            if self.get(outpoint) != Some(CoinStatus::Frozen) {
+                self.set(outpoint, CoinStatus::Reserved { until });
+            }
+        }
+    }
+
+    /// Select `outpoint` before outpoints without a status.
+    pub fn prefer(&mut self, outpoint: OutPoint) -> Option<CoinStatus> {
+        self.set(outpoint, CoinStatus::Preferred)
+    }
+
+    /// Clear the status of `outpoint`, returning it.
+    pub fn release(&mut self, outpoint: OutPoint) -> Option<CoinStatus> {
+        self.coins.remove(&outpoint)
+    }
+
+    /// Clear the reservations which expired at unix timestamp `now`, returning their outpoints.
+    pub fn expire(&mut self, now: u64) -> Vec<OutPoint> {
+        let expired = self
+            .coins
+            .iter()
+            .filter(|(_, status)| matches!(status, CoinStatus::Reserved { until } if *until <= now))
+            .map(|(&op, _)| op)
+            .collect::<Vec<_>>();
+        for op in &expired {
+            self.coins.remove(op);
+        }
+        expired
+    }
+
+    /// Status of `outpoint` (if any).
+    pub fn get(&self, outpoint: OutPoint) -> Option<CoinStatus> {
+        self.coins.get(&outpoint).copied()
+    }
+
+    /// Iterate over all outpoints with a status.
+    pub fn iter(&self) -> impl Iterator<Item = (OutPoint, CoinStatus)> + '_ {
+        self.coins.iter().map(|(&op, &status)| (op, status))
+    }
+
+    /// Whether `outpoint` may be selected at unix timestamp `now`.
+    pub fn is_available(&self, outpoint: OutPoint, now: u64) -> bool {
+        self.get(outpoint)
+            .map_or(true, |status| status.is_available(now))
+    }
+
+    /// Whether `outpoint` is preferred.
+    pub fn is_preferred(&self, outpoint: OutPoint) -> bool {
+        self.get(outpoint) == Some(CoinStatus::Preferred)
+    }
+
+    /// Filter for [`InputCandidates::filter`] which removes unavailable inputs.
+    ///
+    /// As with any filter, `must_select` inputs are kept.
+    pub fn filter(&self, now: u64) -> impl Fn(&Input) -> bool + '_ {
+        move |input| self.is_available(input.prev_outpoint(), now)
+    }
+
+    /// Construct [`InputCandidates`] from the unspent `outpoints` of `canonical_unspents`.
+    ///
+    /// Outpoints in `must_select` (e.g. the inputs of a tx being replaced) are `must_select` even
+    /// if they are unavailable. Other unavailable outpoints are skipped, and preferred outpoints
+    /// are moved first with [`InputCandidates::prioritize`].
+    pub fn input_candidates<O>(
+        &self,
+        canonical_unspents: &CanonicalUnspents,
+        outpoints: O,
+        must_select: &HashSet<OutPoint>,
+        now: u64,
+    ) -> InputCandidates
+    where
+        O: IntoIterator<Item = (OutPoint, Plan)>,
+    {
+        let (must_select, can_select): (Vec<_>, Vec<_>) = canonical_unspents
+            .try_get_unspents(
+                outpoints
+                    .into_iter()
+                    .filter(|(op, _)| must_select.contains(op) || self.is_available(*op, now))
+                    .collect::<Vec<_>>(),
+            )
+            .partition(|input| must_select.contains(&input.prev_outpoint()));
+        InputCandidates::new(must_select, can_select)
+            .prioritize(|input| self.is_preferred(input.prev_outpoint()))
+    }
+}
+
+impl fmt::Display for CoinControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (outpoint, status) in self.iter() {
+            match status {
+                CoinStatus::Frozen => writeln!(f, "{} frozen", outpoint)?,
+                CoinStatus::Reserved { until } => writeln!(f, "{} reserved {}", outpoint, until)?,
+                CoinStatus::Preferred => writeln!(f, "{} preferred", outpoint)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Occurs when parsing [`CoinControl`] fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCoinControlError {
+    /// Line number (starting from 1) of the invalid line.
+    pub line: usize,
+}
+
+impl fmt::Display for ParseCoinControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid coin control entry on line {}", self.line)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseCoinControlError {}
+
+impl FromStr for CoinControl {
+    type Err = ParseCoinControlError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut coin_control = CoinControl::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = ParseCoinControlError { line: i + 1 };
+            let mut words = line.split_whitespace();
+            let outpoint = words
+                .next()
+                .and_then(|word| word.parse::<OutPoint>().ok())
+                .ok_or_else(|| err.clone())?;
+            let status = match (words.next(), words.next()) {
+                (Some("frozen"), None) => CoinStatus::Frozen,
+                (Some("preferred"), None) => CoinStatus::Preferred,
+                (Some("reserved"), Some(until)) => CoinStatus::Reserved {
+                    until: until.parse().map_err(|_| err.clone())?,
+                },
+                _ => return Err(err),
+            };
+            if words.next().is_some() {
+                return Err(err);
+            }
+            coin_control.set(outpoint, status);
+        }
+        Ok(coin_control)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use alloc::string::ToString;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{
+        absolute, transaction, Amount, ScriptBuf, Transaction, TxIn, TxOut, Txid, WPubkeyHash,
+    };
+
+    use crate::test_utils::{confirmed, descriptor, plan};
+
+    fn outpoint(n: u8, vout: u32) -> OutPoint {
+        OutPoint::new(Txid::from_byte_array([n; 32]), vout)
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let mut coin_control = CoinControl::new();
+        coin_control.freeze(outpoint(1, 0));
+        coin_control.reserve([outpoint(2, 1), outpoint(1, 0)], 1_700_000_000);
+        coin_control.prefer(outpoint(3, 2));
+
+        let s = coin_control.to_string();
+        assert_eq!(s.lines().count(), 3);
+        assert_eq!(s.parse::<CoinControl>().unwrap(), coin_control);
+        assert_eq!(coin_control.get(outpoint(1, 0)), Some(CoinStatus::Frozen));
+
+        let s = "# comment\n\n".to_string() + &s + "nonsense\n";
+        assert_eq!(
+            s.parse::<CoinControl>(),
+            Err(ParseCoinControlError { line: 6 })
+        );
+    }
+
+    #[test]
+    fn input_candidates_respect_coin_control() {
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: (0..4)
+                .map(|_| TxOut {
+                    value: Amount::from_sat(10_000),
+                    script_pubkey: descriptor().script_pubkey(),
+                })
+                .chain([TxOut {
+                    value: Amount::from_sat(10_000),
+                    script_pubkey: ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros()),
+                }])
+                .collect(),
+        };
+        let txid = tx.compute_txid();
+        let canon = CanonicalUnspents::new([(tx, confirmed())]);
+        let plan = plan();
+        let outpoints = (0..4).map(|vout| (OutPoint::new(txid, vout), plan.clone()));
+
+        let mut coin_control = CoinControl::new();
+        coin_control.freeze(OutPoint::new(txid, 0));
+        coin_control.reserve([OutPoint::new(txid, 1)], 100);
+        coin_control.prefer(OutPoint::new(txid, 3));
+
+        let no_must_select = HashSet::new();
+        let candidates =
+            coin_control.input_candidates(&canon, outpoints.clone(), &no_must_select, 50);
+        assert!(!candidates.contains(OutPoint::new(txid, 0)));
+        assert!(!candidates.contains(OutPoint::new(txid, 1)));
+        assert!(candidates.must_select().is_none());
+        // the preferred outpoint comes first
+        let order = candidates
+            .inputs()
+            .map(Input::prev_outpoint)
+            .collect::<Vec<_>>();
+        assert_eq!(order, [OutPoint::new(txid, 3), OutPoint::new(txid, 2)]);
+
+        // must_select outpoints are kept even if frozen
+        let must_select = HashSet::from([OutPoint::new(txid, 0)]);
+        let candidates = coin_control.input_candidates(&canon, outpoints.clone(), &must_select, 50);
+        let must_select = candidates.must_select().map(|group| {
+            group
+                .inputs()
+                .iter()
+                .map(Input::prev_outpoint)
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(must_select, Some(vec![OutPoint::new(txid, 0)]));
+        assert_eq!(candidates.can_select().len(), 2);
+
+        // the reservation expired
+        assert_eq!(coin_control.expire(100), vec![OutPoint::new(txid, 1)]);
+        let candidates = coin_control.input_candidates(&canon, outpoints, &no_must_select, 100);
+        assert!(candidates.contains(OutPoint::new(txid, 1)));
+        assert!(!candidates.contains(OutPoint::new(txid, 0)));
+    }
+}
@@ -0,0 +1,362 @@
+use alloc::vec::Vec;
+use core::fmt::Display;
+
+use bdk_coin_select::{TXIN_BASE_WEIGHT, TX_FIXED_FIELD_WEIGHT};
+use bitcoin::{consensus::encode::VarInt, Amount, FeeRate, OutPoint, Psbt, Weight};
+use miniscript::bitcoin;
+
+use crate::collections::HashSet;
+use crate::{Finalizer, Input, Output, ScriptSource, Selection};
+
+/// Inputs and outputs contributed by a single party of a collaborative tx.
+#[derive(Debug, Clone)]
+pub struct Party {
+    /// Inputs funded by the party.
+    pub inputs: Vec<Input>,
+    /// Outputs paid by the party.
+    pub outputs: Vec<Output>,
+    /// Where the party's change goes.
+    ///
+    /// If `None`, or if the change would be dust, the excess goes to fees.
+    pub change_script: Option<ScriptSource>,
+}
+
+/// The share of a single party in a collaborative tx.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartyShare {
+    /// Weight attributed to the party.
+    ///
+    /// This is the weight of its inputs and outputs, plus its share of the tx header.
+    pub weight: Weight,
+    /// Fee paid by the party.
+    pub fee: Amount,
+    /// Index of the party's change output in [`Selection::outputs`].
+    pub change_index: Option<usize>,
+    /// Outpoints of the party's inputs.
+    pub inputs: Vec<OutPoint>,
+}
+
+/// Occurs when a collaborative tx cannot be built.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CollaborativeTxError {
+    /// No party contributed an input.
+    NoInputs,
+    /// The same outpoint was contributed more than once.
+    DuplicateInput(OutPoint),
+    /// A party cannot pay for its outputs and fee.
+    InsufficientFunds {
+        /// Index of the party.
+        party: usize,
+        /// Amount missing.
+        missing: Amount,
+    },
+}
+
+impl Display for CollaborativeTxError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NoInputs => write!(f, "no party contributed an input"),
+            Self::DuplicateInput(op) => write!(f, "input {} was contributed twice", op),
+            Self::InsufficientFunds { party, missing } => {
+                write!(
+                    f,
+                    "party {} is missing {} to pay for its share",
+                    party, missing
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CollaborativeTxError {}
+
+/// Builder for a tx that is funded by several parties.
+///
+/// Each party pays for the weight of its own inputs and outputs at the target feerate, and the
+/// tx header is split equally between the parties.
+#[derive(Debug, Clone)]
+pub struct CollaborativeTxBuilder {
+    feerate: FeeRate,
+    parties: Vec<Party>,
+}
+
+/// A tx funded by several parties, see [`CollaborativeTxBuilder`].
+///
+/// Every party creates the same unsigned tx with [`Selection::create_psbt`] using the same
+/// [`PsbtParams`](crate::PsbtParams), signs and finalizes its own inputs with
+/// [`finalizer`](Self::finalizer), and the partial psbts are merged with [`combine_psbts`].
+#[derive(Debug, Clone)]
+pub struct CollaborativeTx {
+    /// The inputs and outputs of every party.
+    pub selection: Selection,
+    /// The share of each party, in the order the parties were added.
+    pub shares: Vec<PartyShare>,
+}
+
+impl CollaborativeTxBuilder {
+    /// New builder with the target `feerate`.
+    pub fn new(feerate: FeeRate) -> Self {
+        Self {
+            feerate,
+            parties: Vec::new(),
+        }
+    }
+
+    /// Add a party.
+    pub fn add_party(mut self, party: Party) -> Self {
+        self.parties.push(party);
+        self
+    }
+
+    /// Build the [`CollaborativeTx`].
+    ///
+    /// # Errors
+    ///
+    /// - If there are no inputs or an input is contributed twice.
+    /// - If a party cannot pay for its outputs and fee.
+    pub fn build(self) -> Result<CollaborativeTx, CollaborativeTxError> {
+        let mut seen = HashSet::<OutPoint>::new();
+        for input in self.parties.iter().flat_map(|party| &party.inputs) {
+            if !seen.insert(input.prev_outpoint()) {
+                return Err(CollaborativeTxError::DuplicateInput(input.prev_outpoint()));
+            }
+        }
+        let inputs = self
+            .parties
+            .iter()
+            .flat_map(|party| &party.inputs)
+            .collect::<Vec<_>>();
+        if inputs.is_empty() {
+            return Err(CollaborativeTxError::NoInputs);
+        }
+        let is_segwit = inputs.iter().any(|input| input.is_segwit());
+
+        // The header is split equally, with the remainder paid by the first party. We assume
+        // every party with a change script gets a change output when determining its size.
+        let max_output_count = self
+            .parties
+            .iter()
+            .map(|party| party.outputs.len() + party.change_script.iter().count())
+            .sum::<usize>();
+        let header_weight = TX_FIXED_FIELD_WEIGHT
+            + VarInt::from(inputs.len()).size() as u64 * 4
+            + VarInt::from(max_output_count).size() as u64 * 4
+            + if is_segwit { 2 } else { 0 };
+        let party_count = self.parties.len() as u64;
+
+        let mut outputs = self
+            .parties
+            .iter()
+            .flat_map(|party| party.outputs.iter().cloned())
+            .collect::<Vec<_>>();
+        let mut shares = Vec::new();
+        let mut change_indices = Vec::new();
+        for (index, party) in self.parties.iter().enumerate() {
+            let header_share = header_weight / party_count
+                + if index == 0 {
+                    header_weight % party_count
+                } else {
+                    0
+                };
+            let weight = |change: Option<&Output>| {
+                Weight::from_wu(party_weight(party, change, is_segwit).to_wu() + header_share)
+            };
+            let input_value = party
+                .inputs
+                .iter()
+                .map(|input| input.prev_txout().value)
+                .sum::<Amount>();
+            let output_value = party
+                .outputs
+                .iter()
+                .map(|output| output.value)
+                .sum::<Amount>();
+            let change = party.change_script.as_ref().and_then(|script| {
+                let change = Output::from((script.clone(), Amount::ZERO));
+                let fee = self.party_fee(weight(Some(&change)));
+                let value = input_value.checked_sub(output_value + fee)?;
+                if value < script.script().minimal_non_dust() {
+                    return None;
+                }
+                Some(Output::from((script.clone(), value)))
+            });
+            let weight = weight(change.as_ref());
+            let required = self.party_fee(weight) + output_value;
+            if input_value < required {
+                return Err(CollaborativeTxError::InsufficientFunds {
+                    party: index,
+                    missing: required - input_value,
+                });
+            }
+            let fee =
+                input_value - output_value - change.as_ref().map_or(Amount::ZERO, |c| c.value);
+            let change_index = change.map(|change| {
+                outputs.push(change);
+                change_indices.push(outputs.len() - 1);
+                outputs.len() - 1
+            });
+            shares.push(PartyShare {
+                weight,
+                fee,
+                change_index,
+                inputs: party
+                    .inputs
+                    .iter()
+                    .map(|input| input.prev_outpoint())
+                    .collect(),
+            });
+        }
+
+        Ok(CollaborativeTx {
+            selection: Selection {
+                inputs: inputs.into_iter().cloned().collect(),
+                outputs,
+                change_indices,
+                truc: false,
+            },
+            shares,
+        })
+    }
+
+    fn party_fee(&self, weight: Weight) -> Amount {
+        self.feerate
+            .fee_vb(weight.to_vbytes_ceil())
+            .unwrap_or(Amount::MAX_MONEY)
+    }
+}
+
+/// Weight of the inputs and outputs of `party`, excluding the tx header.
+fn party_weight(party: &Party, change: Option<&Output>, is_segwit: bool) -> Weight {
+    let input_weight = party
+        .inputs
+        .iter()
+        .map(|input| {
+            let mut weight = TXIN_BASE_WEIGHT + input.satisfaction_weight();
+            if is_segwit && !input.is_segwit() {
+                // empty witness stack
+                weight += 1;
+            }
+            weight
+        })
+        .sum::<u64>();
+    let output_weight = party
+        .outputs
+        .iter()
+        .chain(change)
+        .map(|output| output.txout().weight().to_wu())
+        .sum::<u64>();
+    Weight::from_wu(input_weight + output_weight)
+}
+
+impl CollaborativeTx {
+    /// Finalizer for the inputs of the party at `index`.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of bounds.
+    pub fn finalizer(&self, index: usize) -> Finalizer {
+        let share = &self.shares[index];
+        Finalizer::new(
+            self.selection
+                .inputs
+                .iter()
+                .filter(|input| share.inputs.contains(&input.prev_outpoint()))
+                .filter_map(|input| Some((input.prev_outpoint(), input.plan().cloned()?))),
+        )
+    }
+}
+
+/// Combine the partial psbts of the parties into one.
+///
+/// # Errors
+///
+/// If the psbts are not of the same unsigned tx.
+pub fn combine_psbts(
+    psbts: impl IntoIterator<Item = Psbt>,
+) -> Result<Option<Psbt>, bitcoin::psbt::Error> {
+    let mut psbts = psbts.into_iter();
+    let mut combined = match psbts.next() {
+        Some(psbt) => psbt,
+        None => return Ok(None),
+    };
+    for psbt in psbts {
+        combined.combine(psbt)?;
+    }
+    Ok(Some(combined))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::test_utils::{spk, Wallet};
+    use crate::{PsbtParams, TxOrdering};
+
+    #[test]
+    fn collaborative_tx_splits_fees_and_combines() {
+        let alice = Wallet::new(bdk_testenv::utils::DESCRIPTORS[2]);
+        let bob = Wallet::new(bdk_testenv::utils::DESCRIPTORS[0]);
+        let feerate = FeeRate::from_sat_per_vb_u32(5);
+        let collab = CollaborativeTxBuilder::new(feerate)
+            .add_party(Party {
+                inputs: vec![alice.input(1, 50_000)],
+                outputs: vec![Output::with_script(spk(1), Amount::from_sat(30_000))],
+                change_script: Some(alice.change()),
+            })
+            .add_party(Party {
+                inputs: vec![bob.input(2, 20_000), bob.input(3, 20_000)],
+                outputs: vec![Output::with_script(spk(2), Amount::from_sat(30_000))],
+                change_script: Some(bob.change()),
+            })
+            .build()
+            .unwrap();
+
+        let [a, b] = [&collab.shares[0], &collab.shares[1]];
+        assert!(b.weight > a.weight, "bob has more inputs");
+        assert!(b.fee > a.fee);
+        for share in &collab.shares {
+            assert!(share.fee >= feerate * share.weight);
+            assert!(share.change_index.is_some());
+        }
+        let total_fee = a.fee + b.fee;
+        assert_eq!(a.weight + b.weight, collab.selection.predicted_weight());
+        assert!(total_fee / collab.selection.predicted_weight() >= feerate);
+
+        // every party builds the same psbt and only signs and finalizes its own inputs
+        let params = PsbtParams {
+            ordering: TxOrdering::Shuffle { seed: 3 },
+            ..Default::default()
+        };
+        let psbts = [&alice, &bob]
+            .into_iter()
+            .enumerate()
+            .map(|(index, wallet)| {
+                let mut psbt = collab.selection.create_psbt(params.clone()).unwrap();
+                wallet.sign(&mut psbt);
+                let res = collab.finalizer(index).finalize(&mut psbt);
+                let finalized = res
+                    .results()
+                    .values()
+                    .filter(|res| matches!(res, Ok(true)))
+                    .count();
+                assert_eq!(finalized, collab.shares[index].inputs.len());
+                psbt
+            })
+            .collect::<Vec<_>>();
+        let tx = combine_psbts(psbts).unwrap().unwrap().extract_tx().unwrap();
+        assert_eq!(tx.input.len(), 3);
+        assert!(tx.input.iter().all(|txin| !txin.witness.is_empty()));
+
+        assert!(matches!(
+            CollaborativeTxBuilder::new(feerate)
+                .add_party(Party {
+                    inputs: vec![alice.input(1, 10_000)],
+                    outputs: vec![Output::with_script(spk(1), Amount::from_sat(10_000))],
+                    change_script: None,
+                })
+                .build(),
+            Err(CollaborativeTxError::InsufficientFunds { party: 0, .. })
+        ));
+    }
+}
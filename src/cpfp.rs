@@ -0,0 +1,561 @@
+use alloc::sync::Arc;
+use core::fmt::Display;
+
+use bitcoin::{Amount, FeeRate, OutPoint, Transaction, Txid, Weight};
+use miniscript::bitcoin;
+
+use crate::collections::{HashMap, HashSet};
+use crate::{CpfpParams, Input};
+
+/// The fee needed to bring a package of `weight` which pays `fee` up to `feerate`.
+pub(crate) fn package_bump_fee(feerate: FeeRate, weight: Weight, fee: Amount) -> Amount {
+    feerate
+        .fee_vb(weight.to_vbytes_ceil())
+        .unwrap_or(Amount::MAX_MONEY)
+        .checked_sub(fee)
+        .unwrap_or(Amount::ZERO)
+}
+
+/// A set of unconfirmed txs, including all of their unconfirmed ancestors.
+///
+/// Confirmed txs are never part of the package. If some ancestors could not be determined (e.g.
+/// a prev output is missing), the package is incomplete, see [`is_complete`](Self::is_complete).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AncestorPackage {
+    txs: HashMap<Txid, (Weight, Amount)>,
+    incomplete: bool,
+}
+
+impl AncestorPackage {
+    /// Package of ancestors which could not be determined.
+    pub(crate) fn unknown() -> Self {
+        Self {
+            txs: HashMap::new(),
+            incomplete: true,
+        }
+    }
+
+    /// Add the unconfirmed tx of `txid` with `weight` which pays `fee`.
+    ///
+    /// Use this to build packages of ancestors which cannot be determined from the canonical txs,
+    /// see [`CanonicalUnspents::with_ancestors`](crate::CanonicalUnspents::with_ancestors).
+    pub fn insert(&mut self, txid: Txid, weight: Weight, fee: Amount) {
+        self.txs.insert(txid, (weight, fee));
+    }
+
+    /// Whether all unconfirmed ancestors are known.
+    ///
+    /// The weight and fee of an incomplete package are not reliable, so inputs with incomplete
+    /// ancestors are rejected by [`filter_long_chains`](crate::filter_long_chains) and
+    /// [`InputCandidates::bump_ancestors`](crate::InputCandidates::bump_ancestors). Supply the
+    /// missing ancestors with
+    /// [`CanonicalUnspents::with_ancestors`](crate::CanonicalUnspents::with_ancestors) to spend
+    /// such inputs.
+    pub fn is_complete(&self) -> bool {
+        !self.incomplete
+    }
+
+    /// Txids of the txs in the package.
+    pub fn txids(&self) -> impl ExactSizeIterator<Item = Txid> + '_ {
+        self.txs.keys().copied()
+    }
+
+    /// Whether the package contains the tx of `txid`.
+    pub fn contains(&self, txid: Txid) -> bool {
+        self.txs.contains_key(&txid)
+    }
+
+    /// Whether the package is empty, i.e. there are no unconfirmed ancestors.
+    pub fn is_empty(&self) -> bool {
+        self.txs.is_empty()
+    }
+
+    /// Total weight of the package.
+    pub fn weight(&self) -> Weight {
+        self.txs.values().map(|(weight, _)| *weight).sum()
+    }
+
+    /// Total fee of the package.
+    pub fn fee(&self) -> Amount {
+        self.txs.values().map(|(_, fee)| *fee).sum()
+    }
+
+    /// Feerate of the package as a whole.
+    pub fn feerate(&self) -> FeeRate {
+        let weight = self.weight();
+        if weight == Weight::ZERO {
+            return FeeRate::ZERO;
+        }
+        self.fee() / weight
+    }
+
+    /// The additional fee a child must pay to bring the package up to `feerate`.
+    pub fn bump_fee(&self, feerate: FeeRate) -> Amount {
+        package_bump_fee(feerate, self.weight(), self.fee())
+    }
+
+    /// Merge `other` into this package. Txs contained in both are only counted once.
+    ///
+    /// The merged package is incomplete if either package is.
+    pub fn merge(&mut self, other: &AncestorPackage) {
+        self.txs
+            .extend(other.txs.iter().map(|(txid, stats)| (*txid, *stats)));
+        self.incomplete |= other.incomplete;
+    }
+}
+
+/// Unconfirmed parent txs to bump with a child tx (CPFP).
+#[derive(Debug, Clone)]
+pub struct CpfpSet {
+    parents: HashMap<Txid, Arc<Transaction>>,
+    package: AncestorPackage,
+}
+
+/// Occurs when the given parent tx has no output that is an input candidate.
+#[derive(Debug)]
+pub struct ParentTxHasNoOutputsAvailable {
+    /// Parent txid.
+    pub txid: Txid,
+}
+
+impl Display for ParentTxHasNoOutputsAvailable {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "parent tx {} has no output that is available for spending",
+            self.txid
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParentTxHasNoOutputsAvailable {}
+
+impl CpfpSet {
+    pub(crate) fn new(parents: HashMap<Txid, Arc<Transaction>>, package: AncestorPackage) -> Self {
+        Self { parents, package }
+    }
+
+    /// Txids of the parent txs to bump.
+    pub fn txids(&self) -> impl ExactSizeIterator<Item = Txid> + '_ {
+        self.parents.keys().copied()
+    }
+
+    /// The parent txs and all of their unconfirmed ancestors.
+    pub fn package(&self) -> &AncestorPackage {
+        &self.package
+    }
+
+    /// Tries to find the largest output of each parent tx among `candidates`.
+    ///
+    /// The returned outpoints can be used to create the `must_select` inputs to pass into
+    /// `InputCandidates`. This guarantees that the child spends from every parent, and therefore
+    /// bumps all of them.
+    pub fn must_select_largest_output_of_each_parent<'a>(
+        &self,
+        candidates: impl IntoIterator<Item = &'a Input>,
+    ) -> Result<HashSet<OutPoint>, ParentTxHasNoOutputsAvailable> {
+        let mut largest = HashMap::<Txid, (Amount, OutPoint)>::new();
+        for input in candidates {
+            let outpoint = input.prev_outpoint();
+            if !self.parents.contains_key(&outpoint.txid) {
+                continue;
+            }
+            let value = input.prev_txout().value;
+            let entry = largest.entry(outpoint.txid).or_insert((value, outpoint));
+            if value > entry.0 {
+                *entry = (value, outpoint);
+            }
+        }
+        self.parents
+            .keys()
+            .map(|&txid| {
+                largest
+                    .get(&txid)
+                    .map(|(_, outpoint)| *outpoint)
+                    .ok_or(ParentTxHasNoOutputsAvailable { txid })
+            })
+            .collect()
+    }
+
+    /// Coin selector CPFP parameters.
+    pub fn selector_cpfp_params(&self) -> CpfpParams {
+        CpfpParams {
+            package_weight: self.package.weight(),
+            package_fee: self.package.fee(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use alloc::vec::Vec;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{ScriptBuf, WPubkeyHash};
+
+    use crate::test_utils::{confirmed, descriptor, plan, tx};
+    use crate::{
+        filter_long_chains, CanonicalUnspents, ChangePolicyType, InputCandidates, Output,
+        ScriptSource, Selector, SelectorParams, DEFAULT_ANCESTOR_LIMIT,
+    };
+
+    /// A confirmed `grandparent`, an unconfirmed `parent` paying 1000 sats fee, and a confirmed
+    /// unrelated utxo.
+    fn canonical_unspents() -> (CanonicalUnspents, Transaction, Transaction, Transaction) {
+        let ours = descriptor().script_pubkey();
+        let other = ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros());
+        let grandparent = tx(
+            &[OutPoint::new(Txid::from_byte_array([1; 32]), 0)],
+            &[(ours.clone(), 100_000)],
+        );
+        let parent = tx(
+            &[OutPoint::new(grandparent.compute_txid(), 0)],
+            &[(ours.clone(), 50_000), (other, 49_000)],
+        );
+        let unrelated = tx(
+            &[OutPoint::new(Txid::from_byte_array([2; 32]), 0)],
+            &[(ours, 200_000)],
+        );
+        let status = confirmed();
+        let canon = CanonicalUnspents::new([
+            (grandparent.clone(), status),
+            (parent.clone(), None),
+            (unrelated.clone(), status),
+        ]);
+        (canon, grandparent, parent, unrelated)
+    }
+
+    #[test]
+    fn ancestor_package() {
+        let (canon, grandparent, parent, _) = canonical_unspents();
+
+        let package = canon.ancestor_package([parent.compute_txid()]).unwrap();
+        assert_eq!(package.txids().collect::<Vec<_>>(), [parent.compute_txid()]);
+        assert_eq!(package.weight(), parent.weight());
+        assert_eq!(package.fee(), Amount::from_sat(1_000));
+
+        let package = canon
+            .ancestor_package([grandparent.compute_txid()])
+            .unwrap();
+        assert!(package.is_empty());
+        assert_eq!(
+            package.bump_fee(FeeRate::from_sat_per_vb_u32(10)),
+            Amount::ZERO
+        );
+
+        // A child of the parent inherits the parent as an ancestor.
+        let child = tx(
+            &[OutPoint::new(parent.compute_txid(), 0)],
+            &[(descriptor().script_pubkey(), 49_500)],
+        );
+        let status = confirmed();
+        let canon = CanonicalUnspents::new([
+            (grandparent, status),
+            (parent.clone(), None),
+            (child.clone(), None),
+        ]);
+        let package = canon.ancestor_package([child.compute_txid()]).unwrap();
+        assert_eq!(package.txids().count(), 2);
+        assert_eq!(package.weight(), parent.weight() + child.weight());
+        assert_eq!(package.fee(), Amount::from_sat(1_500));
+    }
+
+    #[test]
+    fn cpfp_selection_pays_for_package() {
+        let (canon, grandparent, parent, unrelated) = canonical_unspents();
+        assert!(matches!(
+            canon.cpfp_set([grandparent.compute_txid()]),
+            Err(crate::CpfpSetError::ParentIsConfirmed(_))
+        ));
+        let cpfp_set = canon.cpfp_set([parent.compute_txid()]).unwrap();
+
+        let plan = plan();
+        let inputs = canon
+            .try_get_unspents([
+                (OutPoint::new(parent.compute_txid(), 0), plan.clone()),
+                (OutPoint::new(unrelated.compute_txid(), 0), plan),
+            ])
+            .collect::<Vec<_>>();
+        let must_select = cpfp_set
+            .must_select_largest_output_of_each_parent(&inputs)
+            .unwrap();
+        assert_eq!(
+            must_select.iter().copied().collect::<Vec<_>>(),
+            [OutPoint::new(parent.compute_txid(), 0)]
+        );
+        let (must_select, can_select): (Vec<_>, Vec<_>) = inputs
+            .into_iter()
+            .partition(|input| must_select.contains(&input.prev_outpoint()));
+
+        let feerate = FeeRate::from_sat_per_vb_u32(10);
+        let mut params = SelectorParams::new(
+            feerate,
+            vec![],
+            ScriptSource::from_descriptor(descriptor()),
+            ChangePolicyType::NoDust,
+            bdk_coin_select::DrainWeights::TR_KEYSPEND,
+        );
+        params.cpfp = Some(cpfp_set.selector_cpfp_params());
+        let selection = InputCandidates::new(must_select, can_select)
+            .into_selection(|s: &mut Selector| s.select_until_target_met(), params)
+            .unwrap();
+        assert_eq!(
+            selection.inputs[0].prev_outpoint(),
+            OutPoint::new(parent.compute_txid(), 0)
+        );
+
+        let report = selection.analyze(Default::default()).unwrap();
+        let package_weight = selection.predicted_weight() + parent.weight();
+        let package_fee = report.fee + Amount::from_sat(1_000);
+        assert!(package_fee >= feerate.fee_vb(package_weight.to_vbytes_ceil()).unwrap());
+        assert!(package_fee / package_weight < FeeRate::from_sat_per_vb_u32(11));
+    }
+
+    #[test]
+    fn ancestor_aware_selection() {
+        let (canon, _, parent, _) = canonical_unspents();
+        let plan = plan();
+        let input = canon
+            .try_get_unspent(OutPoint::new(parent.compute_txid(), 0), plan)
+            .unwrap();
+        assert_eq!(
+            input.ancestors().txids().collect::<Vec<_>>(),
+            [parent.compute_txid()]
+        );
+
+        let feerate = FeeRate::from_sat_per_vb_u32(10);
+        let select = |candidates: InputCandidates| {
+            let params = SelectorParams::new(
+                feerate,
+                vec![Output::with_script(
+                    ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros()),
+                    Amount::from_sat(10_000),
+                )],
+                ScriptSource::from_descriptor(descriptor()),
+                ChangePolicyType::NoDust,
+                bdk_coin_select::DrainWeights::TR_KEYSPEND,
+            );
+            candidates
+                .into_selection(|s: &mut Selector| s.select_until_target_met(), params)
+                .unwrap()
+                .analyze(Default::default())
+                .unwrap()
+        };
+        let candidates = InputCandidates::new([], [input]);
+        let report = select(candidates.clone());
+        let bumped_report = select(candidates.clone().bump_ancestors(feerate));
+        assert_eq!(
+            bumped_report.fee - report.fee,
+            input_bump_fee(&candidates, feerate)
+        );
+        assert!(bumped_report.fee > report.fee);
+
+        assert_eq!(
+            candidates
+                .clone()
+                .filter(filter_long_chains(2))
+                .groups()
+                .count(),
+            1
+        );
+        assert_eq!(candidates.filter(filter_long_chains(1)).groups().count(), 0);
+    }
+
+    #[test]
+    fn shared_ancestors_are_bumped_once() {
+        let (_, grandparent, _, _) = canonical_unspents();
+        let ours = descriptor().script_pubkey();
+        let parent = tx(
+            &[OutPoint::new(grandparent.compute_txid(), 0)],
+            &[(ours.clone(), 50_000), (ours, 49_000)],
+        );
+        let canon = CanonicalUnspents::new([(grandparent, confirmed()), (parent.clone(), None)]);
+        let plan = plan();
+        let inputs = canon
+            .try_get_unspents(
+                (0..2).map(|vout| (OutPoint::new(parent.compute_txid(), vout), plan.clone())),
+            )
+            .collect::<Vec<_>>();
+
+        let feerate = FeeRate::from_sat_per_vb_u32(10);
+        let select = |candidates: InputCandidates| {
+            let params = SelectorParams::new(
+                feerate,
+                vec![Output::with_script(
+                    ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros()),
+                    Amount::from_sat(60_000),
+                )],
+                ScriptSource::from_descriptor(descriptor()),
+                ChangePolicyType::NoDust,
+                bdk_coin_select::DrainWeights::TR_KEYSPEND,
+            );
+            candidates
+                .into_selection(|s: &mut Selector| s.select_until_target_met(), params)
+                .unwrap()
+                .analyze(Default::default())
+                .unwrap()
+        };
+        let candidates = InputCandidates::new([], inputs);
+        let report = select(candidates.clone());
+        let bumped_report = select(candidates.clone().bump_ancestors(feerate));
+        assert_eq!(report.input_value, Amount::from_sat(99_000));
+        assert_eq!(bumped_report.input_value, Amount::from_sat(99_000));
+
+        // each input pays for the parent, but it is only bumped once
+        let parent_bump_fee = candidates.can_select()[0].ancestor_bump_fee(feerate);
+        assert_eq!(input_bump_fee(&candidates, feerate), parent_bump_fee * 2);
+        assert_eq!(bumped_report.fee - report.fee, parent_bump_fee);
+    }
+
+    #[test]
+    fn unknown_ancestors_are_rejected() {
+        let (_, grandparent, parent, _) = canonical_unspents();
+        // the prev output of the parent is missing
+        let canon = CanonicalUnspents::new([(parent.clone(), None)]);
+        assert!(canon.ancestor_package([parent.compute_txid()]).is_err());
+        let input = canon
+            .try_get_unspent(OutPoint::new(parent.compute_txid(), 0), plan())
+            .unwrap();
+        assert!(!input.ancestors().is_complete());
+
+        let candidates = InputCandidates::new([], [input]);
+        assert_eq!(
+            candidates
+                .clone()
+                .filter(filter_long_chains(DEFAULT_ANCESTOR_LIMIT))
+                .groups()
+                .count(),
+            0
+        );
+        let bumped = candidates.bump_ancestors(FeeRate::from_sat_per_vb_u32(10));
+        assert_eq!(bumped.coin_select_candidates()[0].value, 0);
+
+        // the ancestors are known once the grandparent is
+        let canon = CanonicalUnspents::new([(grandparent, confirmed()), (parent.clone(), None)]);
+        let input = canon
+            .try_get_unspent(OutPoint::new(parent.compute_txid(), 0), plan())
+            .unwrap();
+        assert!(input.ancestors().is_complete());
+    }
+
+    #[test]
+    fn supplied_ancestors_are_used() {
+        let (_, grandparent, parent, _) = canonical_unspents();
+        let feerate = FeeRate::from_sat_per_vb_u32(10);
+        // the grandparent is unconfirmed and only known to the caller, so the fee of the parent
+        // cannot be determined either
+        let mut supplied = AncestorPackage::default();
+        supplied.insert(
+            grandparent.compute_txid(),
+            grandparent.weight(),
+            Amount::from_sat(100),
+        );
+        supplied.insert(
+            parent.compute_txid(),
+            parent.weight(),
+            Amount::from_sat(1_000),
+        );
+        let canon = CanonicalUnspents::new([(parent.clone(), None)])
+            .with_ancestors(parent.compute_txid(), supplied.clone());
+        assert_eq!(
+            canon.ancestor_package([parent.compute_txid()]).unwrap(),
+            supplied
+        );
+
+        let input = canon
+            .try_get_unspent(OutPoint::new(parent.compute_txid(), 0), plan())
+            .unwrap();
+        assert_eq!(input.ancestors(), &supplied);
+        let candidates = InputCandidates::new([], [input]);
+        assert_eq!(
+            candidates
+                .clone()
+                .filter(filter_long_chains(DEFAULT_ANCESTOR_LIMIT))
+                .groups()
+                .count(),
+            1
+        );
+        assert_eq!(
+            candidates
+                .clone()
+                .filter(filter_long_chains(2))
+                .groups()
+                .count(),
+            0
+        );
+        let value = candidates.groups().next().unwrap().value();
+        let bumped = candidates.bump_ancestors(feerate);
+        assert!(supplied.bump_fee(feerate) > Amount::ZERO);
+        assert_eq!(
+            bumped.coin_select_candidates()[0].value,
+            (value - supplied.bump_fee(feerate)).to_sat()
+        );
+    }
+
+    fn input_bump_fee(candidates: &InputCandidates, feerate: FeeRate) -> Amount {
+        candidates
+            .groups()
+            .map(|group| group.ancestor_bump_fee(feerate))
+            .sum()
+    }
+
+    #[test]
+    fn anchor_cpfp() {
+        let (_, grandparent, _, unrelated) = canonical_unspents();
+        let mut parent = tx(
+            &[OutPoint::new(grandparent.compute_txid(), 0)],
+            &[
+                (ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros()), 99_800),
+                (ScriptBuf::new_p2a(), 0),
+            ],
+        );
+        parent.version = crate::TRUC_VERSION;
+        let status = confirmed();
+        let canon = CanonicalUnspents::new([
+            (grandparent, status),
+            (parent.clone(), None),
+            (unrelated.clone(), status),
+        ]);
+        assert!(canon
+            .try_get_p2a_unspent(OutPoint::new(parent.compute_txid(), 0))
+            .is_none());
+        let anchor = canon
+            .try_get_p2a_unspent(OutPoint::new(parent.compute_txid(), 1))
+            .unwrap();
+        assert!(anchor.is_segwit());
+        assert_eq!(anchor.satisfaction_weight(), 1);
+
+        let plan = plan();
+        let funding = canon
+            .try_get_unspent(OutPoint::new(unrelated.compute_txid(), 0), plan)
+            .unwrap();
+
+        let cpfp_set = canon.cpfp_set([parent.compute_txid()]).unwrap();
+        let mut params = SelectorParams::new(
+            FeeRate::from_sat_per_vb_u32(10),
+            vec![],
+            ScriptSource::from_descriptor(descriptor()),
+            ChangePolicyType::NoDust,
+            bdk_coin_select::DrainWeights::TR_KEYSPEND,
+        );
+        params.cpfp = Some(cpfp_set.selector_cpfp_params());
+        params.truc = true;
+        let selection = InputCandidates::new([anchor], [funding])
+            .into_selection(|s: &mut Selector| s.select_until_target_met(), params)
+            .unwrap();
+        assert_eq!(selection.inputs.len(), 2);
+
+        let psbt = selection
+            .create_psbt(crate::PsbtParams {
+                version: crate::TRUC_VERSION,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(
+            psbt.inputs[0].final_script_witness,
+            Some(bitcoin::Witness::new())
+        );
+    }
+}
@@ -5,11 +5,16 @@ use core::fmt;
 
 use bitcoin::constants::COINBASE_MATURITY;
 use bitcoin::transaction::OutputsIndexError;
-use bitcoin::{absolute, psbt, relative, Amount, Sequence, Txid};
+use bitcoin::{absolute, psbt, relative, Amount, FeeRate, Sequence, Txid, Witness};
 use miniscript::bitcoin;
 use miniscript::bitcoin::{OutPoint, Transaction, TxOut};
 use miniscript::plan::Plan;
 
+use crate::AncestorPackage;
+
+/// Satisfaction weight of a pay-to-anchor (P2A) spend: an empty witness stack.
+const P2A_SATISFACTION_WEIGHT: usize = 1;
+
 /// Confirmation status of a tx data.
 #[derive(Debug, Clone, Copy)]
 pub struct TxStatus {
@@ -193,6 +198,7 @@ pub struct Input {
     plan: PlanOrPsbtInput,
     status: Option<TxStatus>,
     is_coinbase: bool,
+    ancestors: AncestorPackage,
 }
 
 impl Input {
@@ -220,6 +226,47 @@ impl Input {
             plan: PlanOrPsbtInput::Plan(Box::new(plan)),
             status,
             is_coinbase,
+            ancestors: AncestorPackage::default(),
+        })
+    }
+
+    /// Create [`Input`] that spends a pay-to-anchor (P2A) output of `prev_tx`.
+    ///
+    /// P2A outputs are anyone-can-spend and are satisfied with an empty witness, so no plan is
+    /// needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OutputsIndexError` if the previous txout is not found in `prev_tx`
+    /// at `output_index`.
+    pub fn from_p2a_output<T>(
+        prev_tx: T,
+        output_index: usize,
+        status: Option<TxStatus>,
+    ) -> Result<Self, OutputsIndexError>
+    where
+        T: Into<Arc<Transaction>>,
+    {
+        let tx: Arc<Transaction> = prev_tx.into();
+        let prev_txout = tx.tx_out(output_index).cloned()?;
+        let psbt_input = psbt::Input {
+            witness_utxo: Some(prev_txout.clone()),
+            final_script_witness: Some(Witness::new()),
+            ..Default::default()
+        };
+        Ok(Self {
+            prev_outpoint: OutPoint::new(tx.compute_txid(), output_index as _),
+            prev_txout,
+            is_coinbase: tx.is_coinbase(),
+            prev_tx: Some(tx),
+            plan: PlanOrPsbtInput::PsbtInput {
+                psbt_input: Box::new(psbt_input),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                absolute_timelock: absolute::LockTime::ZERO,
+                satisfaction_weight: P2A_SATISFACTION_WEIGHT,
+            },
+            status,
+            ancestors: AncestorPackage::default(),
         })
     }
 
@@ -238,6 +285,7 @@ impl Input {
             plan: PlanOrPsbtInput::Plan(Box::new(plan)),
             status,
             is_coinbase,
+            ancestors: AncestorPackage::default(),
         }
     }
 
@@ -300,6 +348,7 @@ impl Input {
             plan,
             status,
             is_coinbase,
+            ancestors: AncestorPackage::default(),
         })
     }
 
@@ -341,6 +390,21 @@ impl Input {
         self.is_coinbase
     }
 
+    /// Unconfirmed ancestors of this input (including the tx of the prev output).
+    ///
+    /// This is empty unless set with [`with_ancestors`](Self::with_ancestors).
+    pub fn ancestors(&self) -> &AncestorPackage {
+        &self.ancestors
+    }
+
+    /// Set the unconfirmed ancestors of this input.
+    ///
+    /// [`CanonicalUnspents`](crate::CanonicalUnspents) sets this for unconfirmed inputs.
+    pub fn with_ancestors(mut self, ancestors: AncestorPackage) -> Self {
+        self.ancestors = ancestors;
+        self
+    }
+
     /// Whether prev output is an immature coinbase output and cannot be spent in the next block.
     pub fn is_immature(&self, tip_height: absolute::Height) -> bool {
         if !self.is_coinbase {
@@ -545,6 +609,21 @@ impl InputGroup {
             .sum()
     }
 
+    /// Unconfirmed ancestors of all contained inputs.
+    pub fn ancestors(&self) -> AncestorPackage {
+        let mut ancestors = AncestorPackage::default();
+        for input in self.inputs() {
+            ancestors.merge(input.ancestors());
+        }
+        ancestors
+    }
+
+    /// The additional fee needed to bring the unconfirmed ancestors of all contained inputs up to
+    /// `feerate`.
+    pub fn ancestor_bump_fee(&self, feerate: FeeRate) -> Amount {
+        self.ancestors().bump_fee(feerate)
+    }
+
     /// Input count.
     pub fn input_count(&self) -> usize {
         self.inputs().len()
@@ -3,13 +3,13 @@ use core::fmt;
 use core::ops::Deref;
 
 use bdk_coin_select::{metrics::LowestFee, Candidate, NoBnbSolution};
-use bitcoin::{absolute, FeeRate, OutPoint};
+use bitcoin::{absolute, Amount, FeeRate, OutPoint, Weight};
 use miniscript::bitcoin;
 
 use crate::collections::{BTreeMap, HashSet};
 use crate::{
-    cs_feerate, CannotMeetTarget, Input, InputGroup, Selection, Selector, SelectorError,
-    SelectorParams,
+    cs_feerate, CannotMeetTarget, CanonicalUnspents, Input, InputGroup, Selection, Selector,
+    SelectorError, SelectorParams, TrucViolation,
 };
 
 /// Input candidates.
@@ -20,11 +20,21 @@ pub struct InputCandidates {
     must_select: Option<InputGroup>,
     can_select: Vec<InputGroup>,
     cs_candidates: Vec<Candidate>,
+    ancestor_feerate: Option<FeeRate>,
 }
 
-fn cs_candidate_from_group(group: &InputGroup) -> Candidate {
+fn cs_candidate_from_group(group: &InputGroup, ancestor_feerate: Option<FeeRate>) -> Candidate {
+    let value = match ancestor_feerate {
+        // The bump fee of unknown ancestors cannot be determined, so the group is worthless.
+        Some(_) if !group.ancestors().is_complete() => Amount::ZERO,
+        Some(feerate) => group
+            .value()
+            .checked_sub(group.ancestor_bump_fee(feerate))
+            .unwrap_or(Amount::ZERO),
+        None => group.value(),
+    };
     Candidate {
-        value: group.value().to_sat(),
+        value: value.to_sat(),
         weight: group.weight(),
         input_count: group.input_count(),
         is_segwit: group.is_segwit(),
@@ -51,23 +61,25 @@ impl InputCandidates {
             .filter(|input| contains.insert(input.prev_outpoint()))
             .map(InputGroup::from_input)
             .collect::<Vec<_>>();
-        let cs_candidates = Self::build_cs_candidates(&must_select, &can_select);
+        let cs_candidates = Self::build_cs_candidates(&must_select, &can_select, None);
         InputCandidates {
             contains,
             must_select,
             can_select,
             cs_candidates,
+            ancestor_feerate: None,
         }
     }
 
     fn build_cs_candidates(
         must_select: &Option<InputGroup>,
         can_select: &[InputGroup],
+        ancestor_feerate: Option<FeeRate>,
     ) -> Vec<Candidate> {
         must_select
             .iter()
             .chain(can_select)
-            .map(cs_candidate_from_group)
+            .map(|group| cs_candidate_from_group(group, ancestor_feerate))
             .collect()
     }
 
@@ -113,12 +125,16 @@ impl InputCandidates {
 
     /// Regroup inputs with given `policy`.
     ///
-    /// Anything grouped with `must_select` inputs also becomes `must_select`.
+    /// Anything grouped with `must_select` inputs also becomes `must_select`. The `policy` is
+    /// called once per input, first for the `must_select` inputs.
     pub fn regroup<P, G>(self, mut policy: P) -> Self
     where
         P: FnMut(&Input) -> G,
         G: Ord + Clone,
     {
+        let mut must_select = self.must_select.map_or(vec![], |g| g.into_inputs());
+        let must_select_order = must_select.iter().map(&mut policy).collect::<Vec<_>>();
+
         let mut order = Vec::<G>::with_capacity(self.contains.len());
         let mut groups = BTreeMap::<G, Vec<Input>>::new();
         for input in self
@@ -138,8 +154,6 @@ impl InputCandidates {
             entry.push(input);
         }
 
-        let mut must_select = self.must_select.map_or(vec![], |g| g.into_inputs());
-        let must_select_order = must_select.iter().map(&mut policy).collect::<Vec<_>>();
         for g_id in must_select_order {
             if let Some(inputs) = groups.remove(&g_id) {
                 must_select.extend(inputs);
@@ -156,7 +170,8 @@ impl InputCandidates {
             }
         }
 
-        let cs_candidates = Self::build_cs_candidates(&must_select, &can_select);
+        let cs_candidates =
+            Self::build_cs_candidates(&must_select, &can_select, self.ancestor_feerate);
         let no_dup = self.contains;
 
         Self {
@@ -164,6 +179,7 @@ impl InputCandidates {
             must_select,
             can_select,
             cs_candidates,
+            ancestor_feerate: self.ancestor_feerate,
         }
     }
 
@@ -188,10 +204,158 @@ impl InputCandidates {
         for op in to_rm {
             self.contains.remove(&op);
         }
-        self.cs_candidates = Self::build_cs_candidates(&self.must_select, &self.can_select);
+        self.cs_candidates =
+            Self::build_cs_candidates(&self.must_select, &self.can_select, self.ancestor_feerate);
         self
     }
 
+    /// Filters out groups which do not satisfy `policy`.
+    ///
+    /// Does not filter the `must_select` group.
+    pub fn filter_groups<P>(self, policy: P) -> Self
+    where
+        P: FnMut(&InputGroup) -> bool,
+    {
+        self.partition_groups(policy).0
+    }
+
+    /// Filters out groups which do not satisfy `policy`, and returns them alongside.
+    ///
+    /// This is useful to keep track of the filtered out groups, e.g. to consolidate uneconomical
+    /// inputs later at a lower feerate. Does not filter the `must_select` group.
+    pub fn partition_groups<P>(mut self, policy: P) -> (Self, Vec<InputGroup>)
+    where
+        P: FnMut(&InputGroup) -> bool,
+    {
+        let (can_select, dropped): (Vec<_>, Vec<_>) = self.can_select.into_iter().partition(policy);
+        for input in dropped.iter().flat_map(InputGroup::inputs) {
+            self.contains.remove(&input.prev_outpoint());
+        }
+        self.can_select = can_select;
+        self.cs_candidates =
+            Self::build_cs_candidates(&self.must_select, &self.can_select, self.ancestor_feerate);
+        (self, dropped)
+    }
+
+    /// Move groups with any input matching `policy` before all other `can_select` groups.
+    ///
+    /// Algorithms which select in order (e.g. [`Selector::select_until_target_met`]) then select
+    /// them first. The order is otherwise kept.
+    pub fn prioritize<P>(mut self, mut policy: P) -> Self
+    where
+        P: FnMut(&Input) -> bool,
+    {
+        let (mut can_select, low): (Vec<_>, Vec<_>) = self
+            .can_select
+            .into_iter()
+            .partition(|group| group.any(&mut policy));
+        can_select.extend(low);
+        self.can_select = can_select;
+        self.cs_candidates =
+            Self::build_cs_candidates(&self.must_select, &self.can_select, self.ancestor_feerate);
+        self
+    }
+
+    /// Move groups with any input matching `policy` after all other `can_select` groups.
+    ///
+    /// Algorithms which select in order (e.g. [`Selector::select_until_target_met`]) then only
+    /// select them when the other groups are insufficient. The order is otherwise kept.
+    pub fn deprioritize<P>(mut self, mut policy: P) -> Self
+    where
+        P: FnMut(&Input) -> bool,
+    {
+        let (low, mut can_select): (Vec<_>, Vec<_>) = self
+            .can_select
+            .into_iter()
+            .partition(|group| group.any(&mut policy));
+        can_select.extend(low);
+        self.can_select = can_select;
+        self.cs_candidates =
+            Self::build_cs_candidates(&self.must_select, &self.can_select, self.ancestor_feerate);
+        self
+    }
+
+    /// Split the candidates into separate [`InputCandidates`] by the key `policy` returns for each
+    /// input.
+    ///
+    /// Selecting from a single partition never spends inputs of different keys together, e.g. to
+    /// keep keychains (accounts) from being linked on-chain while only spending what is needed.
+    /// Groups with inputs of different keys are split up. `must_select` inputs stay
+    /// `must_select` within their partition.
+    pub fn split_by<P, K>(self, mut policy: P) -> BTreeMap<K, InputCandidates>
+    where
+        P: FnMut(&Input) -> K,
+        K: Ord,
+    {
+        let ancestor_feerate = self.ancestor_feerate;
+        let mut partitions = BTreeMap::<K, (Vec<Input>, Vec<Vec<Input>>)>::new();
+        for input in self
+            .must_select
+            .into_iter()
+            .flat_map(InputGroup::into_inputs)
+        {
+            partitions.entry(policy(&input)).or_default().0.push(input);
+        }
+        for group in self.can_select {
+            let mut split = BTreeMap::<K, Vec<Input>>::new();
+            for input in group.into_inputs() {
+                split.entry(policy(&input)).or_default().push(input);
+            }
+            for (key, inputs) in split {
+                partitions.entry(key).or_default().1.push(inputs);
+            }
+        }
+        partitions
+            .into_iter()
+            .map(|(key, (must_select, can_select))| {
+                let must_select = InputGroup::from_inputs(must_select);
+                let can_select = can_select
+                    .into_iter()
+                    .filter_map(InputGroup::from_inputs)
+                    .collect::<Vec<_>>();
+                let contains = must_select
+                    .iter()
+                    .chain(&can_select)
+                    .flat_map(InputGroup::inputs)
+                    .map(Input::prev_outpoint)
+                    .collect();
+                let cs_candidates =
+                    Self::build_cs_candidates(&must_select, &can_select, ancestor_feerate);
+                let candidates = InputCandidates {
+                    contains,
+                    must_select,
+                    can_select,
+                    cs_candidates,
+                    ancestor_feerate,
+                };
+                (key, candidates)
+            })
+            .collect()
+    }
+
+    /// Pay for bumping the unconfirmed ancestors of the input candidates up to `feerate`.
+    ///
+    /// The value of each group is reduced by [`InputGroup::ancestor_bump_fee`] so that selecting
+    /// an unconfirmed input also pays for the low-feerate ancestors it inherits. `feerate` should
+    /// be the [`SelectorParams::target_feerate`]. Groups with incomplete ancestors (see
+    /// [`AncestorPackage::is_complete`](crate::AncestorPackage::is_complete)) are valued at zero.
+    ///
+    /// Ancestors shared between groups are paid for by each group during selection. When
+    /// finalizing, the [`Selector`] only charges the merged ancestor package of the selected
+    /// groups once and returns the rest to change. Do not combine with [`SelectorParams::cpfp`]
+    /// for the same ancestors.
+    pub fn bump_ancestors(mut self, feerate: FeeRate) -> Self {
+        self.ancestor_feerate = Some(feerate);
+        self.cs_candidates =
+            Self::build_cs_candidates(&self.must_select, &self.can_select, self.ancestor_feerate);
+        self
+    }
+
+    /// The feerate to bump the ancestors to, see [`bump_ancestors`](Self::bump_ancestors).
+    pub(crate) fn ancestor_feerate(&self) -> Option<FeeRate> {
+        self.ancestor_feerate
+    }
+
     /// Attempt to convert the input candidates into a valid [`Selection`] with a given
     /// `algorithm` and selector `params`.
     pub fn into_selection<A, E>(
@@ -207,8 +371,11 @@ impl InputCandidates {
             .select_with_algorithm(algorithm)
             .map_err(IntoSelectionError::SelectionAlgorithm)?;
         let selection = selector
-            .try_finalize()
+            .finalize()
             .ok_or(IntoSelectionError::CannotMeetTarget(CannotMeetTarget))?;
+        if selector.is_truc() {
+            selection.check_truc().map_err(IntoSelectionError::Truc)?;
+        }
         Ok(selection)
     }
 }
@@ -222,6 +389,8 @@ pub enum IntoSelectionError<E> {
     SelectionAlgorithm(E),
     /// The target cannot be met
     CannotMeetTarget(CannotMeetTarget),
+    /// The selection breaks the TRUC rules
+    Truc(TrucViolation),
 }
 
 impl<E: fmt::Display> fmt::Display for IntoSelectionError<E> {
@@ -234,6 +403,7 @@ impl<E: fmt::Display> fmt::Display for IntoSelectionError<E> {
                 write!(f, "selection algorithm failed: {error}")
             }
             IntoSelectionError::CannotMeetTarget(error) => write!(f, "{error}"),
+            IntoSelectionError::Truc(error) => write!(f, "{error}"),
         }
     }
 }
@@ -314,6 +484,81 @@ pub fn group_by_spk() -> impl Fn(&Input) -> bitcoin::ScriptBuf {
     |input| input.prev_txout().script_pubkey.clone()
 }
 
+/// Group id of policies which only group some of the inputs.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum GroupId<K> {
+    /// Inputs with the same key are grouped.
+    Key(K),
+    /// Input which is not grouped with any other.
+    Single(OutPoint),
+}
+
+/// Group inputs by the keychain (e.g. descriptor) of their script pubkey.
+///
+/// `keychains` maps each script pubkey to its keychain. Inputs with an unknown script pubkey are
+/// not grouped.
+///
+/// Each keychain becomes a single all-or-nothing group: selecting any input of a keychain spends
+/// every input of it, and the groups of different keychains may still be selected together. To
+/// only spend from one keychain at a time, split the candidates with
+/// [`InputCandidates::split_by`] instead and select within one partition.
+pub fn group_by_keychain<K: Ord + Clone>(
+    keychains: impl IntoIterator<Item = (bitcoin::ScriptBuf, K)>,
+) -> impl Fn(&Input) -> GroupId<K> {
+    let keychains = keychains.into_iter().collect::<BTreeMap<_, _>>();
+    move |input| match keychains.get(&input.prev_txout().script_pubkey) {
+        Some(keychain) => GroupId::Key(keychain.clone()),
+        None => GroupId::Single(input.prev_outpoint()),
+    }
+}
+
+/// Group inputs by the tx which created them.
+///
+/// Spending some outputs of a tx while keeping others links them anyway once they are spent, so
+/// this spends all our outputs of a tx together.
+pub fn group_by_txid() -> impl Fn(&Input) -> bitcoin::Txid {
+    |input| input.prev_outpoint().txid
+}
+
+/// Group inputs by user-provided labels, e.g. from BIP-329.
+///
+/// Inputs without a label are not grouped. As with [`group_by_keychain`], the inputs of a label
+/// are spent all together or not at all, but may be spent together with other labels. Use
+/// [`InputCandidates::split_by`] to keep labels apart.
+pub fn group_by_label<L: Ord + Clone>(
+    labels: impl IntoIterator<Item = (OutPoint, L)>,
+) -> impl Fn(&Input) -> GroupId<L> {
+    let labels = labels.into_iter().collect::<BTreeMap<_, _>>();
+    move |input| {
+        let outpoint = input.prev_outpoint();
+        match labels.get(&outpoint) {
+            Some(label) => GroupId::Key(label.clone()),
+            None => GroupId::Single(outpoint),
+        }
+    }
+}
+
+/// Bitcoin Core's maximum number of outputs of the same address in one group.
+pub const OUTPUT_GROUP_MAX_ENTRIES: usize = 100;
+
+/// Spend every output of the same address together, as Bitcoin Core's `avoid_partial_spends`.
+///
+/// Unlike [`group_by_spk`], an address with more than `max_entries` outputs is split into groups
+/// of at most `max_entries`, see [`OUTPUT_GROUP_MAX_ENTRIES`].
+pub fn avoid_partial_spends(
+    max_entries: usize,
+) -> impl FnMut(&Input) -> (bitcoin::ScriptBuf, usize) {
+    let max_entries = max_entries.max(1);
+    let mut counts = BTreeMap::<bitcoin::ScriptBuf, usize>::new();
+    move |input| {
+        let spk = input.prev_txout().script_pubkey.clone();
+        let count = counts.entry(spk.clone()).or_insert(0);
+        let chunk = *count / max_entries;
+        *count += 1;
+        (spk, chunk)
+    }
+}
+
 /// Filter out inputs that cannot be spent now.
 pub fn filter_unspendable_now(
     tip_height: absolute::Height,
@@ -322,7 +567,401 @@ pub fn filter_unspendable_now(
     move |input| input.is_spendable_now(tip_height, tip_time)
 }
 
+/// Filter out groups whose value does not exceed the fee of spending them at `feerate`.
+///
+/// The fee includes bumping the unconfirmed ancestors of the group up to `feerate` (see
+/// [`InputGroup::ancestor_bump_fee`]). Groups whose ancestors cannot be determined are filtered
+/// out.
+///
+/// Use with [`InputCandidates::filter_groups`], or [`InputCandidates::partition_groups`] to keep
+/// the uneconomical groups for consolidating later.
+pub fn filter_uneconomical(feerate: FeeRate) -> impl Fn(&InputGroup) -> bool {
+    move |group| {
+        let ancestors = group.ancestors();
+        if !ancestors.is_complete() {
+            return false;
+        }
+        let cost = feerate
+            .fee_wu(Weight::from_wu(group.weight()))
+            .and_then(|fee| fee.checked_add(ancestors.bump_fee(feerate)))
+            .unwrap_or(Amount::MAX);
+        group.value() > cost
+    }
+}
+
+/// Only keep the given `outpoints`, e.g. to sweep specific outputs.
+///
+/// As with any filter, `must_select` inputs are kept.
+pub fn filter_outpoints(outpoints: impl IntoIterator<Item = OutPoint>) -> impl Fn(&Input) -> bool {
+    let outpoints = outpoints.into_iter().collect::<HashSet<_>>();
+    move |input| outpoints.contains(&input.prev_outpoint())
+}
+
+/// Filter out inputs on script pubkeys which have already been spent from (reused addresses), as
+/// Bitcoin Core's `avoid_reuse` wallet flag.
+///
+/// To only select them as a last resort, use
+/// [`InputCandidates::deprioritize`] with [`CanonicalUnspents::is_spk_spent`] instead.
+pub fn filter_reused_spks(canonical_unspents: &CanonicalUnspents) -> impl Fn(&Input) -> bool + '_ {
+    move |input| !canonical_unspents.is_spk_spent(&input.prev_txout().script_pubkey)
+}
+
+/// Bitcoin Core's default limit on the number of unconfirmed ancestors of a tx, including itself.
+pub const DEFAULT_ANCESTOR_LIMIT: usize = 25;
+
+/// Filter out inputs with unconfirmed ancestor chains that are too long for the new tx to be
+/// relayed.
+///
+/// `max_ancestors` includes the new tx itself, see [`DEFAULT_ANCESTOR_LIMIT`]. Inputs whose
+/// ancestors cannot be determined are filtered out as well.
+pub fn filter_long_chains(max_ancestors: usize) -> impl Fn(&Input) -> bool {
+    move |input| input.ancestors().is_complete() && input.ancestors().txids().len() < max_ancestors
+}
+
 /// No filtering.
 pub fn no_filtering() -> impl Fn(&InputGroup) -> bool {
     |_| true
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::{TxOut, Txid};
+
+    use crate::test_utils::{confirmed, descriptor, plan, spk, tx};
+
+    /// Input spending `vout` of tx `n` to `spk(script)`.
+    fn input(n: u8, vout: u32, script: u8) -> Input {
+        Input::from_prev_txout(
+            plan(),
+            OutPoint::new(Txid::from_byte_array([n; 32]), vout),
+            TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: spk(script),
+            },
+            None,
+            false,
+        )
+    }
+
+    /// `(txid byte, vout)` of the inputs of each group, `must_select` first.
+    fn groups(candidates: &InputCandidates) -> Vec<Vec<(u8, u32)>> {
+        candidates
+            .groups()
+            .map(|group| {
+                group
+                    .inputs()
+                    .iter()
+                    .map(|input| {
+                        let op = input.prev_outpoint();
+                        (op.txid.to_byte_array()[0], op.vout)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn input_candidates() -> InputCandidates {
+        InputCandidates::new(
+            [input(1, 0, 1)],
+            [
+                input(1, 1, 2),
+                input(2, 0, 3),
+                input(2, 1, 1),
+                input(3, 0, 3),
+            ],
+        )
+    }
+
+    #[test]
+    fn group_by_keychain_groups_keychain() {
+        let keychains = [
+            (spk(1), "external"),
+            (spk(2), "internal"),
+            (spk(3), "external"),
+        ];
+        let candidates = input_candidates().regroup(group_by_keychain(keychains));
+        assert_eq!(
+            groups(&candidates),
+            [vec![(1, 0), (2, 0), (2, 1), (3, 0)], vec![(1, 1)]]
+        );
+
+        // unknown script pubkeys are not grouped
+        let candidates = input_candidates().regroup(group_by_keychain([(spk(3), 0)]));
+        assert_eq!(
+            groups(&candidates),
+            [
+                vec![(1, 0)],
+                vec![(1, 1)],
+                vec![(2, 0), (3, 0)],
+                vec![(2, 1)]
+            ]
+        );
+    }
+
+    /// Outpoints selected with `select_until_target_met` to pay 5_000 sats.
+    fn selected(candidates: InputCandidates) -> Vec<(u8, u32)> {
+        let params = SelectorParams::new(
+            FeeRate::from_sat_per_vb_u32(1),
+            vec![crate::Output::with_script(spk(9), Amount::from_sat(5_000))],
+            crate::ScriptSource::from_descriptor(descriptor()),
+            crate::ChangePolicyType::NoDust,
+            bdk_coin_select::DrainWeights::TR_KEYSPEND,
+        );
+        let selection = candidates
+            .into_selection(|s: &mut Selector| s.select_until_target_met(), params)
+            .unwrap();
+        selection
+            .inputs
+            .iter()
+            .map(|input| {
+                let op = input.prev_outpoint();
+                (op.txid.to_byte_array()[0], op.vout)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keychains_are_selected_all_or_nothing_or_separately() {
+        let keychains = BTreeMap::from([
+            (spk(1), "external"),
+            (spk(2), "internal"),
+            (spk(3), "external"),
+        ]);
+
+        // grouping spends every input of the keychain of the must_select input
+        let grouped = input_candidates().regroup(group_by_keychain(keychains.clone()));
+        assert_eq!(selected(grouped), [(1, 0), (2, 0), (2, 1), (3, 0)]);
+
+        // splitting only spends what is needed from a single keychain
+        let mut partitions = input_candidates()
+            .split_by(|input| keychains.get(&input.prev_txout().script_pubkey).copied());
+        assert_eq!(
+            partitions.keys().copied().collect::<Vec<_>>(),
+            [Some("external"), Some("internal")]
+        );
+        let external = partitions.remove(&Some("external")).unwrap();
+        assert_eq!(
+            groups(&external),
+            [vec![(1, 0)], vec![(2, 0)], vec![(2, 1)], vec![(3, 0)]]
+        );
+        assert_eq!(selected(external), [(1, 0)]);
+        let internal = partitions.remove(&Some("internal")).unwrap();
+        assert!(internal.must_select().is_none());
+        assert_eq!(selected(internal), [(1, 1)]);
+    }
+
+    #[test]
+    fn group_by_txid_groups_siblings() {
+        let candidates = input_candidates().regroup(group_by_txid());
+        assert_eq!(
+            groups(&candidates),
+            [vec![(1, 0), (1, 1)], vec![(2, 0), (2, 1)], vec![(3, 0)]]
+        );
+    }
+
+    #[test]
+    fn group_by_label_groups_labelled() {
+        let labels = [
+            (OutPoint::new(Txid::from_byte_array([2; 32]), 0), "kyc"),
+            (OutPoint::new(Txid::from_byte_array([3; 32]), 0), "kyc"),
+            (
+                OutPoint::new(Txid::from_byte_array([1; 32]), 1),
+                "donations",
+            ),
+        ];
+        let candidates = input_candidates().regroup(group_by_label(labels));
+        assert_eq!(
+            groups(&candidates),
+            [
+                vec![(1, 0)],
+                vec![(1, 1)],
+                vec![(2, 0), (3, 0)],
+                vec![(2, 1)]
+            ]
+        );
+    }
+
+    #[test]
+    fn avoid_partial_spends_caps_groups() {
+        let candidates = input_candidates().regroup(avoid_partial_spends(OUTPUT_GROUP_MAX_ENTRIES));
+        assert_eq!(
+            groups(&candidates),
+            [vec![(1, 0), (2, 1)], vec![(1, 1)], vec![(2, 0), (3, 0)]]
+        );
+
+        // five outputs to the same address, one of them must be selected
+        let candidates = InputCandidates::new(
+            [input(4, 2, 1)],
+            (0..5)
+                .filter(|&vout| vout != 2)
+                .map(|vout| input(4, vout, 1)),
+        )
+        .regroup(avoid_partial_spends(2));
+        assert_eq!(
+            groups(&candidates),
+            [vec![(4, 2), (4, 0)], vec![(4, 1), (4, 3)], vec![(4, 4)]]
+        );
+    }
+
+    #[test]
+    fn reused_spks_are_filtered_or_deprioritized() {
+        let reused = descriptor().script_pubkey();
+        let plan = plan();
+        let funding = tx(
+            &[OutPoint::default()],
+            &[(reused.clone(), 10_000), (reused.clone(), 10_000)],
+        );
+        let fresh = tx(
+            &[OutPoint::new(Txid::from_byte_array([9; 32]), 0)],
+            &[(spk(1), 10_000)],
+        );
+        let spending = tx(
+            &[OutPoint::new(funding.compute_txid(), 0)],
+            &[(spk(2), 10_000)],
+        );
+        let status = confirmed();
+        let mut canon = CanonicalUnspents::new([
+            (funding.clone(), status),
+            (fresh.clone(), status),
+            (spending.clone(), None),
+        ]);
+        assert!(canon.is_spk_spent(&reused));
+        assert!(!canon.is_spk_spent(&spk(1)));
+
+        let unspents = [
+            OutPoint::new(funding.compute_txid(), 1),
+            OutPoint::new(fresh.compute_txid(), 0),
+        ];
+        let candidates = |canon: &CanonicalUnspents| {
+            InputCandidates::new(
+                [],
+                canon.try_get_unspents(unspents.iter().map(|&op| (op, plan.clone()))),
+            )
+        };
+
+        let filtered = candidates(&canon).filter(filter_reused_spks(&canon));
+        assert!(!filtered.contains(unspents[0]));
+        assert!(filtered.contains(unspents[1]));
+
+        let deprioritized = candidates(&canon)
+            .deprioritize(|input| canon.is_spk_spent(&input.prev_txout().script_pubkey));
+        let order = deprioritized
+            .inputs()
+            .map(Input::prev_outpoint)
+            .collect::<Vec<_>>();
+        assert_eq!(order, [unspents[1], unspents[0]]);
+
+        // the spend is gone once the spending tx is replaced
+        canon
+            .extract_replacements([spending.compute_txid()])
+            .unwrap();
+        assert!(!canon.is_spk_spent(&reused));
+        let filtered = candidates(&canon).filter(filter_reused_spks(&canon));
+        assert!(filtered.contains(unspents[0]));
+    }
+
+    #[test]
+    fn replaced_spends_are_released_once() {
+        let reused = descriptor().script_pubkey();
+        let funding = tx(
+            &[OutPoint::default()],
+            &[
+                (reused.clone(), 10_000),
+                (reused.clone(), 10_000),
+                (reused.clone(), 10_000),
+            ],
+        );
+        let funding_txid = funding.compute_txid();
+        let original = tx(
+            &[
+                OutPoint::new(funding_txid, 0),
+                OutPoint::new(funding_txid, 1),
+            ],
+            &[(spk(1), 19_000)],
+        );
+        let other = tx(&[OutPoint::new(funding_txid, 2)], &[(spk(2), 9_000)]);
+        let mut canon = CanonicalUnspents::new([
+            (funding, confirmed()),
+            (original.clone(), None),
+            (other.clone(), None),
+        ]);
+
+        canon
+            .extract_replacements([original.compute_txid(), original.compute_txid()])
+            .unwrap();
+        // the spend by `other` remains
+        assert!(canon.is_spk_spent(&reused));
+
+        canon.extract_replacements([other.compute_txid()]).unwrap();
+        assert!(!canon.is_spk_spent(&reused));
+    }
+
+    #[test]
+    fn uneconomical_groups_are_dropped() {
+        let input = |n: u8, value: u64| {
+            Input::from_prev_txout(
+                plan(),
+                OutPoint::new(Txid::from_byte_array([n; 32]), 0),
+                TxOut {
+                    value: Amount::from_sat(value),
+                    script_pubkey: spk(n),
+                },
+                None,
+                false,
+            )
+        };
+        // a p2wpkh input is 68 vbytes, i.e. 680 sats at 10 sat/vB
+        let feerate = FeeRate::from_sat_per_vb_u32(10);
+        let (candidates, dropped) = InputCandidates::new(
+            [input(1, 300)],
+            [input(2, 600), input(3, 10_000), input(4, 680)],
+        )
+        .partition_groups(filter_uneconomical(feerate));
+        assert_eq!(groups(&candidates), [vec![(1, 0)], vec![(3, 0)]]);
+        let dropped = dropped
+            .iter()
+            .map(|group| group.value().to_sat())
+            .collect::<Vec<_>>();
+        assert_eq!(dropped, [600, 680]);
+        assert!(!candidates.contains(OutPoint::new(Txid::from_byte_array([2; 32]), 0)));
+        assert_eq!(candidates.coin_select_candidates().len(), 2);
+
+        // economical at a lower feerate
+        let candidates = InputCandidates::new([], [input(2, 600), input(4, 680)])
+            .filter_groups(filter_uneconomical(FeeRate::from_sat_per_vb_u32(1)));
+        assert_eq!(candidates.can_select().len(), 2);
+    }
+
+    #[test]
+    fn uneconomical_groups_pay_for_ancestors() {
+        let grandparent = tx(
+            &[OutPoint::new(Txid::from_byte_array([1; 32]), 0)],
+            &[(descriptor().script_pubkey(), 10_000)],
+        );
+        // the unconfirmed parent pays no fee
+        let parent = tx(
+            &[OutPoint::new(grandparent.compute_txid(), 0)],
+            &[(descriptor().script_pubkey(), 10_000)],
+        );
+        let canon = CanonicalUnspents::new([(grandparent, confirmed()), (parent.clone(), None)]);
+        let input = canon
+            .try_get_unspent(OutPoint::new(parent.compute_txid(), 0), plan())
+            .unwrap();
+        let group = InputGroup::from_input(input);
+
+        // spending the input costs 6_800 sats, but bumping its parent costs more than the rest
+        let feerate = FeeRate::from_sat_per_vb_u32(100);
+        let spend_fee = feerate.fee_wu(Weight::from_wu(group.weight())).unwrap();
+        assert!(spend_fee < group.value());
+        assert!(spend_fee + group.ancestor_bump_fee(feerate) >= group.value());
+        assert!(!filter_uneconomical(feerate)(&group));
+
+        // economical at a lower feerate
+        assert!(filter_uneconomical(FeeRate::from_sat_per_vb_u32(10))(
+            &group
+        ));
+    }
+}
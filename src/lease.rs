@@ -0,0 +1,414 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use bitcoin::{OutPoint, Txid};
+use miniscript::bitcoin;
+use miniscript::plan::Plan;
+
+use crate::collections::{BTreeMap, HashSet};
+use crate::{
+    CanonicalUnspents, Input, InputCandidates, IntoSelectionError, Selection, Selector,
+    SelectorParams,
+};
+
+/// Identifies a [`Lease`] issued by a [`LeaseManager`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LeaseId(u64);
+
+impl fmt::Display for LeaseId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "lease {}", self.0)
+    }
+}
+
+/// Time-limited lease on the inputs of a [`Selection`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    /// Leased outpoints.
+    pub outpoints: Vec<OutPoint>,
+    /// Unix timestamp (in seconds) at which the lease expires.
+    pub expires_at: u64,
+    /// Txid of the tx spending the leased outpoints, once known.
+    pub txid: Option<Txid>,
+}
+
+/// Occurs when leasing an outpoint which is already leased.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeaseConflict {
+    /// Outpoint which is already leased.
+    pub outpoint: OutPoint,
+    /// Lease which holds the outpoint.
+    pub lease: LeaseId,
+}
+
+impl fmt::Display for LeaseConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "outpoint {} is held by {}", self.outpoint, self.lease)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for LeaseConflict {}
+
+/// Occurs when [`LeaseManager::select_and_lease`] fails.
+#[derive(Debug)]
+pub enum SelectAndLeaseError<E> {
+    /// Selection failed.
+    Selection(IntoSelectionError<E>),
+    /// A selected input is already leased.
+    Conflict(LeaseConflict),
+}
+
+impl<E: fmt::Display> fmt::Display for SelectAndLeaseError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Selection(err) => write!(f, "{err}"),
+            Self::Conflict(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: fmt::Debug + fmt::Display> std::error::Error for SelectAndLeaseError<E> {}
+
+/// Issues leases on selected outpoints so that concurrently built psbts never select the same
+/// outpoints.
+///
+/// Use [`select_and_lease`](Self::select_and_lease) to select from [`InputCandidates`] constructed
+/// with [`input_candidates`](Self::input_candidates) (or [`filter`](Self::filter)) and lease the
+/// inputs of the selection in one step. When building psbts from multiple threads, share the
+/// manager behind a mutex.
+#[derive(Debug, Clone, Default)]
+pub struct LeaseManager {
+    next_id: u64,
+    leases: BTreeMap<LeaseId, Lease>,
+    leased: BTreeMap<OutPoint, LeaseId>,
+}
+
+impl LeaseManager {
+    /// New manager without leases.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Select from `candidates` and lease the inputs of the selection until the unix timestamp
+    /// `expires_at`.
+    ///
+    /// `replaces` are the txids of the txs which the selection replaces, see
+    /// [`lease`](Self::lease).
+    ///
+    /// Leased inputs are filtered out of `candidates` before selecting, so this only fails with a
+    /// [`LeaseConflict`] if a `must_select` input is held by a lease of a tx which is not
+    /// replaced. Leases which expired at `now` are released first.
+    pub fn select_and_lease<A, E>(
+        &mut self,
+        candidates: InputCandidates,
+        algorithm: A,
+        params: SelectorParams,
+        replaces: &[Txid],
+        now: u64,
+        expires_at: u64,
+    ) -> Result<(Selection, LeaseId), SelectAndLeaseError<E>>
+    where
+        A: FnMut(&mut Selector) -> Result<(), E>,
+    {
+        self.expire(now);
+        let selection = candidates
+            .filter(self.filter(now))
+            .into_selection(algorithm, params)
+            .map_err(SelectAndLeaseError::Selection)?;
+        let id = self
+            .lease(&selection, replaces, now, expires_at)
+            .map_err(SelectAndLeaseError::Conflict)?;
+        Ok((selection, id))
+    }
+
+    /// Lease the inputs of `selection` until the unix timestamp `expires_at`.
+    ///
+    /// `replaces` are the txids of the txs which the selection replaces (see
+    /// [`set_txid`](Self::set_txid)). Their leases are released, and the selection takes over
+    /// their inputs. Pass an empty slice if the selection does not replace any tx.
+    ///
+    /// Leases which expired at `now` are released first.
+    ///
+    /// # Errors
+    ///
+    /// If any input is held by a lease of a tx which is not replaced, in which case nothing is
+    /// leased or released.
+    pub fn lease(
+        &mut self,
+        selection: &Selection,
+        replaces: &[Txid],
+        now: u64,
+        expires_at: u64,
+    ) -> Result<LeaseId, LeaseConflict> {
+        self.expire(now);
+        let outpoints = selection
+            .inputs
+            .iter()
+            .map(Input::prev_outpoint)
+            .collect::<Vec<_>>();
+        let is_replaced = |id: &LeaseId| {
+            self.leases
+                .get(id)
+                .and_then(|lease| lease.txid)
+                .map_or(false, |txid| replaces.contains(&txid))
+        };
+        if let Some((&outpoint, &lease)) = outpoints
+            .iter()
+            .filter_map(|op| self.leased.get_key_value(op))
+            .find(|(_, id)| !is_replaced(id))
+        {
+            return Err(LeaseConflict { outpoint, lease });
+        }
+        for &txid in replaces {
+            self.release_tx(txid);
+        }
+        let id = LeaseId(self.next_id);
+        self.next_id += 1;
+        for &op in &outpoints {
+            self.leased.insert(op, id);
+        }
+        self.leases.insert(
+            id,
+            Lease {
+                outpoints,
+                expires_at,
+                txid: None,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Record the txid of the tx created from the lease, so that it can be released with
+    /// [`release_tx`](Self::release_tx).
+    ///
+    /// Returns `false` if the lease does not exist.
+    pub fn set_txid(&mut self, id: LeaseId, txid: Txid) -> bool {
+        match self.leases.get_mut(&id) {
+            Some(lease) => {
+                lease.txid = Some(txid);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Extend the lease until the unix timestamp `expires_at`.
+    ///
+    /// Returns `false` if the lease does not exist.
+    pub fn renew(&mut self, id: LeaseId, expires_at: u64) -> bool {
+        match self.leases.get_mut(&id) {
+            Some(lease) => {
+                lease.expires_at = expires_at;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Release the lease, e.g. when the psbt is abandoned.
+    pub fn release(&mut self, id: LeaseId) -> Option<Lease> {
+        let lease = self.leases.remove(&id)?;
+        for op in &lease.outpoints {
+            self.leased.remove(op);
+        }
+        Some(lease)
+    }
+
+    /// Release the leases of the tx with `txid`, e.g. when it is abandoned or replaced.
+    pub fn release_tx(&mut self, txid: Txid) -> Vec<Lease> {
+        let ids = self
+            .leases
+            .iter()
+            .filter(|(_, lease)| lease.txid == Some(txid))
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+        ids.into_iter().filter_map(|id| self.release(id)).collect()
+    }
+
+    /// Release the leases which expired at unix timestamp `now`.
+    pub fn expire(&mut self, now: u64) -> Vec<Lease> {
+        let ids = self
+            .leases
+            .iter()
+            .filter(|(_, lease)| lease.expires_at <= now)
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+        ids.into_iter().filter_map(|id| self.release(id)).collect()
+    }
+
+    /// Get the lease.
+    pub fn get(&self, id: LeaseId) -> Option<&Lease> {
+        self.leases.get(&id)
+    }
+
+    /// Iterate over all leases.
+    pub fn leases(&self) -> impl Iterator<Item = (LeaseId, &Lease)> + '_ {
+        self.leases.iter().map(|(&id, lease)| (id, lease))
+    }
+
+    /// Whether `outpoint` is held by a lease which has not expired at unix timestamp `now`.
+    pub fn is_leased(&self, outpoint: OutPoint, now: u64) -> bool {
+        self.leased
+            .get(&outpoint)
+            .and_then(|id| self.leases.get(id))
+            .map_or(false, |lease| lease.expires_at > now)
+    }
+
+    /// Filter for [`InputCandidates::filter`] which removes leased inputs.
+    ///
+    /// As with any filter, `must_select` inputs are kept, so the inputs of a tx that is being
+    /// replaced can still be selected. Pass the txid of the replaced tx to
+    /// [`lease`](Self::lease) to take over its leases.
+    pub fn filter(&self, now: u64) -> impl Fn(&Input) -> bool + '_ {
+        move |input| !self.is_leased(input.prev_outpoint(), now)
+    }
+
+    /// Construct [`InputCandidates`] from the unspent `outpoints` of `canonical_unspents`,
+    /// excluding leased outpoints.
+    ///
+    /// Outpoints in `must_select` (e.g. the inputs of a tx being replaced) are `must_select` even
+    /// if they are leased, see [`lease`](Self::lease) for taking over the leases of replaced txs.
+    pub fn input_candidates<O>(
+        &self,
+        canonical_unspents: &CanonicalUnspents,
+        outpoints: O,
+        must_select: &HashSet<OutPoint>,
+        now: u64,
+    ) -> InputCandidates
+    where
+        O: IntoIterator<Item = (OutPoint, Plan)>,
+    {
+        let (must_select, can_select): (Vec<_>, Vec<_>) = canonical_unspents
+            .try_get_unspents(
+                outpoints
+                    .into_iter()
+                    .filter(|(op, _)| must_select.contains(op) || !self.is_leased(*op, now))
+                    .collect::<Vec<_>>(),
+            )
+            .partition(|input| must_select.contains(&input.prev_outpoint()));
+        InputCandidates::new(must_select, can_select)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bdk_coin_select::{DrainWeights, InsufficientFunds};
+    use bitcoin::hashes::Hash;
+    use bitcoin::{absolute, transaction, Amount, FeeRate, Transaction, TxIn, TxOut};
+
+    use crate::test_utils::{confirmed, descriptor, plan};
+    use crate::{ChangePolicyType, Output, ScriptSource};
+
+    fn params() -> SelectorParams {
+        SelectorParams::new(
+            FeeRate::from_sat_per_vb_u32(2),
+            vec![Output::with_script(
+                descriptor().script_pubkey(),
+                Amount::from_sat(15_000),
+            )],
+            ScriptSource::from_descriptor(descriptor()),
+            ChangePolicyType::NoDust,
+            DrainWeights::TR_KEYSPEND,
+        )
+    }
+
+    fn select_and_lease(
+        leases: &mut LeaseManager,
+        candidates: InputCandidates,
+        replaces: &[Txid],
+        now: u64,
+    ) -> Result<(Selection, LeaseId), SelectAndLeaseError<InsufficientFunds>> {
+        leases.select_and_lease(
+            candidates,
+            |s: &mut Selector| s.select_until_target_met(),
+            params(),
+            replaces,
+            now,
+            now + 600,
+        )
+    }
+
+    #[test]
+    fn concurrent_selections_do_not_overlap() {
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: (0..4)
+                .map(|_| TxOut {
+                    value: Amount::from_sat(10_000),
+                    script_pubkey: descriptor().script_pubkey(),
+                })
+                .collect(),
+        };
+        let txid = tx.compute_txid();
+        let canon = CanonicalUnspents::new([(tx, confirmed())]);
+        let plan = plan();
+        let outpoints = (0..4).map(|vout| (OutPoint::new(txid, vout), plan.clone()));
+        let no_must_select = HashSet::new();
+        let candidates = |leases: &LeaseManager, now: u64| {
+            leases.input_candidates(&canon, outpoints.clone(), &no_must_select, now)
+        };
+
+        let mut leases = LeaseManager::new();
+        // selecting from stale candidates skips the leased inputs
+        let stale = candidates(&leases, 0);
+        let (first, first_id) = select_and_lease(&mut leases, stale.clone(), &[], 0).unwrap();
+        assert_eq!(
+            leases.lease(&first, &[], 0, 600),
+            Err(LeaseConflict {
+                outpoint: first.inputs[0].prev_outpoint(),
+                lease: first_id,
+            })
+        );
+        let (second, _) = select_and_lease(&mut leases, stale, &[], 0).unwrap();
+        assert!(first.inputs.iter().all(|input| !second
+            .inputs
+            .iter()
+            .any(|other| other.prev_outpoint() == input.prev_outpoint())));
+
+        // all outpoints are leased
+        let exhausted = candidates(&leases, 0);
+        assert_eq!(exhausted.inputs().count(), 0);
+        assert!(matches!(
+            select_and_lease(&mut leases, exhausted, &[], 0),
+            Err(SelectAndLeaseError::Selection(_))
+        ));
+
+        // a leased must_select input conflicts unless its tx is replaced
+        let must_select = first.inputs.iter().map(Input::prev_outpoint).collect();
+        let replacing = leases.input_candidates(&canon, outpoints.clone(), &must_select, 0);
+        assert!(replacing.contains(first.inputs[0].prev_outpoint()));
+        let first_txid = Txid::from_byte_array([1; 32]);
+        assert!(leases.set_txid(first_id, first_txid));
+        assert!(matches!(
+            select_and_lease(&mut leases, replacing.clone(), &[], 0),
+            Err(SelectAndLeaseError::Conflict(LeaseConflict { lease, .. })) if lease == first_id
+        ));
+        let (replacement, replacement_id) =
+            select_and_lease(&mut leases, replacing, &[first_txid], 0).unwrap();
+        assert!(leases.get(first_id).is_none());
+        assert!(replacement
+            .inputs
+            .iter()
+            .all(|input| leases.is_leased(input.prev_outpoint(), 0)));
+
+        // releasing the abandoned tx frees its outpoints
+        let replacement_txid = Txid::from_byte_array([2; 32]);
+        assert!(leases.set_txid(replacement_id, replacement_txid));
+        assert_eq!(leases.release_tx(replacement_txid).len(), 1);
+        assert_eq!(
+            candidates(&leases, 0).inputs().count(),
+            4 - second.inputs.len()
+        );
+
+        // the remaining lease expires
+        assert_eq!(candidates(&leases, 600).inputs().count(), 4);
+        assert_eq!(leases.expire(600).len(), 1);
+        assert_eq!(leases.leases().count(), 0);
+    }
+}
@@ -12,28 +12,48 @@ extern crate alloc;
 #[cfg(feature = "std")]
 extern crate std;
 
+mod analysis;
+mod batch;
 mod canonical_unspents;
+mod coin_control;
+mod collaborative;
+mod cpfp;
 mod finalizer;
 mod input;
 mod input_candidates;
+mod lease;
 mod output;
+mod payjoin;
 mod rbf;
 mod selection;
 mod selector;
 mod signer;
+mod silent_payments;
+#[cfg(test)]
+mod test_utils;
+mod truc;
 
+pub use analysis::*;
+pub use batch::*;
 pub use canonical_unspents::*;
+pub use coin_control::*;
+pub use collaborative::*;
+pub use cpfp::*;
 pub use finalizer::*;
 pub use input::*;
 pub use input_candidates::*;
+pub use lease::*;
 pub use miniscript;
 pub use miniscript::bitcoin;
 use miniscript::{DefiniteDescriptorKey, Descriptor};
 pub use output::*;
+pub use payjoin::*;
 pub use rbf::*;
 pub use selection::*;
 pub use selector::*;
 pub use signer::*;
+pub use silent_payments::*;
+pub use truc::*;
 
 #[cfg(feature = "std")]
 pub(crate) mod collections {
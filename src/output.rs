@@ -1,16 +1,110 @@
 use alloc::boxed::Box;
-use bitcoin::{Amount, ScriptBuf, TxOut};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use bitcoin::bip32::KeySource;
+use bitcoin::key::{TapTweak, TweakedPublicKey, XOnlyPublicKey};
+use bitcoin::secp256k1::{Secp256k1, Verification};
+use bitcoin::taproot::{TapLeafHash, TapTree};
+use bitcoin::{psbt, Amount, Script, ScriptBuf, TxOut};
 use miniscript::bitcoin;
 
-use crate::DefiniteDescriptor;
+use crate::{DefiniteDescriptor, SilentPaymentAddress};
+
+/// Whether `script` is a pay-to-anchor (P2A) script.
+pub(crate) fn is_p2a(script: &Script) -> bool {
+    script == ScriptBuf::new_p2a().as_script()
+}
+
+/// Taproot output from an internal key and an optional script tree.
+///
+/// Unlike a [`DefiniteDescriptor`], the scripts in the tree do not need to be miniscript, e.g.
+/// when the tree is provided by another party.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaprootOutput {
+    internal_key: XOnlyPublicKey,
+    tap_tree: Option<TapTree>,
+    key_origins: BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, KeySource)>,
+    output_key: TweakedPublicKey,
+}
+
+impl TaprootOutput {
+    /// From the internal key and script tree (if any).
+    pub fn new<C: Verification>(
+        secp: &Secp256k1<C>,
+        internal_key: XOnlyPublicKey,
+        tap_tree: Option<TapTree>,
+    ) -> Self {
+        let merkle_root = tap_tree.as_ref().map(TapTree::root_hash);
+        let (output_key, _) = internal_key.tap_tweak(secp, merkle_root);
+        Self {
+            internal_key,
+            tap_tree,
+            key_origins: BTreeMap::new(),
+            output_key,
+        }
+    }
+
+    /// Add the origin of `key`, and the hashes of the leaves it appears in.
+    ///
+    /// The internal key has no leaf hashes.
+    pub fn with_key_origin(
+        mut self,
+        key: XOnlyPublicKey,
+        leaf_hashes: Vec<TapLeafHash>,
+        origin: KeySource,
+    ) -> Self {
+        self.key_origins.insert(key, (leaf_hashes, origin));
+        self
+    }
+
+    /// Internal key.
+    pub fn internal_key(&self) -> XOnlyPublicKey {
+        self.internal_key
+    }
+
+    /// Script tree (if any).
+    pub fn tap_tree(&self) -> Option<&TapTree> {
+        self.tap_tree.as_ref()
+    }
+
+    /// Key origins.
+    pub fn key_origins(&self) -> &BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, KeySource)> {
+        &self.key_origins
+    }
+
+    /// Tweaked output key.
+    pub fn output_key(&self) -> TweakedPublicKey {
+        self.output_key
+    }
+
+    /// Script pubkey.
+    pub fn script_pubkey(&self) -> ScriptBuf {
+        ScriptBuf::new_p2tr_tweaked(self.output_key)
+    }
+
+    /// Fill the taproot fields of `psbt_output`.
+    pub fn update_psbt_output(&self, psbt_output: &mut psbt::Output) {
+        psbt_output.tap_internal_key = Some(self.internal_key);
+        psbt_output.tap_tree = self.tap_tree.clone();
+        psbt_output
+            .tap_key_origins
+            .extend(self.key_origins.iter().map(|(k, v)| (*k, v.clone())));
+    }
+}
 
 /// Source of the output script pubkey
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum ScriptSource {
     /// bitcoin script
     Script(ScriptBuf),
     /// definite descriptor
     Descriptor(Box<DefiniteDescriptor>),
+    /// silent payment address, resolved after selection with
+    /// [`Selection::resolve_silent_payments`](crate::Selection::resolve_silent_payments)
+    SilentPayment(SilentPaymentAddress),
+    /// taproot internal key and script tree
+    Taproot(Box<TaprootOutput>),
 }
 
 impl From<ScriptBuf> for ScriptSource {
@@ -25,6 +119,18 @@ impl From<DefiniteDescriptor> for ScriptSource {
     }
 }
 
+impl From<SilentPaymentAddress> for ScriptSource {
+    fn from(address: SilentPaymentAddress) -> Self {
+        Self::from_silent_payment(address)
+    }
+}
+
+impl From<TaprootOutput> for ScriptSource {
+    fn from(taproot: TaprootOutput) -> Self {
+        Self::from_taproot(taproot)
+    }
+}
+
 impl ScriptSource {
     /// From script
     pub fn from_script(script: ScriptBuf) -> Self {
@@ -36,19 +142,60 @@ impl ScriptSource {
         Self::Descriptor(Box::new(descriptor))
     }
 
+    /// From silent payment address
+    pub fn from_silent_payment(address: SilentPaymentAddress) -> Self {
+        Self::SilentPayment(address)
+    }
+
+    /// From taproot internal key and script tree
+    pub fn from_taproot(taproot: TaprootOutput) -> Self {
+        Self::Taproot(Box::new(taproot))
+    }
+
+    /// Pay-to-anchor (P2A) script.
+    pub fn p2a() -> Self {
+        Self::Script(ScriptBuf::new_p2a())
+    }
+
+    /// Whether this is a pay-to-anchor (P2A) script.
+    pub fn is_p2a(&self) -> bool {
+        matches!(self, ScriptSource::Script(spk) if is_p2a(spk))
+    }
+
     /// To ScriptBuf
+    ///
+    /// An unresolved silent payment returns a placeholder of the same weight, see
+    /// [`SilentPaymentAddress::placeholder_script`].
     pub fn script(&self) -> ScriptBuf {
         match self {
             ScriptSource::Script(spk) => spk.clone(),
             ScriptSource::Descriptor(descriptor) => descriptor.script_pubkey(),
+            ScriptSource::SilentPayment(address) => address.placeholder_script(),
+            ScriptSource::Taproot(taproot) => taproot.script_pubkey(),
         }
     }
 
     /// Get descriptor (if any).
     pub fn descriptor(&self) -> Option<&DefiniteDescriptor> {
         match self {
-            ScriptSource::Script(_) => None,
             ScriptSource::Descriptor(descriptor) => Some(descriptor),
+            _ => None,
+        }
+    }
+
+    /// Get taproot internal key and script tree (if any).
+    pub fn taproot(&self) -> Option<&TaprootOutput> {
+        match self {
+            ScriptSource::Taproot(taproot) => Some(taproot),
+            _ => None,
+        }
+    }
+
+    /// Get unresolved silent payment address (if any).
+    pub fn silent_payment(&self) -> Option<&SilentPaymentAddress> {
+        match self {
+            ScriptSource::SilentPayment(address) => Some(address),
+            _ => None,
         }
     }
 }
@@ -74,11 +221,25 @@ impl From<(DefiniteDescriptor, Amount)> for Output {
     }
 }
 
+impl From<(SilentPaymentAddress, Amount)> for Output {
+    fn from((address, value): (SilentPaymentAddress, Amount)) -> Self {
+        Self::with_silent_payment(address, value)
+    }
+}
+
+impl From<(TaprootOutput, Amount)> for Output {
+    fn from((taproot, value): (TaprootOutput, Amount)) -> Self {
+        Self::with_taproot(taproot, value)
+    }
+}
+
 impl From<(ScriptSource, Amount)> for Output {
     fn from((src, value): (ScriptSource, Amount)) -> Self {
         match src {
             ScriptSource::Descriptor(desc) => Self::with_descriptor(*desc, value),
             ScriptSource::Script(s) => Self::with_script(s, value),
+            ScriptSource::SilentPayment(address) => Self::with_silent_payment(address, value),
+            ScriptSource::Taproot(taproot) => Self::with_taproot(*taproot, value),
         }
     }
 }
@@ -100,6 +261,42 @@ impl Output {
         }
     }
 
+    /// From taproot internal key and script tree
+    pub fn with_taproot(taproot: TaprootOutput, value: Amount) -> Self {
+        Self {
+            value,
+            script_pubkey_source: taproot.into(),
+        }
+    }
+
+    /// To silent payment address
+    ///
+    /// The output must be resolved with
+    /// [`Selection::resolve_silent_payments`](crate::Selection::resolve_silent_payments) before
+    /// creating the psbt.
+    pub fn with_silent_payment(address: SilentPaymentAddress, value: Amount) -> Self {
+        Self {
+            value,
+            script_pubkey_source: address.into(),
+        }
+    }
+
+    /// Pay-to-anchor (P2A) output.
+    ///
+    /// The `value` may be zero (ephemeral dust), in which case the output must be spent by a child
+    /// in the same package.
+    pub fn p2a(value: Amount) -> Self {
+        Self {
+            value,
+            script_pubkey_source: ScriptSource::p2a(),
+        }
+    }
+
+    /// Whether this is a pay-to-anchor (P2A) output.
+    pub fn is_p2a(&self) -> bool {
+        self.script_pubkey_source.is_p2a()
+    }
+
     /// Script pubkey
     pub fn script_pubkey(&self) -> ScriptBuf {
         self.script_pubkey_source.script()
@@ -110,6 +307,11 @@ impl Output {
         self.script_pubkey_source.descriptor()
     }
 
+    /// Taproot internal key and script tree
+    pub fn taproot(&self) -> Option<&TaprootOutput> {
+        self.script_pubkey_source.taproot()
+    }
+
     /// Create txout.
     pub fn txout(&self) -> TxOut {
         TxOut {
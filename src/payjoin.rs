@@ -0,0 +1,885 @@
+use alloc::vec::Vec;
+use core::fmt::Display;
+
+use bdk_coin_select::{DrainWeights, TXIN_BASE_WEIGHT};
+use bitcoin::{psbt, Amount, FeeRate, OutPoint, Psbt, Script, ScriptBuf, TxOut, Weight};
+use miniscript::bitcoin;
+
+use crate::collections::HashMap;
+use crate::selection::unsigned_psbt_input;
+use crate::{
+    filter_uneconomical, ChangePolicyType, Finalizer, Input, InputCandidates, ScriptSource,
+    Selection, Selector, SelectorParams,
+};
+
+/// Payjoin parameters which the sender communicates to the receiver, see [BIP-78].
+///
+/// [BIP-78]: https://github.com/bitcoin/bips/blob/master/bip-0078.mediawiki
+#[derive(Debug, Clone)]
+pub struct PayjoinParams {
+    /// Script of the receiver's output.
+    pub payee: ScriptBuf,
+    /// Index of the sender's output from which the receiver may take the additional fee.
+    pub fee_output_index: Option<usize>,
+    /// Maximum amount the receiver may take from the fee output.
+    pub max_additional_fee_contribution: Amount,
+    /// Minimum feerate of the payjoin proposal.
+    pub min_feerate: FeeRate,
+    /// Whether the receiver must keep the script of its output.
+    pub disable_output_substitution: bool,
+}
+
+impl PayjoinParams {
+    /// Params which do not allow the receiver to take any additional fee from the sender.
+    pub fn new(payee: ScriptBuf) -> Self {
+        Self {
+            payee,
+            fee_output_index: None,
+            max_additional_fee_contribution: Amount::ZERO,
+            min_feerate: FeeRate::ZERO,
+            disable_output_substitution: false,
+        }
+    }
+}
+
+/// Occurs when the receiver's payjoin proposal is rejected by the sender.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PayjoinError {
+    /// The tx version was changed.
+    VersionChanged,
+    /// The tx locktime was changed.
+    LockTimeChanged,
+    /// An input of the original tx is missing.
+    MissingInput(OutPoint),
+    /// The sequence of an input of the original tx was changed.
+    SequenceChanged(OutPoint),
+    /// The prev output of an input is unknown.
+    MissingUtxo(OutPoint),
+    /// An input added by the receiver is not finalized.
+    ReceiverInputNotFinalized(OutPoint),
+    /// An input added by the receiver has a different script type than the sender's inputs.
+    MixedInputScripts(OutPoint),
+    /// An input added by the receiver has a different sequence than the sender's inputs.
+    MixedSequence(OutPoint),
+    /// An output of the original tx is missing or its value was changed.
+    OutputChanged(usize),
+    /// The receiver's output was substituted or reduced although this was disabled.
+    PayeeOutputChanged,
+    /// The receiver took more than allowed from the fee output.
+    FeeContributionTooHigh {
+        /// Amount taken from the fee output.
+        contribution: Amount,
+        /// Maximum amount allowed.
+        max: Amount,
+    },
+    /// The proposal pays less absolute fee than the original tx.
+    FeeDecreased,
+    /// The fee increase of the proposal is less than the amount taken from the fee output, i.e.
+    /// the receiver kept part of it.
+    FeeContributionNotPaid {
+        /// Amount taken from the fee output.
+        contribution: Amount,
+        /// Fee of the proposal minus the fee of the original tx.
+        fee_increase: Amount,
+    },
+    /// The feerate of the proposal is below the minimum.
+    FeerateTooLow {
+        /// Estimated feerate of the proposal.
+        feerate: FeeRate,
+        /// Minimum feerate.
+        min_feerate: FeeRate,
+    },
+}
+
+impl Display for PayjoinError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::VersionChanged => write!(f, "tx version was changed"),
+            Self::LockTimeChanged => write!(f, "tx locktime was changed"),
+            Self::MissingInput(op) => write!(f, "original input {} is missing", op),
+            Self::SequenceChanged(op) => write!(f, "sequence of input {} was changed", op),
+            Self::MissingUtxo(op) => write!(f, "prev output of input {} is unknown", op),
+            Self::ReceiverInputNotFinalized(op) => {
+                write!(f, "receiver input {} is not finalized", op)
+            }
+            Self::MixedInputScripts(op) => {
+                write!(f, "receiver input {} has a different script type", op)
+            }
+            Self::MixedSequence(op) => {
+                write!(f, "receiver input {} has a different sequence", op)
+            }
+            Self::OutputChanged(index) => write!(f, "original output {} was changed", index),
+            Self::PayeeOutputChanged => write!(f, "receiver output was changed"),
+            Self::FeeContributionTooHigh { contribution, max } => write!(
+                f,
+                "fee contribution {} exceeds the maximum of {}",
+                contribution, max
+            ),
+            Self::FeeDecreased => write!(f, "proposal pays less fee than the original tx"),
+            Self::FeeContributionNotPaid {
+                contribution,
+                fee_increase,
+            } => write!(
+                f,
+                "fee increase {} is less than the fee contribution {}",
+                fee_increase, contribution
+            ),
+            Self::FeerateTooLow {
+                feerate,
+                min_feerate,
+            } => write!(
+                f,
+                "proposal feerate {} is below the minimum of {}",
+                feerate, min_feerate
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PayjoinError {}
+
+/// Prev output of the psbt input at `index`.
+fn psbt_prev_txout(psbt: &Psbt, index: usize) -> Option<TxOut> {
+    let psbt_input = psbt.inputs.get(index)?;
+    if let Some(txout) = &psbt_input.witness_utxo {
+        return Some(txout.clone());
+    }
+    let vout = psbt.unsigned_tx.input.get(index)?.previous_output.vout;
+    psbt_input
+        .non_witness_utxo
+        .as_ref()?
+        .output
+        .get(vout as usize)
+        .cloned()
+}
+
+fn same_script_type(a: &Script, b: &Script) -> bool {
+    (a.is_p2pkh() && b.is_p2pkh())
+        || (a.is_p2sh() && b.is_p2sh())
+        || (a.is_p2wpkh() && b.is_p2wpkh())
+        || (a.is_p2wsh() && b.is_p2wsh())
+        || (a.is_p2tr() && b.is_p2tr())
+}
+
+fn is_finalized(psbt_input: &psbt::Input) -> bool {
+    psbt_input.final_script_sig.is_some() || psbt_input.final_script_witness.is_some()
+}
+
+/// The sender side of a payjoin.
+///
+/// The sender sends the finalized original tx to the receiver, and checks the receiver's
+/// proposal with [`process_proposal`](Self::process_proposal) before signing it.
+#[derive(Debug, Clone)]
+pub struct PayjoinSender {
+    original: Psbt,
+    unsigned_inputs: HashMap<OutPoint, psbt::Input>,
+    satisfaction_weights: HashMap<OutPoint, u64>,
+    params: PayjoinParams,
+}
+
+impl PayjoinSender {
+    /// Create the sender from the `selection` and the `original` psbt created from it.
+    ///
+    /// `original` must be the finalized psbt sent to the receiver. Its weight gives the feerate
+    /// of the original tx, which bounds the fee the receiver may take for its inputs.
+    pub fn new(selection: &Selection, original: Psbt, params: PayjoinParams) -> Self {
+        Self {
+            unsigned_inputs: selection
+                .inputs
+                .iter()
+                .map(|input| (input.prev_outpoint(), unsigned_psbt_input(input)))
+                .collect(),
+            satisfaction_weights: selection
+                .inputs
+                .iter()
+                .map(|input| (input.prev_outpoint(), input.satisfaction_weight()))
+                .collect(),
+            original,
+            params,
+        }
+    }
+
+    /// Payjoin params.
+    pub fn params(&self) -> &PayjoinParams {
+        &self.params
+    }
+
+    /// Check the receiver's `proposal` and restore our inputs so that it can be signed.
+    ///
+    /// After signing, finalize our inputs with the [`Finalizer`] of the selection, see
+    /// [`Selection::into_finalizer`]. The inputs of the receiver are already finalized.
+    ///
+    /// The receiver may take at most [`PayjoinParams::max_additional_fee_contribution`] from the
+    /// fee output, and no more than the fee of its inputs at the feerate of the original tx.
+    ///
+    /// # Errors
+    ///
+    /// If the receiver tampered with our inputs or outputs, added inputs with a different script
+    /// type or sequence than ours, took more fee than allowed or did not pay it as fee, or the
+    /// proposal pays less fee than the original tx or below the minimum feerate.
+    pub fn process_proposal(&self, mut proposal: Psbt) -> Result<Psbt, PayjoinError> {
+        let original_tx = &self.original.unsigned_tx;
+        let proposal_tx = &proposal.unsigned_tx;
+        if proposal_tx.version != original_tx.version {
+            return Err(PayjoinError::VersionChanged);
+        }
+        if proposal_tx.lock_time != original_tx.lock_time {
+            return Err(PayjoinError::LockTimeChanged);
+        }
+
+        // Inputs
+        let mut original_input_value = Amount::ZERO;
+        let mut sender_scripts = Vec::<ScriptBuf>::new();
+        for (index, txin) in original_tx.input.iter().enumerate() {
+            let op = txin.previous_output;
+            let proposal_txin = proposal_tx
+                .input
+                .iter()
+                .find(|proposal_txin| proposal_txin.previous_output == op)
+                .ok_or(PayjoinError::MissingInput(op))?;
+            if proposal_txin.sequence != txin.sequence {
+                return Err(PayjoinError::SequenceChanged(op));
+            }
+            let txout =
+                psbt_prev_txout(&self.original, index).ok_or(PayjoinError::MissingUtxo(op))?;
+            original_input_value += txout.value;
+            sender_scripts.push(txout.script_pubkey);
+        }
+        // The receiver must use our sequence if all our inputs share it (BIP-78).
+        let sender_sequence =
+            original_tx
+                .input
+                .first()
+                .map(|txin| txin.sequence)
+                .filter(|&sequence| {
+                    original_tx
+                        .input
+                        .iter()
+                        .all(|txin| txin.sequence == sequence)
+                });
+        let max_satisfaction_weight = self
+            .satisfaction_weights
+            .values()
+            .copied()
+            .max()
+            .unwrap_or(0);
+        let mut receiver_input_value = Amount::ZERO;
+        let mut receiver_input_weight = Weight::ZERO;
+        for (index, txin) in proposal_tx.input.iter().enumerate() {
+            let op = txin.previous_output;
+            if original_tx
+                .input
+                .iter()
+                .any(|txin| txin.previous_output == op)
+            {
+                continue;
+            }
+            let psbt_input = &proposal.inputs[index];
+            if !is_finalized(psbt_input) {
+                return Err(PayjoinError::ReceiverInputNotFinalized(op));
+            }
+            if matches!(sender_sequence, Some(sequence) if sequence != txin.sequence) {
+                return Err(PayjoinError::MixedSequence(op));
+            }
+            let txout = psbt_prev_txout(&proposal, index).ok_or(PayjoinError::MissingUtxo(op))?;
+            if !sender_scripts
+                .iter()
+                .all(|script| same_script_type(script, &txout.script_pubkey))
+            {
+                return Err(PayjoinError::MixedInputScripts(op));
+            }
+            receiver_input_value += txout.value;
+            // The receiver pays for an input of our type, not for the weight it happened to use.
+            receiver_input_weight += Weight::from_wu(TXIN_BASE_WEIGHT + max_satisfaction_weight);
+        }
+
+        let output_value =
+            |tx: &bitcoin::Transaction| tx.output.iter().map(|txout| txout.value).sum::<Amount>();
+        let original_fee = original_input_value
+            .checked_sub(output_value(original_tx))
+            .unwrap_or(Amount::ZERO);
+        let original_feerate = original_fee
+            / self
+                .original
+                .clone()
+                .extract_tx_unchecked_fee_rate()
+                .weight();
+
+        // Outputs
+        let mut used = Vec::<usize>::new();
+        let mut contribution = Amount::ZERO;
+        for (index, txout) in original_tx.output.iter().enumerate() {
+            let found = proposal_tx
+                .output
+                .iter()
+                .enumerate()
+                .find(|(i, proposal_txout)| {
+                    !used.contains(i) && proposal_txout.script_pubkey == txout.script_pubkey
+                })
+                .map(|(i, proposal_txout)| (i, proposal_txout.value));
+            if txout.script_pubkey == self.params.payee {
+                match found {
+                    Some((i, value)) if value >= txout.value => used.push(i),
+                    _ if self.params.disable_output_substitution => {
+                        return Err(PayjoinError::PayeeOutputChanged)
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            let (i, value) = found.ok_or(PayjoinError::OutputChanged(index))?;
+            used.push(i);
+            if self.params.fee_output_index == Some(index) && value <= txout.value {
+                contribution = txout.value - value;
+            } else if value != txout.value {
+                return Err(PayjoinError::OutputChanged(index));
+            }
+        }
+        let max_contribution = original_feerate
+            .fee_vb(receiver_input_weight.to_vbytes_ceil())
+            .unwrap_or(Amount::MAX_MONEY)
+            .min(self.params.max_additional_fee_contribution);
+        if contribution > max_contribution {
+            return Err(PayjoinError::FeeContributionTooHigh {
+                contribution,
+                max: max_contribution,
+            });
+        }
+
+        // Fee
+        let fee = (original_input_value + receiver_input_value)
+            .checked_sub(output_value(proposal_tx))
+            .ok_or(PayjoinError::FeeDecreased)?;
+        let fee_increase = fee
+            .checked_sub(original_fee)
+            .ok_or(PayjoinError::FeeDecreased)?;
+        // Whatever the receiver takes from the fee output must go to fees (BIP-78).
+        if fee_increase < contribution {
+            return Err(PayjoinError::FeeContributionNotPaid {
+                contribution,
+                fee_increase,
+            });
+        }
+        let feerate = fee / self.predicted_weight(&proposal);
+        if feerate < self.params.min_feerate {
+            return Err(PayjoinError::FeerateTooLow {
+                feerate,
+                min_feerate: self.params.min_feerate,
+            });
+        }
+
+        // Restore our inputs.
+        for (txin, psbt_input) in proposal.unsigned_tx.input.iter().zip(&mut proposal.inputs) {
+            if let Some(unsigned_input) = self.unsigned_inputs.get(&txin.previous_output) {
+                *psbt_input = unsigned_input.clone();
+            }
+        }
+        Ok(proposal)
+    }
+
+    /// Predicted weight of the `proposal` once our inputs are satisfied.
+    fn predicted_weight(&self, proposal: &Psbt) -> Weight {
+        let mut tx = proposal.unsigned_tx.clone();
+        let mut weight = 0;
+        for (txin, psbt_input) in tx.input.iter_mut().zip(&proposal.inputs) {
+            match self.satisfaction_weights.get(&txin.previous_output) {
+                Some(satisfaction_weight) => weight += satisfaction_weight,
+                None => {
+                    txin.script_sig = psbt_input.final_script_sig.clone().unwrap_or_default();
+                    txin.witness = psbt_input.final_script_witness.clone().unwrap_or_default();
+                }
+            }
+        }
+        tx.weight() + Weight::from_wu(weight)
+    }
+}
+
+/// Occurs when the receiver cannot contribute to a payjoin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PayjoinReceiverError {
+    /// The original tx is not finalized.
+    OriginalNotFinalized,
+    /// The prev output of an input of the original tx is unknown.
+    MissingUtxo(OutPoint),
+    /// The original tx has no output to the payee.
+    MissingPayeeOutput,
+    /// None of the candidates can be added.
+    NoSuitableInput,
+}
+
+impl Display for PayjoinReceiverError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::OriginalNotFinalized => write!(f, "original tx is not finalized"),
+            Self::MissingUtxo(op) => write!(f, "prev output of input {} is unknown", op),
+            Self::MissingPayeeOutput => write!(f, "original tx does not pay the payee"),
+            Self::NoSuitableInput => write!(f, "no suitable input to contribute"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PayjoinReceiverError {}
+
+/// The receiver's payjoin proposal, see [`contribute_payjoin`].
+#[derive(Debug, Clone)]
+pub struct PayjoinContribution {
+    /// The proposal to sign and send back to the sender.
+    pub psbt: Psbt,
+    /// Our contributed inputs.
+    pub inputs: Vec<Input>,
+}
+
+impl PayjoinContribution {
+    /// Finalizer for our contributed inputs.
+    pub fn finalizer(&self) -> Finalizer {
+        Finalizer::new(self.inputs.iter().filter_map(|input| {
+            input
+                .plan()
+                .cloned()
+                .map(|plan| (input.prev_outpoint(), plan))
+        }))
+    }
+}
+
+/// Contribute our inputs to the sender's finalized `original` psbt (receiver side).
+///
+/// The `can_select` groups of `candidates` which consist of a single input of the same script
+/// type as the sender's inputs are selected in order with [`Selector::select_until_target_met`],
+/// until they pay for themselves at the feerate of the original tx. Inputs which are
+/// uneconomical at that feerate (see [`filter_uneconomical`]) are never contributed. Use
+/// [`InputCandidates::prioritize`] to choose which inputs are contributed first. Each input is
+/// added at a random position derived from `seed`.
+///
+/// Our payee output receives the value of the inputs, minus the fee for the inputs at the
+/// feerate of the original tx. The sender pays for as much of that fee as `params` allows.
+///
+/// The sender's inputs are cleared, as their signatures are no longer valid.
+pub fn contribute_payjoin(
+    original: &Psbt,
+    candidates: &InputCandidates,
+    params: &PayjoinParams,
+    seed: u64,
+) -> Result<PayjoinContribution, PayjoinReceiverError> {
+    if !original.inputs.iter().all(is_finalized) {
+        return Err(PayjoinReceiverError::OriginalNotFinalized);
+    }
+    let mut original_input_value = Amount::ZERO;
+    let mut sender_scripts = Vec::<ScriptBuf>::new();
+    for (index, txin) in original.unsigned_tx.input.iter().enumerate() {
+        let txout = psbt_prev_txout(original, index)
+            .ok_or(PayjoinReceiverError::MissingUtxo(txin.previous_output))?;
+        original_input_value += txout.value;
+        sender_scripts.push(txout.script_pubkey);
+    }
+    let payee_index = original
+        .unsigned_tx
+        .output
+        .iter()
+        .position(|txout| txout.script_pubkey == params.payee)
+        .ok_or(PayjoinReceiverError::MissingPayeeOutput)?;
+
+    // Keep the feerate of the original tx.
+    let original_tx = original.clone().extract_tx_unchecked_fee_rate();
+    let original_fee = original_input_value
+        .checked_sub(original_tx.output.iter().map(|txout| txout.value).sum())
+        .unwrap_or(Amount::ZERO);
+    let feerate = original_fee / original_tx.weight();
+
+    let candidates = InputCandidates::new(
+        [],
+        candidates
+            .can_select()
+            .iter()
+            .filter(|group| group.input_count() == 1)
+            .flat_map(|group| group.inputs())
+            .filter(|input| {
+                let script = &input.prev_txout().script_pubkey;
+                !original
+                    .unsigned_tx
+                    .input
+                    .iter()
+                    .any(|txin| txin.previous_output == input.prev_outpoint())
+                    && sender_scripts
+                        .iter()
+                        .all(|sender_script| same_script_type(sender_script, script))
+            })
+            .cloned(),
+    )
+    .filter_groups(filter_uneconomical(feerate));
+    // Our payee output takes the place of the change output.
+    let payee_weight = original.unsigned_tx.output[payee_index].weight().to_wu();
+    let selector_params = SelectorParams::new(
+        feerate,
+        Vec::new(),
+        ScriptSource::from_script(params.payee.clone()),
+        ChangePolicyType::NoDust,
+        DrainWeights {
+            output_weight: payee_weight,
+            spend_weight: 0,
+            n_outputs: 1,
+        },
+    );
+    let mut selector = Selector::new(&candidates, selector_params)
+        .map_err(|_| PayjoinReceiverError::NoSuitableInput)?;
+    selector
+        .select_until_target_met()
+        .map_err(|_| PayjoinReceiverError::NoSuitableInput)?;
+    let inputs = selector
+        .inner()
+        .selected_indices()
+        .iter()
+        .flat_map(|&index| candidates.can_select()[index].inputs())
+        .cloned()
+        .collect::<Vec<_>>();
+
+    // We only pay for our inputs, the rest of the tx is paid for by the sender.
+    let input_weight = Weight::from_wu(
+        inputs
+            .iter()
+            .map(|input| TXIN_BASE_WEIGHT + input.satisfaction_weight())
+            .sum(),
+    );
+    let fee = feerate
+        .fee_vb(input_weight.to_vbytes_ceil())
+        .unwrap_or(Amount::MAX_MONEY);
+
+    let mut psbt = original.clone();
+    let mut sender_fee = Amount::ZERO;
+    if let Some(txout) = params
+        .fee_output_index
+        .and_then(|index| psbt.unsigned_tx.output.get_mut(index))
+    {
+        let available = txout
+            .value
+            .checked_sub(txout.script_pubkey.minimal_non_dust())
+            .unwrap_or(Amount::ZERO);
+        sender_fee = fee
+            .min(params.max_additional_fee_contribution)
+            .min(available);
+        txout.value -= sender_fee;
+    }
+    let input_value = inputs.iter().map(|input| input.prev_txout().value).sum();
+    let payee = &mut psbt.unsigned_tx.output[payee_index];
+    payee.value = (payee.value + input_value)
+        .checked_sub(fee - sender_fee)
+        .ok_or(PayjoinReceiverError::NoSuitableInput)?;
+
+    for psbt_input in &mut psbt.inputs {
+        *psbt_input = psbt::Input::default();
+    }
+    let sequence = psbt
+        .unsigned_tx
+        .input
+        .first()
+        .map(|txin| txin.sequence)
+        .unwrap_or_default();
+    for (i, input) in inputs.iter().enumerate() {
+        let position = crate::selection::random_index(
+            seed.wrapping_add(i as u64),
+            psbt.unsigned_tx.input.len() + 1,
+        );
+        psbt.unsigned_tx.input.insert(
+            position,
+            bitcoin::TxIn {
+                previous_output: input.prev_outpoint(),
+                sequence,
+                ..Default::default()
+            },
+        );
+        psbt.inputs.insert(position, unsigned_psbt_input(input));
+    }
+
+    Ok(PayjoinContribution { psbt, inputs })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::test_utils::Wallet;
+    use crate::{Output, PsbtParams};
+
+    struct Setup {
+        sender: Wallet,
+        receiver: Wallet,
+        selection: Selection,
+        original: Psbt,
+        params: PayjoinParams,
+    }
+
+    fn setup() -> Setup {
+        let sender = Wallet::new(bdk_testenv::utils::DESCRIPTORS[2]);
+        let receiver = Wallet::new(bdk_testenv::utils::DESCRIPTORS[5]);
+        let payee = receiver.at(1).script_pubkey();
+        let selection = InputCandidates::new([], [sender.input(1, 100_000)])
+            .into_selection(
+                |s: &mut Selector| s.select_until_target_met(),
+                SelectorParams::new(
+                    FeeRate::from_sat_per_vb_u32(2),
+                    vec![Output::with_script(payee.clone(), Amount::from_sat(50_000))],
+                    ScriptSource::from_descriptor(sender.at(1)),
+                    ChangePolicyType::NoDust,
+                    DrainWeights::TR_KEYSPEND,
+                ),
+            )
+            .unwrap();
+        let mut original = selection.create_psbt(PsbtParams::default()).unwrap();
+        sender.sign(&mut original);
+        assert!(selection
+            .clone()
+            .into_finalizer()
+            .finalize(&mut original)
+            .is_finalized());
+        let fee_output_index = original
+            .unsigned_tx
+            .output
+            .iter()
+            .position(|txout| txout.script_pubkey == sender.at(1).script_pubkey());
+        let params = PayjoinParams {
+            fee_output_index,
+            max_additional_fee_contribution: Amount::from_sat(1_000),
+            min_feerate: FeeRate::from_sat_per_vb_u32(1),
+            ..PayjoinParams::new(payee)
+        };
+        Setup {
+            sender,
+            receiver,
+            selection,
+            original,
+            params,
+        }
+    }
+
+    /// The receiver's contribution to the finalized original tx.
+    fn propose(setup: &Setup) -> Psbt {
+        let candidates = InputCandidates::new([], [setup.receiver.input(2, 80_000)]);
+        let mut contribution =
+            contribute_payjoin(&setup.original, &candidates, &setup.params, 7).unwrap();
+        setup.receiver.sign(&mut contribution.psbt);
+        contribution.finalizer().finalize(&mut contribution.psbt);
+        contribution.psbt
+    }
+
+    #[test]
+    fn payjoin_round_trip() {
+        let setup = setup();
+        let proposal = propose(&setup);
+        assert_eq!(proposal.unsigned_tx.input.len(), 2);
+
+        let sender = PayjoinSender::new(&setup.selection, setup.original.clone(), setup.params);
+        let mut psbt = sender.process_proposal(proposal).unwrap();
+        setup.sender.sign(&mut psbt);
+        assert!(setup
+            .selection
+            .clone()
+            .into_finalizer()
+            .finalize(&mut psbt)
+            .is_finalized());
+
+        let tx = psbt.extract_tx().unwrap();
+        let payee = tx
+            .output
+            .iter()
+            .find(|txout| txout.script_pubkey == sender.params().payee)
+            .unwrap();
+        assert!(payee.value > Amount::from_sat(129_000));
+        assert!(tx.input.iter().all(|txin| !txin.witness.is_empty()));
+        assert!(tx
+            .input
+            .iter()
+            .all(|txin| txin.sequence == tx.input[0].sequence));
+    }
+
+    #[test]
+    fn payjoin_sender_rejects_tampering() {
+        let setup = setup();
+        let sender = PayjoinSender::new(
+            &setup.selection,
+            setup.original.clone(),
+            setup.params.clone(),
+        );
+        let fee_output_index = setup.params.fee_output_index.unwrap();
+
+        let mut proposal = propose(&setup);
+        let change = proposal
+            .unsigned_tx
+            .output
+            .iter_mut()
+            .find(|txout| {
+                txout.script_pubkey
+                    == setup.original.unsigned_tx.output[fee_output_index].script_pubkey
+            })
+            .unwrap();
+        change.value -= Amount::from_sat(5_000);
+        assert!(matches!(
+            sender.process_proposal(proposal),
+            Err(PayjoinError::FeeContributionTooHigh { .. })
+        ));
+
+        let mut proposal = propose(&setup);
+        let index = proposal
+            .unsigned_tx
+            .input
+            .iter()
+            .position(|txin| txin.previous_output == setup.selection.inputs[0].prev_outpoint())
+            .unwrap();
+        proposal.unsigned_tx.input.remove(index);
+        proposal.inputs.remove(index);
+        assert_eq!(
+            sender.process_proposal(proposal).unwrap_err(),
+            PayjoinError::MissingInput(setup.selection.inputs[0].prev_outpoint())
+        );
+
+        let mut proposal = propose(&setup);
+        let index = proposal
+            .unsigned_tx
+            .input
+            .iter()
+            .position(|txin| txin.previous_output != setup.selection.inputs[0].prev_outpoint())
+            .unwrap();
+        let receiver_op = proposal.unsigned_tx.input[index].previous_output;
+        proposal.unsigned_tx.input[index].sequence = bitcoin::Sequence::MAX;
+        assert_eq!(
+            sender.process_proposal(proposal).unwrap_err(),
+            PayjoinError::MixedSequence(receiver_op)
+        );
+
+        let mut params = setup.params.clone();
+        params.disable_output_substitution = true;
+        let sender = PayjoinSender::new(&setup.selection, setup.original.clone(), params);
+        let mut proposal = propose(&setup);
+        for txout in &mut proposal.unsigned_tx.output {
+            if txout.script_pubkey == setup.params.payee {
+                txout.script_pubkey = setup.receiver.at(2).script_pubkey();
+            }
+        }
+        assert_eq!(
+            sender.process_proposal(proposal).unwrap_err(),
+            PayjoinError::PayeeOutputChanged
+        );
+    }
+
+    #[test]
+    fn payjoin_fee_contribution_is_capped_by_receiver_inputs() {
+        let mut setup = setup();
+        setup.params.max_additional_fee_contribution = Amount::from_sat(10_000);
+        let sender = PayjoinSender::new(
+            &setup.selection,
+            setup.original.clone(),
+            setup.params.clone(),
+        );
+        let fee_output_index = setup.params.fee_output_index.unwrap();
+        let change_spk = &setup.original.unsigned_tx.output[fee_output_index].script_pubkey;
+
+        // the receiver takes the fee of its input at the original feerate
+        let proposal = propose(&setup);
+        let original_change = setup.original.unsigned_tx.output[fee_output_index].value;
+        let change = proposal
+            .unsigned_tx
+            .output
+            .iter()
+            .find(|txout| &txout.script_pubkey == change_spk)
+            .unwrap()
+            .value;
+        let contribution = original_change - change;
+        assert!(contribution > Amount::ZERO);
+        sender.process_proposal(proposal).unwrap();
+
+        // taking more is rejected although it is below max_additional_fee_contribution
+        let mut proposal = propose(&setup);
+        for txout in &mut proposal.unsigned_tx.output {
+            if &txout.script_pubkey == change_spk {
+                txout.value -= Amount::from_sat(500);
+            }
+        }
+        assert!(matches!(
+            sender.process_proposal(proposal),
+            Err(PayjoinError::FeeContributionTooHigh { contribution: c, max })
+                if c == contribution + Amount::from_sat(500)
+                    && max < setup.params.max_additional_fee_contribution
+        ));
+    }
+
+    #[test]
+    fn payjoin_sender_rejects_contribution_moved_to_payee() {
+        let setup = setup();
+        let sender = PayjoinSender::new(
+            &setup.selection,
+            setup.original.clone(),
+            setup.params.clone(),
+        );
+        let fee_output_index = setup.params.fee_output_index.unwrap();
+        let change_spk = &setup.original.unsigned_tx.output[fee_output_index].script_pubkey;
+
+        let mut proposal = propose(&setup);
+        let original_change = setup.original.unsigned_tx.output[fee_output_index].value;
+        let change = proposal
+            .unsigned_tx
+            .output
+            .iter()
+            .find(|txout| &txout.script_pubkey == change_spk)
+            .unwrap()
+            .value;
+        let contribution = original_change - change;
+        assert!(contribution > Amount::ZERO);
+        // the receiver pays the contribution to itself instead of to fees
+        for txout in &mut proposal.unsigned_tx.output {
+            if txout.script_pubkey == setup.params.payee {
+                txout.value += contribution;
+            }
+        }
+        assert_eq!(
+            sender.process_proposal(proposal).unwrap_err(),
+            PayjoinError::FeeContributionNotPaid {
+                contribution,
+                fee_increase: Amount::ZERO,
+            }
+        );
+    }
+
+    #[test]
+    fn payjoin_receiver_selects_inputs_in_order() {
+        let setup = setup();
+        let small = setup.receiver.input(3, 100);
+        let medium = setup.receiver.input(6, 20_000);
+        let large = setup.receiver.input(4, 80_000);
+        let mixed = Wallet::new(bdk_testenv::utils::DESCRIPTORS[0]).input(5, 90_000);
+        let contributed = |candidates: InputCandidates| {
+            contribute_payjoin(&setup.original, &candidates, &setup.params, 7)
+                .unwrap()
+                .inputs
+                .iter()
+                .map(Input::prev_outpoint)
+                .collect::<Vec<_>>()
+        };
+
+        // inputs of another script type are not contributed
+        assert!(!setup.sender.at(0).script_pubkey().is_p2tr());
+        assert!(mixed.prev_txout().script_pubkey.is_p2tr());
+        // the small input is uneconomical at the original feerate
+        let candidates = InputCandidates::new(
+            [],
+            [mixed.clone(), small.clone(), medium.clone(), large.clone()],
+        );
+        assert_eq!(
+            contributed(candidates.clone()),
+            vec![medium.prev_outpoint()]
+        );
+        let candidates =
+            candidates.prioritize(|input| input.prev_outpoint() == large.prev_outpoint());
+        assert_eq!(contributed(candidates), vec![large.prev_outpoint()]);
+
+        assert_eq!(
+            contribute_payjoin(
+                &setup.original,
+                &InputCandidates::new([], [mixed]),
+                &setup.params,
+                7
+            )
+            .unwrap_err(),
+            PayjoinReceiverError::NoSuitableInput
+        );
+    }
+}
@@ -1,16 +1,269 @@
 use alloc::sync::Arc;
+use core::cmp::Ordering;
 use core::fmt::Display;
 
-use bitcoin::{absolute, Amount, OutPoint, Transaction, TxOut, Txid};
+use alloc::vec::Vec;
+use bdk_coin_select::DrainWeights;
+use bitcoin::{absolute, Amount, FeeRate, OutPoint, Script, Transaction, TxOut, Txid, Weight};
 use miniscript::bitcoin;
 
 use crate::collections::{HashMap, HashSet};
-use crate::{CanonicalUnspents, Input, RbfParams};
+use crate::{
+    CanonicalUnspents, ChangePolicyType, Input, Output, RbfParams, ScriptSource, SelectorParams,
+};
 
 /// Set of txs to replace.
 pub struct RbfSet {
     txs: HashMap<Txid, Arc<Transaction>>,
     prev_txouts: HashMap<OutPoint, TxOut>,
+    descendants: HashMap<Txid, (Arc<Transaction>, Amount)>,
+}
+
+/// Maximum number of txs that a replacement may evict from the mempool (rule 5).
+pub const MAX_REPLACEMENT_EVICTIONS: usize = 100;
+
+/// A violated replacement rule, see [`RbfSet::validate_replacement`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RbfViolation {
+    /// The replacement does not spend any input of the original tx, so it does not replace it.
+    NotConflicting(Txid),
+    /// The replacement spends an output of a tx that it evicts.
+    SpendsEvictedTx(OutPoint),
+    /// The replacement spends an unconfirmed output that was not spent by the original txs
+    /// (rule 2).
+    NewUnconfirmedInput(OutPoint),
+    /// The replacement pays less absolute fee than the evicted txs (rule 3).
+    InsufficientFee {
+        /// Fee of the replacement.
+        fee: Amount,
+        /// Total fee of the evicted txs.
+        required: Amount,
+    },
+    /// The additional fee does not pay for the replacement's bandwidth at the incremental relay
+    /// feerate (rule 4).
+    InsufficientIncrementalFee {
+        /// Fee of the replacement minus the fee of the evicted txs.
+        additional_fee: Amount,
+        /// Fee of the replacement at the incremental relay feerate.
+        required: Amount,
+    },
+    /// The replacement evicts too many txs (rule 5).
+    TooManyEvictions {
+        /// Number of evicted txs, including descendants.
+        count: usize,
+        /// Maximum number of evicted txs.
+        max: usize,
+    },
+    /// The replacement does not improve the feerate diagram of the mempool.
+    WorseFeerateDiagram,
+}
+
+impl Display for RbfViolation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NotConflicting(txid) => {
+                write!(f, "replacement does not conflict with original tx {}", txid)
+            }
+            Self::SpendsEvictedTx(op) => {
+                write!(f, "replacement spends output {} of an evicted tx", op)
+            }
+            Self::NewUnconfirmedInput(op) => {
+                write!(f, "replacement spends new unconfirmed output {}", op)
+            }
+            Self::InsufficientFee { fee, required } => write!(
+                f,
+                "replacement fee {} is less than the evicted fee {}",
+                fee, required
+            ),
+            Self::InsufficientIncrementalFee {
+                additional_fee,
+                required,
+            } => write!(
+                f,
+                "replacement pays {} additional fee, {} required",
+                additional_fee, required
+            ),
+            Self::TooManyEvictions { count, max } => write!(
+                f,
+                "replacement evicts {} txs, more than the maximum of {}",
+                count, max
+            ),
+            Self::WorseFeerateDiagram => {
+                write!(f, "replacement does not improve the feerate diagram")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RbfViolation {}
+
+/// Outcome of each replacement rule, see [`RbfSet::validate_replacement`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplacementReport {
+    /// The replacement conflicts with every original tx and does not spend evicted txs.
+    pub conflicts: Result<(), RbfViolation>,
+    /// Rule 2: no new unconfirmed inputs.
+    pub new_unconfirmed_inputs: Result<(), RbfViolation>,
+    /// Rule 3: pays at least the absolute fee of the evicted txs.
+    pub absolute_fee: Result<(), RbfViolation>,
+    /// Rule 4: pays for its own bandwidth at the incremental relay feerate.
+    pub incremental_fee: Result<(), RbfViolation>,
+    /// Rule 5: evicts at most [`MAX_REPLACEMENT_EVICTIONS`] txs.
+    pub evictions: Result<(), RbfViolation>,
+    /// The feerate diagram of the replacement is strictly better than that of the evicted txs.
+    pub feerate_diagram: Result<(), RbfViolation>,
+}
+
+impl ReplacementReport {
+    /// Iterate over the violated rules.
+    pub fn violations(&self) -> impl Iterator<Item = &RbfViolation> + '_ {
+        [
+            &self.conflicts,
+            &self.new_unconfirmed_inputs,
+            &self.absolute_fee,
+            &self.incremental_fee,
+            &self.evictions,
+            &self.feerate_diagram,
+        ]
+        .into_iter()
+        .filter_map(|res| res.as_ref().err())
+    }
+
+    /// Whether the replacement passes every rule.
+    pub fn is_valid(&self) -> bool {
+        self.violations().next().is_none()
+    }
+}
+
+/// Error when attempting to [`validate_replacement`](RbfSet::validate_replacement).
+#[derive(Debug)]
+pub enum ValidateReplacementError {
+    /// Previous output of the replacement not found
+    PreviousOutputNotFound(OutPoint),
+}
+
+impl Display for ValidateReplacementError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::PreviousOutputNotFound(op) => write!(f, "previous output not found: {}", op),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ValidateReplacementError {}
+
+/// A chunk of a feerate diagram.
+#[derive(Debug, Clone, Copy)]
+struct Chunk {
+    weight: u64,
+    fee: u64,
+}
+
+impl Chunk {
+    fn merge(self, other: Chunk) -> Chunk {
+        Chunk {
+            weight: self.weight + other.weight,
+            fee: self.fee + other.fee,
+        }
+    }
+
+    fn cmp_feerate(&self, other: &Chunk) -> Ordering {
+        (self.fee as u128 * other.weight as u128).cmp(&(other.fee as u128 * self.weight as u128))
+    }
+}
+
+/// Linearize `txs` by picking the ancestor set with the highest feerate first, then merge the
+/// result into chunks of decreasing feerate.
+fn feerate_diagram(txs: &HashMap<Txid, (Arc<Transaction>, Amount)>) -> Vec<Chunk> {
+    let mut remaining = txs.keys().copied().collect::<HashSet<Txid>>();
+    let mut chunks = Vec::<Chunk>::new();
+    while !remaining.is_empty() {
+        let (ancestors, mut chunk) = remaining
+            .iter()
+            .map(|&txid| {
+                let mut ancestors = HashSet::<Txid>::new();
+                let mut stack = vec![txid];
+                while let Some(txid) = stack.pop() {
+                    if remaining.contains(&txid) && ancestors.insert(txid) {
+                        let tx = &txs[&txid].0;
+                        stack.extend(tx.input.iter().map(|txin| txin.previous_output.txid));
+                    }
+                }
+                let chunk = ancestors
+                    .iter()
+                    .map(|txid| Chunk {
+                        weight: txs[txid].0.weight().to_wu(),
+                        fee: txs[txid].1.to_sat(),
+                    })
+                    .fold(Chunk { weight: 0, fee: 0 }, Chunk::merge);
+                (ancestors, chunk)
+            })
+            .max_by(|(_, a), (_, b)| a.cmp_feerate(b))
+            .expect("remaining must not be empty");
+        for txid in ancestors {
+            remaining.remove(&txid);
+        }
+        while let Some(last) = chunks.last() {
+            if last.cmp_feerate(&chunk) != Ordering::Less {
+                break;
+            }
+            chunk = chunk.merge(chunks.pop().expect("must have last"));
+        }
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+/// Fee of the `diagram` at `weight` as a fraction.
+fn diagram_fee_at(diagram: &[Chunk], weight: u64) -> (u128, u128) {
+    let mut acc = Chunk { weight: 0, fee: 0 };
+    for chunk in diagram {
+        if weight < acc.weight + chunk.weight {
+            let num = acc.fee as u128 * chunk.weight as u128
+                + chunk.fee as u128 * (weight - acc.weight) as u128;
+            return (num, chunk.weight as u128);
+        }
+        acc = acc.merge(*chunk);
+    }
+    (acc.fee as u128, 1)
+}
+
+/// Compare the fee of two feerate diagrams at every point.
+///
+/// Returns `None` if the diagrams are incomparable, i.e. each is better somewhere.
+fn cmp_diagrams(a: &[Chunk], b: &[Chunk]) -> Option<Ordering> {
+    let breakpoints = |diagram: &[Chunk]| {
+        diagram
+            .iter()
+            .scan(0_u64, |weight, chunk| {
+                *weight += chunk.weight;
+                Some(*weight)
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut ord = Ordering::Equal;
+    for weight in breakpoints(a).into_iter().chain(breakpoints(b)) {
+        let (a_num, a_den) = diagram_fee_at(a, weight);
+        let (b_num, b_den) = diagram_fee_at(b, weight);
+        match ((a_num * b_den).cmp(&(b_num * a_den)), ord) {
+            (Ordering::Equal, _) => {}
+            (this, Ordering::Equal) => ord = this,
+            (this, ord) if this != ord => return None,
+            _ => {}
+        }
+    }
+    Some(ord)
+}
+
+/// Outputs of the original txs, split into recipients and change.
+#[derive(Debug, Clone)]
+pub struct OriginalOutputs {
+    /// Outputs paying to recipients. These must be kept by a fee-bumping replacement.
+    pub recipients: Vec<Output>,
+    /// Our change outputs. These can be reduced or dropped by a fee-bumping replacement.
+    pub change: Vec<TxOut>,
 }
 
 /// Occurs when the given original tx has no input spend that is still available for spending.
@@ -40,6 +293,7 @@ impl RbfSet {
     ///
     /// Do not include transactions in `txs` that are descendants of transactions that are already
     /// in `txs`.
+    /// Record those with [`with_descendants`](Self::with_descendants) instead.
     pub fn new<T, O>(txs: T, prev_txouts: O) -> Option<Self>
     where
         T: IntoIterator,
@@ -47,6 +301,7 @@ impl RbfSet {
         O: IntoIterator<Item = (OutPoint, TxOut)>,
     {
         let set = Self {
+            descendants: HashMap::new(),
             txs: txs
                 .into_iter()
                 .map(|tx| {
@@ -68,6 +323,28 @@ impl RbfSet {
         }
     }
 
+    /// Record the unconfirmed descendants of the original txs along with their fees.
+    ///
+    /// Descendants are evicted alongside the original txs, so the replacement must also pay for
+    /// them. [`CanonicalUnspents::extract_replacements`] records them automatically.
+    pub fn with_descendants<D, T>(mut self, descendants: D) -> Self
+    where
+        D: IntoIterator<Item = (T, Amount)>,
+        T: Into<Arc<Transaction>>,
+    {
+        self.descendants
+            .extend(descendants.into_iter().map(|(tx, fee)| {
+                let tx: Arc<Transaction> = tx.into();
+                (tx.compute_txid(), (tx, fee))
+            }));
+        self
+    }
+
+    /// Txids of the unconfirmed descendants of the original txs.
+    pub fn descendants(&self) -> impl ExactSizeIterator<Item = Txid> + '_ {
+        self.descendants.keys().copied()
+    }
+
     /// Txids of the original txs that are to be replaced.
     pub fn txids(&self) -> impl ExactSizeIterator<Item = Txid> + '_ {
         self.txs.keys().copied()
@@ -134,6 +411,242 @@ impl RbfSet {
         Ok(must_select)
     }
 
+    /// Tries to find all inputs of the original txs that are still available.
+    ///
+    /// Like [`must_select_largest_input_of_each_original_tx`], the returned outpoints can be used
+    /// to create the `must_select` inputs. Reusing all original inputs means the replacement only
+    /// adds new inputs when the original inputs cannot pay for the higher fee.
+    ///
+    /// [`must_select_largest_input_of_each_original_tx`]:
+    /// Self::must_select_largest_input_of_each_original_tx
+    pub fn must_select_all_inputs(&self, canon_utxos: &CanonicalUnspents) -> HashSet<OutPoint> {
+        self.txs
+            .values()
+            .flat_map(|tx| tx.input.iter().map(|txin| txin.previous_output))
+            .filter(|spend| !self.txs.contains_key(&spend.txid))
+            .filter(|&spend| canon_utxos.is_unspent(spend))
+            .collect()
+    }
+
+    /// Split the outputs of the original txs into recipients and change.
+    ///
+    /// `is_change` should return `true` for scripts derived from our change descriptor.
+    pub fn original_outputs<F>(&self, mut is_change: F) -> OriginalOutputs
+    where
+        F: FnMut(&Script) -> bool,
+    {
+        let mut txs = self.txs.iter().collect::<Vec<_>>();
+        txs.sort_by_key(|(txid, _)| **txid);
+        let mut recipients = Vec::new();
+        let mut change = Vec::new();
+        for txout in txs.into_iter().flat_map(|(_, tx)| &tx.output) {
+            if is_change(&txout.script_pubkey) {
+                change.push(txout.clone());
+            } else {
+                recipients.push(Output::with_script(
+                    txout.script_pubkey.clone(),
+                    txout.value,
+                ));
+            }
+        }
+        OriginalOutputs { recipients, change }
+    }
+
+    /// Payments of our own descendant txs which are dropped by the replacement.
+    ///
+    /// A descendant is ours if it spends an output of the original txs (or of another descendant)
+    /// for which `is_mine` returns `true`. Its outputs for which `is_mine` returns `false` are
+    /// payments that get evicted with it, add them to the target outputs of the replacement to
+    /// re-include them.
+    pub fn dropped_payments<F>(&self, mut is_mine: F) -> Vec<Output>
+    where
+        F: FnMut(&Script) -> bool,
+    {
+        let evicted_txout = |op: OutPoint| {
+            self.txs
+                .get(&op.txid)
+                .or_else(|| self.descendants.get(&op.txid).map(|(tx, _)| tx))
+                .and_then(|tx| tx.output.get(op.vout as usize))
+        };
+        let mut descendants = self.descendants.iter().collect::<Vec<_>>();
+        descendants.sort_by_key(|(txid, _)| **txid);
+        let mut dropped = Vec::new();
+        for (_, (tx, _)) in descendants {
+            let is_ours = tx.input.iter().any(|txin| {
+                evicted_txout(txin.previous_output)
+                    .map_or(false, |txout| is_mine(&txout.script_pubkey))
+            });
+            if !is_ours {
+                continue;
+            }
+            for txout in &tx.output {
+                if !is_mine(&txout.script_pubkey) {
+                    dropped.push(Output::with_script(
+                        txout.script_pubkey.clone(),
+                        txout.value,
+                    ));
+                }
+            }
+        }
+        dropped
+    }
+
+    /// Selector params to bump the fee of the original txs while keeping every recipient.
+    ///
+    /// The original change outputs are dropped and the excess is sent to `change_script` instead,
+    /// so the change is reduced or removed to pay for the higher fee. Pass the original change
+    /// descriptor as `change_script` to reuse the original change script.
+    ///
+    /// Use this together with [`must_select_all_inputs`](Self::must_select_all_inputs) so that
+    /// new inputs are only added when needed.
+    ///
+    /// Payments of our descendant txs are not included, see
+    /// [`dropped_payments`](Self::dropped_payments).
+    pub fn bump_fee_params<F>(
+        &self,
+        target_feerate: FeeRate,
+        is_change: F,
+        change_script: ScriptSource,
+        change_policy: ChangePolicyType,
+        change_weight: DrainWeights,
+    ) -> SelectorParams
+    where
+        F: FnMut(&Script) -> bool,
+    {
+        let OriginalOutputs { recipients, .. } = self.original_outputs(is_change);
+        let mut params = SelectorParams::new(
+            target_feerate,
+            recipients,
+            change_script,
+            change_policy,
+            change_weight,
+        );
+        params.replace = Some(self.selector_rbf_params());
+        params
+    }
+
+    /// Check `replacement` against Bitcoin Core's replacement rules.
+    ///
+    /// `weight` is the weight of `replacement` once it is signed, e.g.
+    /// [`Selection::predicted_weight`](crate::Selection::predicted_weight). The incremental fee
+    /// (rule 4) and the feerate diagram depend on it, so do not pass the weight of an unsigned
+    /// tx. If `replacement` is already signed, pass `replacement.weight()`.
+    ///
+    /// `canon_utxos` must be the canonical unspents that this set was extracted from. It is used
+    /// to look up the prev outputs of new inputs and whether they are confirmed.
+    ///
+    /// The feerate diagram check only considers the evicted txs and the replacement itself. The
+    /// unconfirmed ancestors of either are not taken into account.
+    pub fn validate_replacement(
+        &self,
+        replacement: &Transaction,
+        weight: Weight,
+        canon_utxos: &CanonicalUnspents,
+    ) -> Result<ReplacementReport, ValidateReplacementError> {
+        let evicted = self
+            .txs
+            .iter()
+            .map(|(txid, tx)| (*txid, (tx.clone(), self._fee(tx))))
+            .chain(
+                self.descendants
+                    .iter()
+                    .map(|(txid, desc)| (*txid, desc.clone())),
+            )
+            .collect::<HashMap<_, _>>();
+        let spends = replacement
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect::<Vec<_>>();
+        let original_spends = self
+            .txs
+            .values()
+            .flat_map(|tx| tx.input.iter().map(|txin| txin.previous_output))
+            .collect::<HashSet<OutPoint>>();
+
+        let mut input_sum = Amount::ZERO;
+        for &op in &spends {
+            let txout = self
+                .prev_txouts
+                .get(&op)
+                .or_else(|| canon_utxos.get_txout(op))
+                .or_else(|| {
+                    let (tx, _) = evicted.get(&op.txid)?;
+                    tx.output.get(op.vout as usize)
+                })
+                .ok_or(ValidateReplacementError::PreviousOutputNotFound(op))?;
+            input_sum += txout.value;
+        }
+        let output_sum = replacement.output.iter().map(|txout| txout.value).sum();
+        let fee = input_sum.checked_sub(output_sum).unwrap_or(Amount::ZERO);
+        let evicted_fee = evicted.values().map(|(_, fee)| *fee).sum::<Amount>();
+
+        let conflicts = match self.txs.iter().find(|(_, tx)| {
+            !tx.input
+                .iter()
+                .any(|txin| spends.contains(&txin.previous_output))
+        }) {
+            Some((txid, _)) => Err(RbfViolation::NotConflicting(*txid)),
+            None => match spends.iter().find(|op| evicted.contains_key(&op.txid)) {
+                Some(op) => Err(RbfViolation::SpendsEvictedTx(*op)),
+                None => Ok(()),
+            },
+        };
+        let new_unconfirmed_inputs = match spends
+            .iter()
+            .find(|op| !original_spends.contains(op) && canon_utxos.is_unconfirmed(op.txid))
+        {
+            Some(op) => Err(RbfViolation::NewUnconfirmedInput(*op)),
+            None => Ok(()),
+        };
+        let absolute_fee = if fee >= evicted_fee {
+            Ok(())
+        } else {
+            Err(RbfViolation::InsufficientFee {
+                fee,
+                required: evicted_fee,
+            })
+        };
+        let incremental_relay_feerate = self.selector_rbf_params().incremental_relay_feerate;
+        let required = incremental_relay_feerate
+            .fee_vb(weight.to_vbytes_ceil())
+            .unwrap_or(Amount::MAX_MONEY);
+        let additional_fee = fee.checked_sub(evicted_fee).unwrap_or(Amount::ZERO);
+        let incremental_fee = if additional_fee >= required {
+            Ok(())
+        } else {
+            Err(RbfViolation::InsufficientIncrementalFee {
+                additional_fee,
+                required,
+            })
+        };
+        let evictions = if evicted.len() <= MAX_REPLACEMENT_EVICTIONS {
+            Ok(())
+        } else {
+            Err(RbfViolation::TooManyEvictions {
+                count: evicted.len(),
+                max: MAX_REPLACEMENT_EVICTIONS,
+            })
+        };
+        let replacement_diagram = [Chunk {
+            weight: weight.to_wu(),
+            fee: fee.to_sat(),
+        }];
+        let feerate_diagram = match cmp_diagrams(&replacement_diagram, &feerate_diagram(&evicted)) {
+            Some(Ordering::Greater) => Ok(()),
+            _ => Err(RbfViolation::WorseFeerateDiagram),
+        };
+
+        Ok(ReplacementReport {
+            conflicts,
+            new_unconfirmed_inputs,
+            absolute_fee,
+            incremental_fee,
+            evictions,
+            feerate_diagram,
+        })
+    }
+
     fn _fee(&self, tx: &Transaction) -> Amount {
         let output_sum: Amount = tx.output.iter().map(|txout| txout.value).sum();
         let input_sum: Amount = tx
@@ -151,7 +664,316 @@ impl RbfSet {
     }
 
     /// Coin selector RBF parameters.
+    ///
+    /// This includes the fees and weights of the [`descendants`](Self::descendants).
     pub fn selector_rbf_params(&self) -> RbfParams {
-        RbfParams::new(self.txs.values().map(|tx| (tx.as_ref(), self._fee(tx))))
+        RbfParams::new(self.txs.values().map(|tx| (tx.as_ref(), self._fee(tx)))).with_descendants(
+            self.descendants
+                .values()
+                .map(|(tx, fee)| (tx.as_ref(), *fee)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::{ScriptBuf, WPubkeyHash};
+
+    use crate::test_utils::{confirmed, descriptor, plan, tx};
+    use crate::{InputCandidates, Selection, Selector};
+
+    fn recipient() -> ScriptBuf {
+        ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros())
+    }
+
+    /// Bump an original tx which spends `original_input` and pays 60_000 sats to a recipient and
+    /// `original_change` back to us. A confirmed utxo of 100_000 sats is also available.
+    fn bump(original_input: u64, original_change: u64, feerate: u32) -> Selection {
+        let ours = descriptor().script_pubkey();
+        let funding = tx(
+            &[OutPoint::new(Txid::from_byte_array([1; 32]), 0)],
+            &[(ours.clone(), original_input), (ours.clone(), 100_000)],
+        );
+        let original = tx(
+            &[OutPoint::new(funding.compute_txid(), 0)],
+            &[(recipient(), 60_000), (ours.clone(), original_change)],
+        );
+        let status = confirmed();
+        let mut canon =
+            CanonicalUnspents::new([(funding.clone(), status), (original.clone(), None)]);
+        let rbf_set = canon
+            .extract_replacements([original.compute_txid()])
+            .unwrap();
+
+        let original_outputs = rbf_set.original_outputs(|spk| spk == ours.as_script());
+        assert_eq!(original_outputs.recipients.len(), 1);
+        assert_eq!(original_outputs.change.len(), 1);
+
+        let plan = plan();
+        let must_select = rbf_set.must_select_all_inputs(&canon);
+        assert_eq!(
+            must_select.iter().copied().collect::<Vec<_>>(),
+            [OutPoint::new(funding.compute_txid(), 0)]
+        );
+        let (must_select, can_select): (Vec<_>, Vec<_>) = canon
+            .try_get_unspents(
+                (0..2).map(|vout| (OutPoint::new(funding.compute_txid(), vout), plan.clone())),
+            )
+            .partition(|input| must_select.contains(&input.prev_outpoint()));
+
+        let params = rbf_set.bump_fee_params(
+            FeeRate::from_sat_per_vb_u32(feerate),
+            |spk| spk == ours.as_script(),
+            ScriptSource::from_descriptor(descriptor()),
+            ChangePolicyType::NoDust,
+            DrainWeights::TR_KEYSPEND,
+        );
+        let selection = InputCandidates::new(must_select, can_select)
+            .into_selection(|s: &mut Selector| s.select_until_target_met(), params)
+            .unwrap();
+        assert_eq!(
+            selection.outputs[0].txout(),
+            TxOut {
+                value: Amount::from_sat(60_000),
+                script_pubkey: recipient(),
+            }
+        );
+        selection
+    }
+
+    #[test]
+    fn bump_fee_reduces_change() {
+        let selection = bump(100_000, 39_000, 10);
+        assert_eq!(selection.inputs.len(), 1);
+        assert_eq!(selection.change_indices, [1]);
+        assert!(selection.outputs[1].value < Amount::from_sat(39_000));
+    }
+
+    #[test]
+    fn bump_fee_adds_inputs_when_needed() {
+        let selection = bump(62_000, 1_500, 20);
+        assert_eq!(selection.inputs.len(), 2);
+        assert_eq!(selection.outputs[0].value, Amount::from_sat(60_000));
+    }
+
+    #[test]
+    fn validate_replacement_rules() {
+        let ours = descriptor().script_pubkey();
+        let funding = tx(
+            &[OutPoint::new(Txid::from_byte_array([1; 32]), 0)],
+            &[(ours.clone(), 100_000)],
+        );
+        let unconfirmed = tx(
+            &[OutPoint::new(Txid::from_byte_array([2; 32]), 0)],
+            &[(ours.clone(), 50_000)],
+        );
+        // original pays 1_000 sats fee, its child pays 2_000 sats fee
+        let original = tx(
+            &[OutPoint::new(funding.compute_txid(), 0)],
+            &[(recipient(), 60_000), (ours.clone(), 39_000)],
+        );
+        let child = tx(
+            &[OutPoint::new(original.compute_txid(), 1)],
+            &[(ours.clone(), 37_000)],
+        );
+        let status = confirmed();
+        let mut canon = CanonicalUnspents::new([
+            (funding.clone(), status),
+            (unconfirmed.clone(), None),
+            (original.clone(), None),
+            (child.clone(), None),
+        ]);
+        let rbf_set = canon
+            .extract_replacements([original.compute_txid()])
+            .unwrap();
+        assert_eq!(
+            rbf_set.descendants().collect::<Vec<_>>(),
+            [child.compute_txid()]
+        );
+
+        // Validate with the weight of the replacement once it is signed.
+        let validate = |replacement: &Transaction| {
+            let satisfaction_weight = plan().satisfaction_weight() as u64;
+            let weight = replacement.weight()
+                + Weight::from_wu(replacement.input.len() as u64 * satisfaction_weight);
+            rbf_set
+                .validate_replacement(replacement, weight, &canon)
+                .unwrap()
+        };
+        let replacement = |fee: u64, extra: &[OutPoint]| {
+            let mut inputs = vec![OutPoint::new(funding.compute_txid(), 0)];
+            inputs.extend_from_slice(extra);
+            let extra_value = extra.len() as u64 * 50_000;
+            tx(
+                &inputs,
+                &[
+                    (recipient(), 60_000),
+                    (ours.clone(), 40_000 + extra_value - fee),
+                ],
+            )
+        };
+
+        let report = validate(&replacement(5_000, &[]));
+        assert!(report.is_valid(), "{:?}", report);
+
+        let report = validate(&replacement(2_500, &[]));
+        assert_eq!(
+            report.absolute_fee,
+            Err(RbfViolation::InsufficientFee {
+                fee: Amount::from_sat(2_500),
+                required: Amount::from_sat(3_000),
+            })
+        );
+
+        // The additional fee covers the vsize of the unsigned replacement, but not of the signed
+        // one.
+        assert!(replacement(3_120, &[]).vsize() <= 120);
+        let report = validate(&replacement(3_120, &[]));
+        assert!(report.absolute_fee.is_ok());
+        assert!(matches!(
+            report.incremental_fee,
+            Err(RbfViolation::InsufficientIncrementalFee { .. })
+        ));
+
+        let new_input = OutPoint::new(unconfirmed.compute_txid(), 0);
+        let report = validate(&replacement(5_000, &[new_input]));
+        assert_eq!(
+            report.new_unconfirmed_inputs,
+            Err(RbfViolation::NewUnconfirmedInput(new_input))
+        );
+
+        let unrelated = tx(&[new_input], &[(ours.clone(), 40_000)]);
+        let report = validate(&unrelated);
+        assert_eq!(
+            report.conflicts,
+            Err(RbfViolation::NotConflicting(original.compute_txid()))
+        );
+    }
+
+    #[test]
+    fn feerate_diagram_comparison() {
+        let chunk = |weight: u64, fee: u64| Chunk { weight, fee };
+        // same total fee, but the first chunk pays a higher feerate
+        let old = [chunk(400, 2_000), chunk(400, 400)];
+        assert_eq!(
+            cmp_diagrams(&[chunk(800, 2_400)], &old),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            cmp_diagrams(&[chunk(800, 6_000)], &old),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(cmp_diagrams(&[chunk(800, 3_000)], &old), None);
+        assert_eq!(cmp_diagrams(&old, &old), Some(Ordering::Equal));
+    }
+
+    #[test]
+    fn descendants_are_paid_for_and_dropped_payments_reported() {
+        let ours = descriptor().script_pubkey();
+        let other = ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([7; 20]));
+        let funding = tx(
+            &[OutPoint::new(Txid::from_byte_array([1; 32]), 0)],
+            &[(ours.clone(), 100_000)],
+        );
+        let original = tx(
+            &[OutPoint::new(funding.compute_txid(), 0)],
+            &[(recipient(), 60_000), (ours.clone(), 39_000)],
+        );
+        // our payment spending the change of the original
+        let child = tx(
+            &[OutPoint::new(original.compute_txid(), 1)],
+            &[(other.clone(), 20_000), (ours.clone(), 17_000)],
+        );
+        // the recipient spending their output
+        let foreign_child = tx(
+            &[OutPoint::new(original.compute_txid(), 0)],
+            &[(other.clone(), 59_000)],
+        );
+        let status = confirmed();
+        let mut canon = CanonicalUnspents::new([
+            (funding.clone(), status),
+            (original.clone(), None),
+            (child.clone(), None),
+            (foreign_child.clone(), None),
+        ]);
+        let rbf_set = canon
+            .extract_replacements([original.compute_txid()])
+            .unwrap();
+        assert_eq!(rbf_set.descendants().len(), 2);
+        assert!(!canon.is_unspent(OutPoint::new(child.compute_txid(), 1)));
+
+        let rbf_params = rbf_set.selector_rbf_params();
+        assert_eq!(rbf_params.descendants.len(), 2);
+        assert_eq!(rbf_params.to_cs_replace().fee, 1_000 + 2_000 + 1_000);
+        assert_eq!(
+            rbf_params.max_feerate(),
+            Amount::from_sat(1_000) / original.weight()
+        );
+
+        let dropped = rbf_set
+            .dropped_payments(|spk| spk == ours.as_script())
+            .iter()
+            .map(Output::txout)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            dropped,
+            [TxOut {
+                value: Amount::from_sat(20_000),
+                script_pubkey: other,
+            }]
+        );
+    }
+
+    #[test]
+    fn all_descendants_are_extracted_once() {
+        let ours = descriptor().script_pubkey();
+        let funding = tx(
+            &[OutPoint::new(Txid::from_byte_array([1; 32]), 0)],
+            &[(ours.clone(), 100_000)],
+        );
+        let original = tx(
+            &[OutPoint::new(funding.compute_txid(), 0)],
+            &[(recipient(), 60_000), (ours.clone(), 39_000)],
+        );
+        // spends both outputs of the original
+        let child = tx(
+            &[
+                OutPoint::new(original.compute_txid(), 0),
+                OutPoint::new(original.compute_txid(), 1),
+            ],
+            &[(ours.clone(), 97_000)],
+        );
+        let grandchild = tx(
+            &[OutPoint::new(child.compute_txid(), 0)],
+            &[(recipient(), 50_000), (ours.clone(), 45_000)],
+        );
+        let mut canon = CanonicalUnspents::new([
+            (funding.clone(), confirmed()),
+            (original.clone(), None),
+            (child.clone(), None),
+            (grandchild.clone(), None),
+        ]);
+        // replacing the grandchild alongside the original does not replace it twice
+        let rbf_set = canon
+            .extract_replacements([original.compute_txid(), grandchild.compute_txid()])
+            .unwrap();
+        assert_eq!(
+            rbf_set.txids().collect::<Vec<_>>(),
+            [original.compute_txid()]
+        );
+        let mut descendants = rbf_set.descendants().collect::<Vec<_>>();
+        descendants.sort();
+        let mut expected = vec![child.compute_txid(), grandchild.compute_txid()];
+        expected.sort();
+        assert_eq!(descendants, expected);
+        assert_eq!(
+            rbf_set.selector_rbf_params().to_cs_replace().fee,
+            1_000 + 2_000 + 2_000
+        );
+        assert!(!canon.is_unspent(OutPoint::new(grandchild.compute_txid(), 1)));
+        assert!(canon.is_unspent(OutPoint::new(funding.compute_txid(), 0)));
     }
 }
@@ -2,11 +2,12 @@ use core::fmt::{Debug, Display};
 use std::vec::Vec;
 
 use bdk_coin_select::FeeRate;
+use bitcoin::hashes::{sha256, Hash, HashEngine};
 use bitcoin::{absolute, transaction, Sequence};
 use miniscript::bitcoin;
 use miniscript::psbt::PsbtExt;
 
-use crate::{Finalizer, Input, Output};
+use crate::{Finalizer, Input, Output, TRUC_VERSION};
 
 const FALLBACK_SEQUENCE: bitcoin::Sequence = bitcoin::Sequence::ENABLE_LOCKTIME_NO_RBF;
 
@@ -16,11 +17,19 @@ pub(crate) fn cs_feerate(feerate: bitcoin::FeeRate) -> bdk_coin_select::FeeRate
 
 /// Final selection of inputs and outputs.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct Selection {
     /// Inputs in this selection.
     pub inputs: Vec<Input>,
     /// Outputs in this selection.
     pub outputs: Vec<Output>,
+    /// Indices into [`outputs`](Self::outputs) which are change outputs.
+    pub change_indices: Vec<usize>,
+    /// Whether this is a TRUC (version 3) tx, see [`SelectorParams::truc`](crate::SelectorParams::truc).
+    ///
+    /// If set, [`create_psbt`](Self::create_psbt) creates a [`TRUC_VERSION`] tx regardless of
+    /// [`PsbtParams::version`].
+    pub truc: bool,
 }
 
 /// Parameters for creating a psbt.
@@ -33,7 +42,8 @@ pub struct PsbtParams {
     ///
     /// The locktime to use if no inputs specifies a required absolute locktime.
     ///
-    /// It is best practive to set this to the latest block height to avoid fee sniping.
+    /// It is best practive to set this to the latest block height to avoid fee sniping, or to
+    /// use [`anti_fee_sniping`](Self::anti_fee_sniping) instead.
     pub fallback_locktime: absolute::LockTime,
 
     /// [`Sequence`] value to use by default if not provided by the input.
@@ -44,6 +54,14 @@ pub struct PsbtParams {
     ///
     /// [`non_witness_utxo`]: bitcoin::psbt::Input::non_witness_utxo
     pub mandate_full_tx_for_segwit_v0: bool,
+
+    /// Ordering of the tx inputs and outputs, default is [`TxOrdering::Untouched`].
+    pub ordering: TxOrdering,
+
+    /// Discourage fee sniping by setting the locktime or sequence from the chain tip.
+    ///
+    /// If set, this replaces `fallback_locktime`. Default is `None`.
+    pub anti_fee_sniping: Option<AntiFeeSniping>,
 }
 
 impl Default for PsbtParams {
@@ -53,10 +71,232 @@ impl Default for PsbtParams {
             fallback_locktime: absolute::LockTime::ZERO,
             fallback_sequence: FALLBACK_SEQUENCE,
             mandate_full_tx_for_segwit_v0: true,
+            ordering: TxOrdering::default(),
+            anti_fee_sniping: None,
+        }
+    }
+}
+
+/// Parameters to discourage fee sniping, following the policy of Bitcoin Core and [BIP-326].
+///
+/// The tx locktime is set to the tip height, and occasionally to a random height up to 100
+/// blocks back. If all inputs are confirmed taproot spends, then half of the time a random input's
+/// sequence is set to its confirmation count instead.
+///
+/// Absolute timelocks required by the input plans are always respected. The sequence is only used
+/// if the tx version is at least 2 and no input requires a timelock of its own.
+///
+/// Inputs must not use a final sequence, otherwise the locktime is not enforced.
+///
+/// [BIP-326]: https://github.com/bitcoin/bips/blob/master/bip-0326.mediawiki
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AntiFeeSniping {
+    /// Height of the current chain tip.
+    pub tip_height: absolute::Height,
+    /// Seed of the random number generator.
+    ///
+    /// The caller should use a fresh random value per tx.
+    pub seed: u64,
+}
+
+impl AntiFeeSniping {
+    /// Largest confirmation count that can be expressed as a relative height timelock.
+    const MAX_SEQUENCE_HEIGHT: u32 = u16::MAX as u32;
+
+    /// Returns the tx locktime and updates `sequences` given the locktime `required` by the inputs.
+    fn apply(
+        &self,
+        inputs: &[&Input],
+        version: transaction::Version,
+        required: absolute::LockTime,
+        sequences: &mut [Sequence],
+    ) -> absolute::LockTime {
+        let mut rng = SeededRng::new(self.seed);
+
+        let use_sequence = version >= transaction::Version::TWO
+            && required == absolute::LockTime::ZERO
+            && !inputs.is_empty()
+            && inputs.iter().all(|input| {
+                input.prev_txout().script_pubkey.is_p2tr()
+                    && input.sequence().is_none()
+                    && (1..=Self::MAX_SEQUENCE_HEIGHT)
+                        .contains(&input.confirmations(self.tip_height))
+            })
+            && rng.gen_range(2) == 0;
+        if use_sequence {
+            let index = rng.gen_range(inputs.len() as u64) as usize;
+            let mut confirmations = inputs[index].confirmations(self.tip_height);
+            if rng.gen_range(10) == 0 {
+                confirmations = confirmations
+                    .saturating_sub(rng.gen_range(100) as u32)
+                    .max(1);
+            }
+            sequences[index] = Sequence::from_height(confirmations as u16);
+            return required;
+        }
+
+        // We cannot mix locktime units.
+        if !required.is_block_height() {
+            return required;
+        }
+        let mut height = self.tip_height.to_consensus_u32();
+        if rng.gen_range(10) == 0 {
+            height = height.saturating_sub(rng.gen_range(100) as u32);
+        }
+        let locktime = absolute::LockTime::from_height(height).expect("must be valid height");
+        if locktime.to_consensus_u32() > required.to_consensus_u32() {
+            locktime
+        } else {
+            required
+        }
+    }
+}
+
+/// Ordering of the inputs and outputs of a created tx.
+///
+/// Keeping the order of the [`Selection`] means the change output always comes last, which
+/// is a well-known wallet fingerprint.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TxOrdering {
+    /// Keep the order of the [`Selection`].
+    #[default]
+    Untouched,
+    /// Sort inputs and outputs lexicographically as described in [BIP-69].
+    ///
+    /// [BIP-69]: https://github.com/bitcoin/bips/blob/master/bip-0069.mediawiki
+    Bip69,
+    /// Shuffle inputs and outputs randomly.
+    ///
+    /// The same `seed` always results in the same order, so the caller should use a fresh random
+    /// value per tx.
+    Shuffle {
+        /// Seed of the random number generator.
+        seed: u64,
+    },
+    /// Caller-supplied order.
+    ///
+    /// Each vec is a permutation of indices into the [`Selection`]'s inputs or outputs, where the
+    /// element at position `i` is the index of the item placed at position `i` of the tx.
+    Custom {
+        /// Input order.
+        inputs: Vec<usize>,
+        /// Output order.
+        outputs: Vec<usize>,
+    },
+}
+
+impl TxOrdering {
+    /// Returns the order of the `inputs` and `outputs` as indices.
+    ///
+    /// Returns `None` if the custom order is not a valid permutation.
+    fn sort_indices(
+        &self,
+        inputs: &[Input],
+        outputs: &[Output],
+    ) -> Option<(Vec<usize>, Vec<usize>)> {
+        let mut input_order = (0..inputs.len()).collect::<Vec<_>>();
+        let mut output_order = (0..outputs.len()).collect::<Vec<_>>();
+        match self {
+            TxOrdering::Untouched => {}
+            TxOrdering::Bip69 => {
+                // Txids are compared in the reversed (displayed) byte order.
+                input_order.sort_by_key(|&i| {
+                    let outpoint = inputs[i].prev_outpoint();
+                    let mut txid = outpoint.txid.to_byte_array();
+                    txid.reverse();
+                    (txid, outpoint.vout)
+                });
+                output_order.sort_by_key(|&i| (outputs[i].value, outputs[i].script_pubkey()));
+            }
+            TxOrdering::Shuffle { seed } => {
+                let mut rng = SeededRng::new(*seed);
+                rng.shuffle(&mut input_order);
+                rng.shuffle(&mut output_order);
+            }
+            TxOrdering::Custom {
+                inputs: custom_inputs,
+                outputs: custom_outputs,
+            } => {
+                let is_permutation = |order: &[usize], len: usize| {
+                    let mut sorted = order.to_vec();
+                    sorted.sort_unstable();
+                    sorted.into_iter().eq(0..len)
+                };
+                if !is_permutation(custom_inputs, inputs.len())
+                    || !is_permutation(custom_outputs, outputs.len())
+                {
+                    return None;
+                }
+                input_order.clone_from(custom_inputs);
+                output_order.clone_from(custom_outputs);
+            }
+        }
+        Some((input_order, output_order))
+    }
+}
+
+/// Deterministic random number generator that hashes the seed with a counter.
+struct SeededRng {
+    seed: u64,
+    counter: u64,
+}
+
+impl SeededRng {
+    fn new(seed: u64) -> Self {
+        Self { seed, counter: 0 }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut engine = sha256::Hash::engine();
+        engine.input(&self.seed.to_le_bytes());
+        engine.input(&self.counter.to_le_bytes());
+        self.counter += 1;
+        let hash = sha256::Hash::from_engine(engine).to_byte_array();
+        u64::from_le_bytes(hash[..8].try_into().expect("must be 8 bytes"))
+    }
+
+    /// Random value in the range `0..n`.
+    fn gen_range(&mut self, n: u64) -> u64 {
+        if n == 0 {
+            return 0;
+        }
+        self.next_u64() % n
+    }
+
+    /// Fisher-Yates shuffle.
+    fn shuffle<T>(&mut self, slice: &mut [T]) {
+        for i in (1..slice.len()).rev() {
+            let j = self.gen_range(i as u64 + 1) as usize;
+            slice.swap(i, j);
         }
     }
 }
 
+/// The psbt input of `input` before it is signed.
+pub(crate) fn unsigned_psbt_input(input: &Input) -> bitcoin::psbt::Input {
+    if let Some(psbt_input) = input.psbt_input() {
+        return psbt_input.clone();
+    }
+    let mut psbt_input = bitcoin::psbt::Input::default();
+    let plan = input
+        .plan()
+        .expect("input candidate must either have finalized psbt input or plan");
+    plan.update_psbt_input(&mut psbt_input);
+    if plan.witness_version().is_some() {
+        psbt_input.witness_utxo = Some(input.prev_txout().clone());
+    }
+    // We are allowed to have full tx for segwit inputs. Might as well include it.
+    // If the caller does not wish to include the full tx in Segwit V0 inputs, they should not
+    // include it in `crate::Input`.
+    psbt_input.non_witness_utxo = input.prev_tx().cloned();
+    psbt_input
+}
+
+/// Random index in the range `0..n` derived from `seed`.
+pub(crate) fn random_index(seed: u64, n: usize) -> usize {
+    SeededRng::new(seed).gen_range(n as u64) as usize
+}
+
 /// Occurs when creating a psbt fails.
 #[derive(Debug)]
 pub enum CreatePsbtError {
@@ -70,6 +310,11 @@ pub enum CreatePsbtError {
     Psbt(bitcoin::psbt::Error),
     /// Update psbt output with descriptor error.
     OutputUpdate(miniscript::psbt::OutputUpdateError),
+    /// The custom [`TxOrdering`] is not a permutation of the inputs and outputs.
+    InvalidOrdering,
+    /// The output at this index of [`Selection::outputs`] is an unresolved silent payment, see
+    /// [`Selection::resolve_silent_payments`].
+    UnresolvedSilentPayment(usize),
 }
 
 impl core::fmt::Display for CreatePsbtError {
@@ -90,6 +335,15 @@ impl core::fmt::Display for CreatePsbtError {
             CreatePsbtError::OutputUpdate(output_update_error) => {
                 Display::fmt(&output_update_error, f)
             }
+            CreatePsbtError::InvalidOrdering => {
+                write!(
+                    f,
+                    "custom ordering is not a permutation of inputs and outputs"
+                )
+            }
+            CreatePsbtError::UnresolvedSilentPayment(index) => {
+                write!(f, "output {} is an unresolved silent payment", index)
+            }
         }
     }
 }
@@ -126,68 +380,99 @@ impl Selection {
     }
 
     /// Create psbt.
-    pub fn create_psbt(&self, params: PsbtParams) -> Result<bitcoin::Psbt, CreatePsbtError> {
+    ///
+    /// Inputs and outputs are ordered according to [`PsbtParams::ordering`].
+    ///
+    /// # Errors
+    ///
+    /// - If the inputs require absolute timelocks of different units.
+    /// - If the custom ordering is invalid.
+    /// - If an input is missing the full previous tx when required.
+    /// - If a silent payment output is not resolved.
+    pub fn create_psbt(&self, mut params: PsbtParams) -> Result<bitcoin::Psbt, CreatePsbtError> {
+        if self.truc {
+            params.version = TRUC_VERSION;
+        }
+        if let Some(index) = self
+            .outputs
+            .iter()
+            .position(|output| output.script_pubkey_source.silent_payment().is_some())
+        {
+            return Err(CreatePsbtError::UnresolvedSilentPayment(index));
+        }
+        let (input_order, output_order) = params
+            .ordering
+            .sort_indices(&self.inputs, &self.outputs)
+            .ok_or(CreatePsbtError::InvalidOrdering)?;
+        let inputs = input_order
+            .into_iter()
+            .map(|i| &self.inputs[i])
+            .collect::<Vec<_>>();
+        let outputs = output_order
+            .into_iter()
+            .map(|i| &self.outputs[i])
+            .collect::<Vec<_>>();
+
+        let fallback_locktime = match params.anti_fee_sniping {
+            Some(_) => absolute::LockTime::ZERO,
+            None => params.fallback_locktime,
+        };
+        let mut lock_time = Self::_accumulate_max_locktime(
+            inputs.iter().filter_map(|input| input.absolute_timelock()),
+            fallback_locktime,
+        )
+        .ok_or(CreatePsbtError::LockTypeMismatch)?;
+        let mut sequences = inputs
+            .iter()
+            .map(|input| input.sequence().unwrap_or(params.fallback_sequence))
+            .collect::<Vec<_>>();
+        if let Some(anti_fee_sniping) = params.anti_fee_sniping {
+            lock_time = anti_fee_sniping.apply(&inputs, params.version, lock_time, &mut sequences);
+        }
+
         let mut psbt = bitcoin::Psbt::from_unsigned_tx(bitcoin::Transaction {
             version: params.version,
-            lock_time: Self::_accumulate_max_locktime(
-                self.inputs
-                    .iter()
-                    .filter_map(|input| input.absolute_timelock()),
-                params.fallback_locktime,
-            )
-            .ok_or(CreatePsbtError::LockTypeMismatch)?,
-            input: self
-                .inputs
+            lock_time,
+            input: inputs
                 .iter()
-                .map(|input| bitcoin::TxIn {
+                .zip(sequences)
+                .map(|(input, sequence)| bitcoin::TxIn {
                     previous_output: input.prev_outpoint(),
-                    sequence: input.sequence().unwrap_or(params.fallback_sequence),
+                    sequence,
                     ..Default::default()
                 })
                 .collect(),
-            output: self.outputs.iter().map(|output| output.txout()).collect(),
+            output: outputs.iter().map(|output| output.txout()).collect(),
         })
         .map_err(CreatePsbtError::Psbt)?;
 
-        for (plan_input, psbt_input) in self.inputs.iter().zip(psbt.inputs.iter_mut()) {
-            if let Some(finalized_psbt_input) = plan_input.psbt_input() {
-                *psbt_input = finalized_psbt_input.clone();
+        for (&plan_input, psbt_input) in inputs.iter().zip(psbt.inputs.iter_mut()) {
+            *psbt_input = unsigned_psbt_input(plan_input);
+            if plan_input.psbt_input().is_some() || psbt_input.non_witness_utxo.is_some() {
                 continue;
             }
-            if let Some(plan) = plan_input.plan() {
-                plan.update_psbt_input(psbt_input);
-
-                let witness_version = plan.witness_version();
-                if witness_version.is_some() {
-                    psbt_input.witness_utxo = Some(plan_input.prev_txout().clone());
-                }
-                // We are allowed to have full tx for segwit inputs. Might as well include it.
-                // If the caller does not wish to include the full tx in Segwit V0 inputs, they should not
-                // include it in `crate::Input`.
-                psbt_input.non_witness_utxo = plan_input.prev_tx().cloned();
-                if psbt_input.non_witness_utxo.is_none() {
-                    if witness_version.is_none() {
-                        return Err(CreatePsbtError::MissingFullTxForLegacyInput(
-                            plan_input.clone(),
-                        ));
-                    }
-                    if params.mandate_full_tx_for_segwit_v0
-                        && witness_version == Some(bitcoin::WitnessVersion::V0)
-                    {
-                        return Err(CreatePsbtError::MissingFullTxForSegwitV0Input(
-                            plan_input.clone(),
-                        ));
-                    }
-                }
-                continue;
+            let witness_version = plan_input.plan().and_then(|plan| plan.witness_version());
+            if witness_version.is_none() {
+                return Err(CreatePsbtError::MissingFullTxForLegacyInput(
+                    plan_input.clone(),
+                ));
+            }
+            if params.mandate_full_tx_for_segwit_v0
+                && witness_version == Some(bitcoin::WitnessVersion::V0)
+            {
+                return Err(CreatePsbtError::MissingFullTxForSegwitV0Input(
+                    plan_input.clone(),
+                ));
             }
-            unreachable!("input candidate must either have finalized psbt input or plan");
         }
-        for (output_index, output) in self.outputs.iter().enumerate() {
+        for (output_index, output) in outputs.iter().enumerate() {
             if let Some(desc) = output.descriptor() {
                 psbt.update_output_with_descriptor(output_index, desc)
                     .map_err(CreatePsbtError::OutputUpdate)?;
             }
+            if let Some(taproot) = output.taproot() {
+                taproot.update_psbt_output(&mut psbt.outputs[output_index]);
+            }
         }
 
         Ok(psbt)
@@ -202,3 +487,336 @@ impl Selection {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::bip32::{Xpriv, Xpub};
+    use bitcoin::secp256k1::Secp256k1;
+    use bitcoin::{psbt, Amount, OutPoint, TxOut, Txid};
+
+    use miniscript::plan::Assets;
+    use miniscript::DescriptorPublicKey;
+
+    use crate::test_utils::{spk, PK};
+    use crate::{DefiniteDescriptor, TaprootOutput, TxStatus};
+
+    const XPRV: &str = "tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L";
+
+    fn input(outpoint: OutPoint, value: u64) -> Input {
+        let psbt_input = psbt::Input {
+            witness_utxo: Some(TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: spk(0),
+            }),
+            ..Default::default()
+        };
+        Input::from_psbt_input(outpoint, Sequence::MAX, psbt_input, 108, None, false).unwrap()
+    }
+
+    fn descriptor() -> DefiniteDescriptor {
+        let secp = Secp256k1::new();
+        let xprv: Xpriv = XPRV.parse().unwrap();
+        let xpub = Xpub::from_priv(&secp, &xprv);
+        format!("wpkh({xpub}/0/0)").parse().unwrap()
+    }
+
+    /// Txid `a` sorts before `b` by internal byte order, but after `b` by displayed byte order.
+    fn selection() -> Selection {
+        let mut a = [0u8; 32];
+        a[0] = 1;
+        a[31] = 2;
+        let mut b = [0u8; 32];
+        b[0] = 2;
+        b[31] = 1;
+        let (a, b) = (Txid::from_byte_array(a), Txid::from_byte_array(b));
+        Selection {
+            inputs: vec![
+                input(OutPoint::new(a, 1), 10_000),
+                input(OutPoint::new(b, 0), 20_000),
+                input(OutPoint::new(a, 0), 30_000),
+            ],
+            outputs: vec![
+                Output::with_descriptor(descriptor(), Amount::from_sat(3_000)),
+                Output::with_script(spk(2), Amount::from_sat(1_000)),
+                Output::with_script(spk(1), Amount::from_sat(1_000)),
+            ],
+            change_indices: vec![],
+            truc: false,
+        }
+    }
+
+    fn create_psbt(selection: &Selection, ordering: TxOrdering) -> bitcoin::Psbt {
+        selection
+            .create_psbt(PsbtParams {
+                ordering,
+                ..Default::default()
+            })
+            .unwrap()
+    }
+
+    /// Check that psbt inputs and outputs are attached to the right tx index.
+    fn assert_attached(selection: &Selection, psbt: &bitcoin::Psbt) {
+        for (txin, psbt_input) in psbt.unsigned_tx.input.iter().zip(&psbt.inputs) {
+            let input = selection
+                .inputs
+                .iter()
+                .find(|input| input.prev_outpoint() == txin.previous_output)
+                .unwrap();
+            assert_eq!(psbt_input.witness_utxo.as_ref(), Some(input.prev_txout()));
+        }
+        let desc_spk = descriptor().script_pubkey();
+        for (txout, psbt_output) in psbt.unsigned_tx.output.iter().zip(&psbt.outputs) {
+            assert_eq!(
+                txout.script_pubkey == desc_spk,
+                !psbt_output.bip32_derivation.is_empty()
+            );
+        }
+    }
+
+    #[test]
+    fn ordering_bip69() {
+        let selection = selection();
+        let psbt = create_psbt(&selection, TxOrdering::Bip69);
+        let input_values = psbt
+            .inputs
+            .iter()
+            .map(|input| input.witness_utxo.as_ref().unwrap().value.to_sat())
+            .collect::<Vec<_>>();
+        assert_eq!(input_values, vec![20_000, 30_000, 10_000]);
+        let outputs = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .map(|txout| (txout.value.to_sat(), txout.script_pubkey.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outputs,
+            vec![
+                (1_000, spk(1)),
+                (1_000, spk(2)),
+                (3_000, descriptor().script_pubkey())
+            ]
+        );
+        assert_attached(&selection, &psbt);
+    }
+
+    #[test]
+    fn ordering_shuffle() {
+        let selection = selection();
+        let untouched = create_psbt(&selection, TxOrdering::Untouched).unsigned_tx;
+        let shuffled = create_psbt(&selection, TxOrdering::Shuffle { seed: 7 }).unsigned_tx;
+        assert_eq!(
+            shuffled,
+            create_psbt(&selection, TxOrdering::Shuffle { seed: 7 }).unsigned_tx,
+            "same seed must result in the same order"
+        );
+        assert_eq!(shuffled.input.len(), untouched.input.len());
+        assert!(untouched
+            .input
+            .iter()
+            .all(|txin| shuffled.input.contains(txin)));
+        assert!(
+            (0..32).any(|seed| {
+                create_psbt(&selection, TxOrdering::Shuffle { seed }).unsigned_tx != untouched
+            }),
+            "shuffle must change the order"
+        );
+        for seed in 0..8 {
+            let psbt = create_psbt(&selection, TxOrdering::Shuffle { seed });
+            assert_attached(&selection, &psbt);
+        }
+    }
+
+    #[test]
+    fn ordering_custom() {
+        let selection = selection();
+        let psbt = create_psbt(
+            &selection,
+            TxOrdering::Custom {
+                inputs: vec![2, 0, 1],
+                outputs: vec![1, 2, 0],
+            },
+        );
+        assert_eq!(
+            psbt.unsigned_tx.input[0].previous_output,
+            selection.inputs[2].prev_outpoint()
+        );
+        assert_eq!(psbt.unsigned_tx.output[2], selection.outputs[0].txout());
+        assert_attached(&selection, &psbt);
+
+        let res = selection.create_psbt(PsbtParams {
+            ordering: TxOrdering::Custom {
+                inputs: vec![0, 0, 1],
+                outputs: vec![0, 1, 2],
+            },
+            ..Default::default()
+        });
+        assert!(matches!(res, Err(CreatePsbtError::InvalidOrdering)));
+    }
+
+    const TIP: u32 = 800_000;
+
+    fn plan_input(desc: &str, after: Option<u32>, vout: u32, conf_height: u32) -> Input {
+        let desc: DefiniteDescriptor = desc.replace("KEY", PK).parse().unwrap();
+        let mut assets = Assets::new().add(PK.parse::<DescriptorPublicKey>().unwrap());
+        if let Some(after) = after {
+            assets = assets.after(absolute::LockTime::from_consensus(after));
+        }
+        let plan = desc.clone().plan(&assets).unwrap();
+        let txout = TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: desc.script_pubkey(),
+        };
+        let status = TxStatus::new(conf_height, 1_700_000_000).unwrap();
+        let outpoint = OutPoint::new(Txid::all_zeros(), vout);
+        Input::from_prev_txout(plan, outpoint, txout, Some(status), false)
+    }
+
+    fn anti_fee_sniping_psbt(inputs: Vec<Input>, seed: u64) -> bitcoin::Psbt {
+        let selection = Selection {
+            inputs,
+            outputs: vec![Output::with_script(spk(1), Amount::from_sat(5_000))],
+            change_indices: vec![],
+            truc: false,
+        };
+        selection
+            .create_psbt(PsbtParams {
+                mandate_full_tx_for_segwit_v0: false,
+                anti_fee_sniping: Some(AntiFeeSniping {
+                    tip_height: absolute::Height::from_consensus(TIP).unwrap(),
+                    seed,
+                }),
+                ..Default::default()
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn anti_fee_sniping_locktime() {
+        let inputs = vec![
+            plan_input("wpkh(KEY)", None, 0, TIP - 9),
+            plan_input("wpkh(KEY)", None, 1, TIP - 9),
+        ];
+        let mut stepped_back = false;
+        for seed in 0..100 {
+            let tx = anti_fee_sniping_psbt(inputs.clone(), seed).unsigned_tx;
+            let locktime = tx.lock_time.to_consensus_u32();
+            assert!((TIP - 99..=TIP).contains(&locktime), "locktime {locktime}");
+            stepped_back |= locktime < TIP;
+            assert!(tx
+                .input
+                .iter()
+                .all(|txin| txin.sequence == FALLBACK_SEQUENCE));
+        }
+        assert!(stepped_back, "locktime must occasionally be stepped back");
+    }
+
+    #[test]
+    fn anti_fee_sniping_taproot_sequence() {
+        let inputs = vec![
+            plan_input("tr(KEY)", None, 0, TIP - 9),
+            plan_input("tr(KEY)", None, 1, TIP - 9),
+        ];
+        let (mut used_locktime, mut used_sequence) = (false, false);
+        for seed in 0..100 {
+            let tx = anti_fee_sniping_psbt(inputs.clone(), seed).unsigned_tx;
+            let relative_heights = tx
+                .input
+                .iter()
+                .filter(|txin| txin.sequence != FALLBACK_SEQUENCE)
+                .map(|txin| txin.sequence.to_relative_lock_time())
+                .collect::<Vec<_>>();
+            if tx.lock_time == absolute::LockTime::ZERO {
+                used_sequence = true;
+                assert_eq!(relative_heights.len(), 1);
+                assert!(matches!(
+                    relative_heights[0],
+                    Some(bitcoin::relative::LockTime::Blocks(height))
+                        if (1..=10).contains(&height.value())
+                ));
+            } else {
+                used_locktime = true;
+                assert!(relative_heights.is_empty());
+            }
+        }
+        assert!(used_locktime && used_sequence);
+    }
+
+    #[test]
+    fn anti_fee_sniping_respects_required_locktime() {
+        let inputs = vec![
+            plan_input("tr(KEY)", None, 0, TIP - 9),
+            plan_input(
+                "wsh(and_v(v:pk(KEY),after(900000)))",
+                Some(900_000),
+                1,
+                TIP - 9,
+            ),
+        ];
+        for seed in 0..20 {
+            let tx = anti_fee_sniping_psbt(inputs.clone(), seed).unsigned_tx;
+            assert_eq!(tx.lock_time.to_consensus_u32(), 900_000);
+        }
+        let inputs = vec![plan_input(
+            "wsh(and_v(v:pk(KEY),after(1700000000)))",
+            Some(1_700_000_000),
+            0,
+            TIP - 9,
+        )];
+        for seed in 0..20 {
+            let tx = anti_fee_sniping_psbt(inputs.clone(), seed).unsigned_tx;
+            assert_eq!(tx.lock_time.to_consensus_u32(), 1_700_000_000);
+        }
+    }
+
+    #[test]
+    fn taproot_output_fills_psbt_fields() {
+        let secp = Secp256k1::new();
+        let xprv: Xpriv = XPRV.parse().unwrap();
+        let xpub = Xpub::from_priv(&secp, &xprv);
+        let desc: DefiniteDescriptor = format!("tr({xpub}/0/0,{{pk({xpub}/1/0),pk({xpub}/1/1)}})")
+            .parse()
+            .unwrap();
+        let from_descriptor = Selection {
+            inputs: vec![],
+            outputs: vec![Output::with_descriptor(
+                desc.clone(),
+                Amount::from_sat(1_000),
+            )],
+            change_indices: vec![],
+            truc: false,
+        }
+        .create_psbt(PsbtParams::default())
+        .unwrap();
+        let expected = &from_descriptor.outputs[0];
+
+        // The tree and key origins as provided by another party.
+        let taproot = expected.tap_key_origins.iter().fold(
+            TaprootOutput::new(
+                &secp,
+                expected.tap_internal_key.unwrap(),
+                expected.tap_tree.clone(),
+            ),
+            |taproot, (key, (leaf_hashes, origin))| {
+                taproot.with_key_origin(*key, leaf_hashes.clone(), origin.clone())
+            },
+        );
+        assert_eq!(taproot.script_pubkey(), desc.script_pubkey());
+
+        let psbt = Selection {
+            inputs: vec![],
+            outputs: vec![Output::with_taproot(taproot, Amount::from_sat(1_000))],
+            change_indices: vec![],
+            truc: false,
+        }
+        .create_psbt(PsbtParams::default())
+        .unwrap();
+        assert_eq!(psbt.unsigned_tx, from_descriptor.unsigned_tx);
+        assert_eq!(psbt.outputs[0].tap_internal_key, expected.tap_internal_key);
+        assert_eq!(psbt.outputs[0].tap_tree, expected.tap_tree);
+        assert_eq!(psbt.outputs[0].tap_key_origins, expected.tap_key_origins);
+        assert_eq!(expected.tap_key_origins.len(), 3);
+    }
+}
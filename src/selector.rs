@@ -1,10 +1,13 @@
 use bdk_coin_select::{
-    ChangePolicy, DrainWeights, InsufficientFunds, Replace, Target, TargetFee, TargetOutputs,
+    ChangePolicy, Drain, DrainWeights, InsufficientFunds, Replace, Target, TargetFee, TargetOutputs,
 };
 use bitcoin::{Amount, FeeRate, Transaction, Weight};
 use miniscript::bitcoin;
 
-use crate::{cs_feerate, InputCandidates, InputGroup, Output, ScriptSource, Selection};
+use crate::{
+    cs_feerate, package_bump_fee, AncestorPackage, InputCandidates, InputGroup, Output,
+    ScriptSource, Selection, TRUC_CHILD_MAX_VSIZE, TRUC_MAX_VSIZE,
+};
 use alloc::vec::Vec;
 use core::fmt;
 
@@ -16,18 +19,20 @@ pub struct Selector<'c> {
     target: Target,
     change_policy: bdk_coin_select::ChangePolicy,
     change_script: ScriptSource,
+    change_split: Option<ChangeSplit>,
+    subtract_fee: Option<(SubtractFee, Target)>,
+    sweep: bool,
+    truc: bool,
     inner: bdk_coin_select::CoinSelector<'c>,
 }
 
 /// Parameters for creating tx.
 ///
-/// TODO: Create a builder interface on this that does checks. I.e.
-/// * Error if recipient is dust.
-/// * Error on multi OP_RETURN outputs.
-/// * Error on anything that does not satisfy mempool policy.
-///   If the caller wants to create non-mempool-policy conforming txs, they can just fill in the
-///   fields directly.
+/// Use [`SelectorParamsBuilder`] to construct parameters that are checked against mempool policy.
+/// If the caller wants to create non-mempool-policy conforming txs, they can construct them with
+/// [`SelectorParams::new`] and fill in the fields directly.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct SelectorParams {
     /// Feerate target!
     ///
@@ -50,8 +55,42 @@ pub struct SelectorParams {
     /// Weight of the change output plus the future weight to spend the change
     pub change_weight: DrainWeights,
 
+    /// Split the change across multiple outputs.
+    ///
+    /// If set, the first change output pays to `change_script` and is followed by the other
+    /// scripts of the split. The output weights are derived from the scripts, and the future spend
+    /// weight of each output is assumed to be `change_weight.spend_weight`.
+    pub change_split: Option<ChangeSplit>,
+
+    /// Subtract the fee from the target outputs instead of funding it on top of them.
+    ///
+    /// Coin selection then only needs to fund the target output values and the fee (including
+    /// any [`replace`](Self::replace) constraints) is taken from the chosen target outputs.
+    pub subtract_fee: Option<SubtractFee>,
+
+    /// Sweep all candidates to this script, minus fees.
+    ///
+    /// If set, the [`Selector`] selects all input candidates and the remaining value (after
+    /// funding `target_outputs` and fees) is sent to this script instead of change. To sweep
+    /// specific outpoints only, filter the candidates with
+    /// [`filter_outpoints`](crate::filter_outpoints). `change_script`, `change_split` and
+    /// `subtract_fee` are ignored in sweep mode.
+    pub sweep_to: Option<ScriptSource>,
+
     /// Params for replacing tx(s).
     pub replace: Option<RbfParams>,
+
+    /// Params for bumping unconfirmed parent tx(s) with this tx (CPFP).
+    ///
+    /// If set, `target_feerate` is the feerate of the whole package.
+    pub cpfp: Option<CpfpParams>,
+
+    /// Whether to create a TRUC (version 3) tx, default is `false`.
+    ///
+    /// If set, the selection is checked against the TRUC rules of BIP-431, see
+    /// [`Selection::check_truc`]. Use [`filter_truc`](crate::filter_truc) to filter the input
+    /// candidates and [`cpfp`](Self::cpfp) to pay for the unconfirmed parent.
+    pub truc: bool,
 }
 
 /// Rbf original tx stats.
@@ -78,13 +117,29 @@ impl From<(&Transaction, Amount)> for OriginalTxStats {
 
 /// Rbf params.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct RbfParams {
     /// Original txs.
     pub original_txs: Vec<OriginalTxStats>,
+    /// Unconfirmed descendants of the original txs.
+    ///
+    /// These are evicted alongside the original txs, so the replacement must also pay for their
+    /// fees (rule 3). Unlike `original_txs`, they do not raise the minimum feerate of the
+    /// replacement.
+    pub descendants: Vec<OriginalTxStats>,
     /// Incremental relay feerate.
     pub incremental_relay_feerate: FeeRate,
 }
 
+/// Cpfp params.
+#[derive(Debug, Clone, Copy)]
+pub struct CpfpParams {
+    /// Total weight of the unconfirmed ancestor package.
+    pub package_weight: Weight,
+    /// Total fee paid by the unconfirmed ancestor package.
+    pub package_fee: Amount,
+}
+
 /// Change policy type
 // TODO: Make this more flexible.
 #[derive(Debug, Clone, Copy)]
@@ -98,6 +153,204 @@ pub enum ChangePolicyType {
     },
 }
 
+/// How to split the change value across multiple change outputs.
+///
+/// The first change output always pays to [`SelectorParams::change_script`], followed by one
+/// output per script of the split.
+#[derive(Debug, Clone)]
+pub enum ChangeSplit {
+    /// Split change by fixed ratios.
+    ///
+    /// Each script receives `ratio / sum_of_ratios` of the change value.
+    Ratios {
+        /// Ratio of `change_script`.
+        change_ratio: u32,
+        /// Other change scripts and their ratios.
+        others: Vec<(ScriptSource, u32)>,
+    },
+    /// Split change into outputs of similar value, one for `change_script` and one per script.
+    Even(Vec<ScriptSource>),
+}
+
+impl ChangeSplit {
+    /// The change scripts and their ratios, starting with `change_script`.
+    pub fn ratios<'a>(
+        &'a self,
+        change_script: &'a ScriptSource,
+    ) -> impl Iterator<Item = (&'a ScriptSource, u32)> + 'a {
+        let (change_ratio, others, scripts) = match self {
+            Self::Ratios {
+                change_ratio,
+                others,
+            } => (*change_ratio, others.as_slice(), &[][..]),
+            Self::Even(scripts) => (1, &[][..], scripts.as_slice()),
+        };
+        core::iter::once((change_script, change_ratio))
+            .chain(others.iter().map(|(script, ratio)| (script, *ratio)))
+            .chain(scripts.iter().map(|script| (script, 1)))
+    }
+
+    /// Number of change outputs, including the one of `change_script`.
+    pub fn output_count(&self) -> usize {
+        1 + match self {
+            Self::Ratios { others, .. } => others.len(),
+            Self::Even(scripts) => scripts.len(),
+        }
+    }
+
+    /// Whether all ratios are non-zero.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Self::Ratios {
+                change_ratio,
+                others,
+            } => *change_ratio > 0 && others.iter().all(|(_, ratio)| *ratio > 0),
+            Self::Even(_) => true,
+        }
+    }
+
+    fn total_ratio(&self, change_script: &ScriptSource) -> u128 {
+        self.ratios(change_script)
+            .map(|(_, ratio)| ratio as u128)
+            .sum()
+    }
+
+    /// Weights of the change outputs given the future weight to spend a single change output.
+    pub fn drain_weights(&self, change_script: &ScriptSource, spend_weight: u64) -> DrainWeights {
+        DrainWeights {
+            output_weight: self
+                .ratios(change_script)
+                .map(|(script, _)| {
+                    let txout = bitcoin::TxOut {
+                        value: Amount::ZERO,
+                        script_pubkey: script.script(),
+                    };
+                    txout.weight().to_wu()
+                })
+                .sum(),
+            spend_weight: spend_weight * self.output_count() as u64,
+            n_outputs: self.output_count(),
+        }
+    }
+
+    /// The minimum change value such that none of the split outputs are dust.
+    pub fn min_value(&self, change_script: &ScriptSource) -> Amount {
+        let total_ratio = self.total_ratio(change_script);
+        let min_value = self
+            .ratios(change_script)
+            .filter(|(_, ratio)| *ratio > 0)
+            .map(|(script, ratio)| {
+                let dust = script.script().minimal_non_dust().to_sat() as u128;
+                (dust * total_ratio + ratio as u128 - 1) / ratio as u128
+            })
+            .max()
+            .unwrap_or(0);
+        Amount::from_sat(min_value.try_into().unwrap_or(u64::MAX))
+    }
+
+    /// Split `value` into change outputs.
+    ///
+    /// The remainder of the division is added to the last output.
+    pub fn split(&self, change_script: &ScriptSource, value: Amount) -> Vec<Output> {
+        let total_ratio = self.total_ratio(change_script);
+        let mut outputs = self
+            .ratios(change_script)
+            .map(|(script, ratio)| {
+                let share = value.to_sat() as u128 * ratio as u128 / total_ratio;
+                let share = Amount::from_sat(share as u64);
+                Output::from((script.clone(), share))
+            })
+            .collect::<Vec<_>>();
+        let remainder = value - outputs.iter().map(|output| output.value).sum::<Amount>();
+        if let Some(last) = outputs.last_mut() {
+            last.value += remainder;
+        }
+        outputs
+    }
+}
+
+/// Subtract the fee from target outputs, like Bitcoin Core's `subtractfeefromoutputs`.
+#[derive(Debug, Clone)]
+pub struct SubtractFee {
+    /// Indices into [`SelectorParams::target_outputs`] which pay the fee.
+    pub outputs: Vec<usize>,
+    /// How the fee is split between the `outputs`.
+    pub split: FeeSplit,
+}
+
+/// How to split the fee between outputs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FeeSplit {
+    /// Each output pays an equal share, the first output pays the remainder.
+    #[default]
+    Equal,
+    /// Each output pays a share proportional to its value.
+    Proportional,
+    /// Each output pays a share proportional to its weight.
+    ByWeight,
+}
+
+impl SubtractFee {
+    /// Subtract the fee from the given target outputs with the given `split`.
+    pub fn new(outputs: impl IntoIterator<Item = usize>, split: FeeSplit) -> Self {
+        Self {
+            outputs: outputs.into_iter().collect(),
+            split,
+        }
+    }
+
+    /// Whether the outputs are non-empty, unique and within `output_count`.
+    pub fn is_valid(&self, output_count: usize) -> bool {
+        !self.outputs.is_empty()
+            && self.outputs.iter().all(|&i| i < output_count)
+            && self
+                .outputs
+                .iter()
+                .enumerate()
+                .all(|(n, i)| !self.outputs[..n].contains(i))
+    }
+
+    /// Subtract `fee` from `outputs`.
+    ///
+    /// Returns `None` without changing `outputs` if there are no fee-paying outputs, an index is
+    /// out of range, or any of the fee-paying outputs would end up as dust.
+    pub fn apply(&self, outputs: &mut [Output], fee: Amount) -> Option<()> {
+        let weights = self
+            .outputs
+            .iter()
+            .map(|&i| {
+                let output = outputs.get(i)?;
+                Some(match self.split {
+                    FeeSplit::Equal => 1,
+                    FeeSplit::Proportional => output.value.to_sat() as u128,
+                    FeeSplit::ByWeight => output.txout().weight().to_wu() as u128,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let total_weight = weights.iter().sum::<u128>().max(1);
+        let mut shares = weights
+            .iter()
+            .map(|&weight| (fee.to_sat() as u128 * weight / total_weight) as u64)
+            .collect::<Vec<_>>();
+        let remainder = fee.to_sat() - shares.iter().sum::<u64>();
+        *shares.first_mut()? += remainder;
+        let values = self
+            .outputs
+            .iter()
+            .zip(shares)
+            .map(|(&i, share)| {
+                let output = &outputs[i];
+                let value = output.value.checked_sub(Amount::from_sat(share))?;
+                (value >= output.script_pubkey().minimal_non_dust()).then_some(value)
+            })
+            .collect::<Option<Vec<_>>>()?;
+        for (&i, value) in self.outputs.iter().zip(values) {
+            outputs[i].value = value;
+        }
+        Some(())
+    }
+}
+
 impl OriginalTxStats {
     /// Return the [`FeeRate`] of the original tx.
     pub fn feerate(&self) -> FeeRate {
@@ -114,14 +367,33 @@ impl RbfParams {
     {
         Self {
             original_txs: tx_to_replace.into_iter().map(Into::into).collect(),
-            incremental_relay_feerate: FeeRate::from_sat_per_vb_unchecked(1),
+            descendants: Vec::new(),
+            incremental_relay_feerate: FeeRate::from_sat_per_vb_u32(1),
         }
     }
 
+    /// Add the unconfirmed descendants of the original txs.
+    pub fn with_descendants<I>(mut self, descendants: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<OriginalTxStats>,
+    {
+        self.descendants
+            .extend(descendants.into_iter().map(Into::into));
+        self
+    }
+
     /// To coin select `Replace` params.
+    ///
+    /// The replacement must pay for the fees of both the original txs and their descendants.
     pub fn to_cs_replace(&self) -> Replace {
         Replace {
-            fee: self.original_txs.iter().map(|otx| otx.fee.to_sat()).sum(),
+            fee: self
+                .original_txs
+                .iter()
+                .chain(&self.descendants)
+                .map(|otx| otx.fee.to_sat())
+                .sum(),
             incremental_relay_feerate: cs_feerate(self.incremental_relay_feerate),
         }
     }
@@ -138,7 +410,23 @@ impl RbfParams {
     }
 }
 
+impl CpfpParams {
+    /// The additional fee the child must pay to bring the ancestor package up to `feerate`.
+    pub fn bump_fee(&self, feerate: FeeRate) -> Amount {
+        package_bump_fee(feerate, self.package_weight, self.package_fee)
+    }
+}
+
 impl SelectorParams {
+    /// Create a [`SelectorParamsBuilder`] which checks the params against mempool policy.
+    pub fn builder(
+        change_script: ScriptSource,
+        change_policy: ChangePolicyType,
+        change_weight: DrainWeights,
+    ) -> SelectorParamsBuilder {
+        SelectorParamsBuilder::new(change_script, change_policy, change_weight)
+    }
+
     /// With default params.
     pub fn new(
         target_feerate: bitcoin::FeeRate,
@@ -153,7 +441,36 @@ impl SelectorParams {
             change_script,
             change_policy,
             change_weight,
+            change_split: None,
+            subtract_fee: None,
+            sweep_to: None,
             replace: None,
+            cpfp: None,
+            truc: false,
+        }
+    }
+
+    /// Weights of the change output(s) plus the future weight to spend them.
+    ///
+    /// In sweep mode, this is the weight of the sweep output. The future spend weight is not
+    /// counted as we do not know how the output will be spent.
+    pub fn drain_weights(&self) -> DrainWeights {
+        if let Some(sweep_to) = &self.sweep_to {
+            let txout = bitcoin::TxOut {
+                value: Amount::ZERO,
+                script_pubkey: sweep_to.script(),
+            };
+            return DrainWeights {
+                output_weight: txout.weight().to_wu(),
+                spend_weight: 0,
+                n_outputs: 1,
+            };
+        }
+        match &self.change_split {
+            Some(split) => {
+                split.drain_weights(&self.change_script, self.change_weight.spend_weight)
+            }
+            None => self.change_weight,
         }
     }
 
@@ -163,16 +480,21 @@ impl SelectorParams {
             .replace
             .as_ref()
             .map_or(FeeRate::ZERO, |r| r.max_feerate());
+        let mut outputs = TargetOutputs::fund_outputs(
+            self.target_outputs
+                .iter()
+                .map(|output| (output.txout().weight().to_wu(), output.value.to_sat())),
+        );
+        // The fee needed to bump the ancestors is funded in the same way as an output value.
+        if let Some(cpfp) = &self.cpfp {
+            outputs.value_sum += cpfp.bump_fee(self.target_feerate).to_sat();
+        }
         Target {
             fee: TargetFee {
                 rate: cs_feerate(self.target_feerate.max(feerate_lb)),
                 replace: self.replace.as_ref().map(|r| r.to_cs_replace()),
             },
-            outputs: TargetOutputs::fund_outputs(
-                self.target_outputs
-                    .iter()
-                    .map(|output| (output.txout().weight().to_wu(), output.value.to_sat())),
-            ),
+            outputs,
         }
     }
 
@@ -182,8 +504,15 @@ impl SelectorParams {
     ///
     /// Fails if `change_descriptor` cannot be satisfied.
     pub fn to_cs_change_policy(&self) -> Result<bdk_coin_select::ChangePolicy, miniscript::Error> {
-        let change_weights = self.change_weight;
-        let dust_value = self.change_script.script().minimal_non_dust().to_sat();
+        let change_weights = self.drain_weights();
+        if let Some(sweep_to) = &self.sweep_to {
+            let dust_value = sweep_to.script().minimal_non_dust().to_sat();
+            return Ok(ChangePolicy::min_value(change_weights, dust_value));
+        }
+        let dust_value = match &self.change_split {
+            Some(split) => split.min_value(&self.change_script).to_sat(),
+            None => self.change_script.script().minimal_non_dust().to_sat(),
+        };
         Ok(match self.change_policy {
             ChangePolicyType::NoDust => ChangePolicy::min_value(change_weights, dust_value),
             ChangePolicyType::NoDustAndLeastWaste { longterm_feerate } => {
@@ -198,6 +527,296 @@ impl SelectorParams {
     }
 }
 
+/// Maximum size of an OP_RETURN output script that is relayed by default (`-datacarriersize`).
+pub const MAX_OP_RETURN_RELAY: usize = 83;
+
+/// Maximum number of keys in a bare multisig output that is considered standard.
+const MAX_STANDARD_BARE_MULTISIG_KEYS: usize = 3;
+
+/// Builder for [`SelectorParams`] that checks the parameters against mempool policy.
+///
+/// [`build`](Self::build) errors on:
+/// * Recipient outputs that are dust, except for a single pay-to-anchor (P2A) output which may be
+///   ephemeral dust. Ephemeral dust is only relayed in a TRUC tx which pays no fee, so it requires
+///   [`truc`](Self::truc), a zero target feerate and no replacement.
+/// * More than one OP_RETURN output.
+/// * OP_RETURN outputs larger than [`MAX_OP_RETURN_RELAY`].
+/// * Output scripts of a non-standard type.
+/// * A target feerate below the minimum relay feerate, unless the tx has ephemeral dust.
+#[derive(Debug, Clone)]
+pub struct SelectorParamsBuilder {
+    target_feerate: FeeRate,
+    target_outputs: Vec<Output>,
+    change_script: ScriptSource,
+    change_policy: ChangePolicyType,
+    change_weight: DrainWeights,
+    change_split: Option<ChangeSplit>,
+    subtract_fee: Option<SubtractFee>,
+    sweep_to: Option<ScriptSource>,
+    replace: Option<RbfParams>,
+    cpfp: Option<CpfpParams>,
+    truc: bool,
+    min_relay_feerate: FeeRate,
+}
+
+/// Occurs when [`SelectorParamsBuilder`] parameters do not satisfy mempool policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SelectorParamsError {
+    /// The target output at `index` is dust.
+    DustOutput {
+        /// Index of the target output.
+        index: usize,
+        /// Value of the output.
+        value: Amount,
+        /// The minimal non-dust value for the output script.
+        min_non_dust: Amount,
+    },
+    /// There is more than one OP_RETURN output.
+    MultipleOpReturn,
+    /// There is more than one pay-to-anchor (P2A) output below the dust limit.
+    MultipleEphemeralDust,
+    /// The tx has ephemeral dust but is not a zero-fee TRUC tx.
+    EphemeralDustWithFee,
+    /// The OP_RETURN output at `index` exceeds [`MAX_OP_RETURN_RELAY`].
+    OpReturnTooLarge {
+        /// Index of the target output.
+        index: usize,
+        /// Size of the output script.
+        size: usize,
+    },
+    /// The target output at `index` has a non-standard script type.
+    NonStandardScript {
+        /// Index of the target output.
+        index: usize,
+    },
+    /// The target feerate is below the minimum relay feerate.
+    FeerateBelowMinRelay {
+        /// Target feerate.
+        feerate: FeeRate,
+        /// Minimum relay feerate.
+        min_relay_feerate: FeeRate,
+    },
+}
+
+impl fmt::Display for SelectorParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DustOutput {
+                index,
+                value,
+                min_non_dust,
+            } => write!(
+                f,
+                "output {index} is dust: value {value} is below {min_non_dust}"
+            ),
+            Self::MultipleOpReturn => write!(f, "more than one OP_RETURN output"),
+            Self::MultipleEphemeralDust => write!(f, "more than one ephemeral dust P2A output"),
+            Self::EphemeralDustWithFee => {
+                write!(f, "ephemeral dust requires a zero-fee TRUC tx")
+            }
+            Self::OpReturnTooLarge { index, size } => write!(
+                f,
+                "OP_RETURN output {index} of {size} bytes exceeds {MAX_OP_RETURN_RELAY} bytes"
+            ),
+            Self::NonStandardScript { index } => {
+                write!(f, "output {index} has a non-standard script")
+            }
+            Self::FeerateBelowMinRelay {
+                feerate,
+                min_relay_feerate,
+            } => write!(
+                f,
+                "feerate {} sat/kwu is below the minimum relay feerate of {} sat/kwu",
+                feerate.to_sat_per_kwu(),
+                min_relay_feerate.to_sat_per_kwu()
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SelectorParamsError {}
+
+/// Whether `script` is of a standard output script type.
+fn is_standard_script(script: &bitcoin::Script) -> bool {
+    if script.is_p2pkh()
+        || script.is_p2sh()
+        || script.is_p2wpkh()
+        || script.is_p2wsh()
+        || script.is_p2tr()
+        || script.is_p2pk()
+        || script.is_op_return()
+    {
+        return true;
+    }
+    if script.is_multisig() {
+        let key_count = script
+            .instructions()
+            .filter(|inst| matches!(inst, Ok(bitcoin::script::Instruction::PushBytes(_))))
+            .count();
+        return key_count <= MAX_STANDARD_BARE_MULTISIG_KEYS;
+    }
+    // Unknown witness versions are standard to allow for future soft forks.
+    matches!(script.witness_version(), Some(version) if version != bitcoin::WitnessVersion::V0)
+}
+
+impl SelectorParamsBuilder {
+    /// Create a builder with the given change parameters.
+    ///
+    /// The target feerate and minimum relay feerate default to [`FeeRate::BROADCAST_MIN`].
+    pub fn new(
+        change_script: ScriptSource,
+        change_policy: ChangePolicyType,
+        change_weight: DrainWeights,
+    ) -> Self {
+        Self {
+            target_feerate: FeeRate::BROADCAST_MIN,
+            target_outputs: Vec::new(),
+            change_script,
+            change_policy,
+            change_weight,
+            change_split: None,
+            subtract_fee: None,
+            sweep_to: None,
+            replace: None,
+            cpfp: None,
+            truc: false,
+            min_relay_feerate: FeeRate::BROADCAST_MIN,
+        }
+    }
+
+    /// Set the target feerate.
+    pub fn target_feerate(mut self, feerate: FeeRate) -> Self {
+        self.target_feerate = feerate;
+        self
+    }
+
+    /// Add a target output.
+    pub fn add_output(mut self, output: impl Into<Output>) -> Self {
+        self.target_outputs.push(output.into());
+        self
+    }
+
+    /// Add target outputs.
+    pub fn add_outputs<O>(mut self, outputs: impl IntoIterator<Item = O>) -> Self
+    where
+        O: Into<Output>,
+    {
+        self.target_outputs
+            .extend(outputs.into_iter().map(Into::into));
+        self
+    }
+
+    /// Split the change across multiple outputs.
+    pub fn change_split(mut self, change_split: ChangeSplit) -> Self {
+        self.change_split = Some(change_split);
+        self
+    }
+
+    /// Subtract the fee from the target outputs.
+    pub fn subtract_fee(mut self, subtract_fee: SubtractFee) -> Self {
+        self.subtract_fee = Some(subtract_fee);
+        self
+    }
+
+    /// Sweep all candidates to `sweep_to`, minus fees.
+    pub fn sweep_to(mut self, sweep_to: ScriptSource) -> Self {
+        self.sweep_to = Some(sweep_to);
+        self
+    }
+
+    /// Set params for replacing tx(s).
+    pub fn replace(mut self, replace: RbfParams) -> Self {
+        self.replace = Some(replace);
+        self
+    }
+
+    /// Set params for bumping unconfirmed parent tx(s).
+    pub fn cpfp(mut self, cpfp: CpfpParams) -> Self {
+        self.cpfp = Some(cpfp);
+        self
+    }
+
+    /// Create a TRUC (version 3) tx.
+    pub fn truc(mut self) -> Self {
+        self.truc = true;
+        self
+    }
+
+    /// Set the minimum relay feerate, default is [`FeeRate::BROADCAST_MIN`].
+    pub fn min_relay_feerate(mut self, feerate: FeeRate) -> Self {
+        self.min_relay_feerate = feerate;
+        self
+    }
+
+    /// Check the parameters against mempool policy and build [`SelectorParams`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the first [`SelectorParamsError`] encountered.
+    pub fn build(self) -> Result<SelectorParams, SelectorParamsError> {
+        let mut has_op_return = false;
+        let mut has_ephemeral_dust = false;
+        for (index, output) in self.target_outputs.iter().enumerate() {
+            let script = output.script_pubkey();
+            if !is_standard_script(&script) {
+                return Err(SelectorParamsError::NonStandardScript { index });
+            }
+            if script.is_op_return() {
+                if has_op_return {
+                    return Err(SelectorParamsError::MultipleOpReturn);
+                }
+                has_op_return = true;
+                if script.len() > MAX_OP_RETURN_RELAY {
+                    return Err(SelectorParamsError::OpReturnTooLarge {
+                        index,
+                        size: script.len(),
+                    });
+                }
+                continue;
+            }
+            let min_non_dust = script.minimal_non_dust();
+            if output.is_p2a() && output.value < min_non_dust {
+                if has_ephemeral_dust {
+                    return Err(SelectorParamsError::MultipleEphemeralDust);
+                }
+                has_ephemeral_dust = true;
+                continue;
+            }
+            if output.value < min_non_dust {
+                return Err(SelectorParamsError::DustOutput {
+                    index,
+                    value: output.value,
+                    min_non_dust,
+                });
+            }
+        }
+        if has_ephemeral_dust {
+            if !self.truc || self.target_feerate != FeeRate::ZERO || self.replace.is_some() {
+                return Err(SelectorParamsError::EphemeralDustWithFee);
+            }
+        } else if self.target_feerate < self.min_relay_feerate {
+            return Err(SelectorParamsError::FeerateBelowMinRelay {
+                feerate: self.target_feerate,
+                min_relay_feerate: self.min_relay_feerate,
+            });
+        }
+        Ok(SelectorParams {
+            target_feerate: self.target_feerate,
+            target_outputs: self.target_outputs,
+            change_script: self.change_script,
+            change_policy: self.change_policy,
+            change_weight: self.change_weight,
+            change_split: self.change_split,
+            subtract_fee: self.subtract_fee,
+            sweep_to: self.sweep_to,
+            replace: self.replace,
+            cpfp: self.cpfp,
+            truc: self.truc,
+        })
+    }
+}
+
 /// Error when the selection is impossible with the input candidates
 #[derive(Debug)]
 pub struct CannotMeetTarget;
@@ -221,6 +840,10 @@ pub enum SelectorError {
     Miniscript(miniscript::Error),
     /// meeting the target is not possible
     CannotMeetTarget(CannotMeetTarget),
+    /// the change split has a zero ratio
+    InvalidChangeSplit,
+    /// the outputs to subtract the fee from are empty, duplicated or out of range
+    InvalidSubtractFee,
 }
 
 impl fmt::Display for SelectorError {
@@ -228,6 +851,13 @@ impl fmt::Display for SelectorError {
         match self {
             Self::Miniscript(err) => write!(f, "{err}"),
             Self::CannotMeetTarget(err) => write!(f, "{err}"),
+            Self::InvalidChangeSplit => write!(f, "change split has a zero ratio"),
+            Self::InvalidSubtractFee => {
+                write!(
+                    f,
+                    "outputs to subtract fee from are empty, duplicated or out of range"
+                )
+            }
         }
     }
 }
@@ -238,25 +868,63 @@ impl std::error::Error for SelectorError {}
 impl<'c> Selector<'c> {
     /// Create new input selector.
     ///
+    /// In sweep mode (see [`SelectorParams::sweep_to`]), all candidates are selected.
+    ///
     /// # Errors
     ///
     /// - If we are unable to create a change policy from the `params`.
+    /// - If the change split of the `params` is invalid.
+    /// - If the outputs to subtract the fee from are invalid.
     /// - If the target is unreachable given the total input value.
     pub fn new(
         candidates: &'c InputCandidates,
-        params: SelectorParams,
+        mut params: SelectorParams,
     ) -> Result<Self, SelectorError> {
-        let target = params.to_cs_target();
+        if !params
+            .change_split
+            .as_ref()
+            .map_or(true, ChangeSplit::is_valid)
+        {
+            return Err(SelectorError::InvalidChangeSplit);
+        }
+        let sweep = params.sweep_to.is_some();
+        if let Some(sweep_to) = params.sweep_to.clone() {
+            params.change_script = sweep_to;
+            params.change_split = None;
+            params.subtract_fee = None;
+        }
+        if !params
+            .subtract_fee
+            .as_ref()
+            .map_or(true, |sf| sf.is_valid(params.target_outputs.len()))
+        {
+            return Err(SelectorError::InvalidSubtractFee);
+        }
         let change_policy = params
             .to_cs_change_policy()
             .map_err(SelectorError::Miniscript)?;
+        let mut target = params.to_cs_target();
+        // When subtracting the fee from outputs, we only need to fund the outputs. The fee is
+        // determined and subtracted when finalizing.
+        let subtract_fee = params.subtract_fee.map(|subtract_fee| {
+            let fee_target = target;
+            target.fee = TargetFee {
+                rate: bdk_coin_select::FeeRate::ZERO,
+                replace: None,
+            };
+            (subtract_fee, fee_target)
+        });
         let target_outputs = params.target_outputs;
         let change_script = params.change_script;
+        let change_split = params.change_split;
+        let truc = params.truc;
         if target.value() > candidates.groups().map(|grp| grp.value().to_sat()).sum() {
             return Err(SelectorError::CannotMeetTarget(CannotMeetTarget));
         }
         let mut inner = bdk_coin_select::CoinSelector::new(candidates.coin_select_candidates());
-        if candidates.must_select().is_some() {
+        if sweep {
+            inner.select_all();
+        } else if candidates.must_select().is_some() {
             inner.select_next();
         }
         Ok(Self {
@@ -265,6 +933,10 @@ impl<'c> Selector<'c> {
             target_outputs,
             change_policy,
             change_script,
+            change_split,
+            subtract_fee,
+            sweep,
+            truc,
             inner,
         })
     }
@@ -289,6 +961,16 @@ impl<'c> Selector<'c> {
         self.change_policy
     }
 
+    /// Whether we are sweeping all candidates, see [`SelectorParams::sweep_to`].
+    pub fn is_sweep(&self) -> bool {
+        self.sweep
+    }
+
+    /// Whether we are creating a TRUC (version 3) tx.
+    pub fn is_truc(&self) -> bool {
+        self.truc
+    }
+
     /// Select with the provided `algorithm`.
     pub fn select_with_algorithm<F, E>(&mut self, mut algorithm: F) -> Result<(), E>
     where
@@ -303,33 +985,140 @@ impl<'c> Selector<'c> {
     }
 
     /// Select in order until target is met.
+    ///
+    /// When creating a TRUC tx, candidates which would make the tx exceed the TRUC size limit
+    /// are skipped, see [`max_truc_weight`](Self::max_truc_weight).
     pub fn select_until_target_met(&mut self) -> Result<(), InsufficientFunds> {
-        self.inner.select_until_target_met(self.target)
+        if !self.truc {
+            return self.inner.select_until_target_met(self.target);
+        }
+        let target = self.selection_target();
+        if self.inner.is_target_met(target) {
+            return Ok(());
+        }
+        let unselected = self.inner.unselected_indices().collect::<Vec<_>>();
+        for index in unselected {
+            self.inner.select(index);
+            if self.weight_with_change() > self.max_truc_weight() {
+                self.inner.deselect(index);
+                continue;
+            }
+            if self.inner.is_target_met(self.selection_target()) {
+                return Ok(());
+            }
+        }
+        Err(InsufficientFunds {
+            missing: self
+                .inner
+                .excess(self.selection_target(), Drain::NONE)
+                .unsigned_abs(),
+        })
+    }
+
+    /// Maximum weight of the TRUC tx with the current selection.
+    ///
+    /// A TRUC tx which spends an unconfirmed output is limited to [`TRUC_CHILD_MAX_VSIZE`],
+    /// otherwise to [`TRUC_MAX_VSIZE`].
+    pub fn max_truc_weight(&self) -> Weight {
+        let groups = self.candidates.groups().collect::<Vec<_>>();
+        let has_unconfirmed_parent = self
+            .inner
+            .apply_selection(&groups)
+            .any(|group| group.any(|input| input.status().is_none()));
+        let max_vsize = if has_unconfirmed_parent {
+            TRUC_CHILD_MAX_VSIZE
+        } else {
+            TRUC_MAX_VSIZE
+        };
+        Weight::from_vb_unchecked(max_vsize)
+    }
+
+    /// Weight of the tx with the current selection, assuming it has change.
+    fn weight_with_change(&self) -> Weight {
+        Weight::from_wu(
+            self.inner
+                .weight(self.target.outputs, self.change_policy.drain_weights),
+        )
     }
 
     /// Whether we added the change output to the selection.
     ///
     /// Return `None` if target is not met yet.
     pub fn has_change(&self) -> Option<bool> {
-        if !self.inner.is_target_met(self.target) {
+        let target = self.selection_target();
+        if !self.inner.is_target_met(target) {
             return None;
         }
-        let has_drain = self
-            .inner
-            .drain_value(self.target, self.change_policy)
-            .is_some();
+        let has_drain = self.inner.drain_value(target, self.change_policy).is_some();
         Some(has_drain)
     }
 
+    /// The [`target`](Self::target) of the current selection.
+    ///
+    /// With [`InputCandidates::bump_ancestors`], each group pays for bumping all of its
+    /// ancestors. Ancestors shared by the selected groups only need to be bumped once, so the
+    /// overpaid amount is returned to the selection by lowering the target.
+    fn selection_target(&self) -> Target {
+        let mut target = self.target;
+        let feerate = match self.candidates.ancestor_feerate() {
+            Some(feerate) => feerate,
+            None => return target,
+        };
+        let groups = self.candidates.groups().collect::<Vec<_>>();
+        let mut package = AncestorPackage::default();
+        let mut charged = Amount::ZERO;
+        for group in self.inner.apply_selection(&groups) {
+            package.merge(&group.ancestors());
+            charged += group.ancestor_bump_fee(feerate);
+        }
+        if package.is_complete() {
+            let overpaid = charged
+                .checked_sub(package.bump_fee(feerate))
+                .unwrap_or(Amount::ZERO);
+            target.outputs.value_sum = target.outputs.value_sum.saturating_sub(overpaid.to_sat());
+        }
+        target
+    }
+
     /// Try get final selection.
     ///
-    /// Return `None` if target is not met yet.
+    /// Return `None` if target is not met yet, if subtracting the fee leaves an output as dust,
+    /// if the sweep output would be dust, or if a TRUC selection breaks the TRUC rules (see
+    /// [`Selection::check_truc`]).
     pub fn try_finalize(&self) -> Option<Selection> {
-        if !self.inner.is_target_met(self.target) {
+        let selection = self.finalize()?;
+        if self.truc && selection.check_truc().is_err() {
+            return None;
+        }
+        Some(selection)
+    }
+
+    /// Final selection without checking the TRUC rules.
+    pub(crate) fn finalize(&self) -> Option<Selection> {
+        let target = self.selection_target();
+        if !self.inner.is_target_met(target) {
+            return None;
+        }
+        let maybe_change = self.inner.drain(target, self.change_policy);
+        if self.sweep && maybe_change.is_none() {
             return None;
         }
-        let maybe_change = self.inner.drain(self.target, self.change_policy);
         let to_apply = self.candidates.groups().collect::<Vec<_>>();
+        let mut outputs = self.target_outputs.clone();
+        if maybe_change.is_some() {
+            let change_value = Amount::from_sat(maybe_change.value);
+            match &self.change_split {
+                Some(split) => outputs.extend(split.split(&self.change_script, change_value)),
+                None => outputs.push(Output::from((self.change_script.clone(), change_value))),
+            }
+        }
+        if let Some((subtract_fee, fee_target)) = &self.subtract_fee {
+            let fee = self
+                .inner
+                .implied_fee(*fee_target, maybe_change.weights)
+                .saturating_sub(self.inner.excess(target, maybe_change).max(0) as u64);
+            subtract_fee.apply(&mut outputs, Amount::from_sat(fee))?;
+        }
         Some(Selection {
             inputs: self
                 .inner
@@ -338,16 +1127,434 @@ impl<'c> Selector<'c> {
                 .flat_map(InputGroup::inputs)
                 .cloned()
                 .collect(),
-            outputs: {
-                let mut outputs = self.target_outputs.clone();
-                if maybe_change.is_some() {
-                    outputs.push(Output::from((
-                        self.change_script.clone(),
-                        Amount::from_sat(maybe_change.value),
-                    )));
-                }
-                outputs
+            change_indices: if self.sweep {
+                Vec::new()
+            } else {
+                (self.target_outputs.len()..outputs.len()).collect()
             },
+            outputs,
+            truc: self.truc,
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::{psbt, OutPoint, ScriptBuf, Sequence, TxOut, Txid, WPubkeyHash};
+
+    use crate::Input;
+
+    fn spk() -> ScriptBuf {
+        ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros())
+    }
+
+    fn spk_n(n: u8) -> ScriptBuf {
+        ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([n; 20]))
+    }
+
+    fn input(vout: u32, value: Amount) -> Input {
+        let psbt_input = psbt::Input {
+            witness_utxo: Some(TxOut {
+                value,
+                script_pubkey: spk(),
+            }),
+            ..Default::default()
+        };
+        Input::from_psbt_input(
+            OutPoint::new(Txid::all_zeros(), vout),
+            Sequence::ENABLE_RBF_NO_LOCKTIME,
+            psbt_input,
+            108,
+            None,
+            false,
+        )
+        .unwrap()
+    }
+
+    fn builder() -> SelectorParamsBuilder {
+        SelectorParams::builder(
+            ScriptSource::from_script(spk()),
+            ChangePolicyType::NoDust,
+            DrainWeights::TR_KEYSPEND,
+        )
+    }
+
+    #[test]
+    fn builder_checks_dust() {
+        let dust = spk().minimal_non_dust();
+        let res = builder()
+            .add_output((spk(), dust - Amount::ONE_SAT))
+            .build();
+        assert!(matches!(
+            res,
+            Err(SelectorParamsError::DustOutput { index: 0, .. })
+        ));
+        assert!(builder().add_output((spk(), dust)).build().is_ok());
+    }
+
+    #[test]
+    fn builder_checks_op_return() {
+        let op_return = |len: usize| {
+            let data = bitcoin::script::PushBytesBuf::try_from(vec![0x90; len]).unwrap();
+            (ScriptBuf::new_op_return(data), Amount::ZERO)
+        };
+        assert!(builder().add_output(op_return(80)).build().is_ok());
+        assert!(matches!(
+            builder().add_output(op_return(81)).build(),
+            Err(SelectorParamsError::OpReturnTooLarge { index: 0, .. })
+        ));
+        assert!(matches!(
+            builder().add_outputs([op_return(8), op_return(8)]).build(),
+            Err(SelectorParamsError::MultipleOpReturn)
+        ));
+    }
+
+    #[test]
+    fn builder_checks_script_type_and_feerate() {
+        let nonstandard = ScriptBuf::from_bytes(vec![0x51]);
+        assert!(matches!(
+            builder()
+                .add_output((spk(), Amount::ONE_BTC))
+                .add_output((nonstandard, Amount::ONE_BTC))
+                .build(),
+            Err(SelectorParamsError::NonStandardScript { index: 1 })
+        ));
+        assert!(matches!(
+            builder()
+                .target_feerate(FeeRate::from_sat_per_kwu(200))
+                .build(),
+            Err(SelectorParamsError::FeerateBelowMinRelay { .. })
+        ));
+        assert!(builder()
+            .target_feerate(FeeRate::from_sat_per_kwu(200))
+            .min_relay_feerate(FeeRate::ZERO)
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn builder_allows_ephemeral_dust_anchor() {
+        let zero_fee_truc = || builder().truc().target_feerate(FeeRate::ZERO);
+        assert!(zero_fee_truc()
+            .add_output((spk(), Amount::from_sat(10_000)))
+            .add_output(Output::p2a(Amount::ZERO))
+            .build()
+            .is_ok());
+        assert_eq!(
+            zero_fee_truc()
+                .add_outputs([Output::p2a(Amount::ZERO), Output::p2a(Amount::ZERO)])
+                .build()
+                .unwrap_err(),
+            SelectorParamsError::MultipleEphemeralDust
+        );
+    }
+
+    #[test]
+    fn builder_rejects_ephemeral_dust_with_fee() {
+        // the default target feerate is non-zero
+        assert_eq!(
+            builder()
+                .truc()
+                .add_output(Output::p2a(Amount::ZERO))
+                .build()
+                .unwrap_err(),
+            SelectorParamsError::EphemeralDustWithFee
+        );
+        assert_eq!(
+            builder()
+                .target_feerate(FeeRate::ZERO)
+                .add_output(Output::p2a(Amount::ZERO))
+                .build()
+                .unwrap_err(),
+            SelectorParamsError::EphemeralDustWithFee
+        );
+        // a p2a output above the dust limit is not ephemeral dust
+        assert!(builder()
+            .add_output(Output::p2a(Amount::from_sat(240)))
+            .build()
+            .is_ok());
+    }
+
+    fn select_all_with_split(value: Amount, split: ChangeSplit) -> (Selection, Amount) {
+        let candidates = InputCandidates::new([], [input(0, value)]);
+        let params = builder()
+            .target_feerate(FeeRate::from_sat_per_vb_u32(2))
+            .add_output((spk(), Amount::from_sat(10_000)))
+            .change_split(split)
+            .build()
+            .unwrap();
+        let drain_weights = params.drain_weights();
+        let mut selector = Selector::new(&candidates, params).unwrap();
+        selector.select_all();
+        let selection = selector.try_finalize().unwrap();
+        let weight = selector
+            .inner()
+            .weight(selector.target().outputs, drain_weights);
+        let min_fee = FeeRate::from_sat_per_vb_u32(2) * Weight::from_wu(weight);
+        (selection, min_fee)
+    }
+
+    #[test]
+    fn change_split_even() {
+        let split = ChangeSplit::Even(vec![
+            ScriptSource::from_script(spk_n(1)),
+            ScriptSource::from_script(spk_n(2)),
+        ]);
+        let (selection, min_fee) = select_all_with_split(Amount::from_sat(100_000), split);
+        assert_eq!(selection.outputs.len(), 4);
+        let change = selection.outputs[1..]
+            .iter()
+            .map(|output| output.value)
+            .collect::<Vec<_>>();
+        let max = change.iter().max().unwrap().to_sat();
+        let min = change.iter().min().unwrap().to_sat();
+        assert!(max - min < 3, "change must be of similar value: {change:?}");
+        let scripts = selection.outputs[1..]
+            .iter()
+            .map(Output::script_pubkey)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            scripts,
+            [spk(), spk_n(1), spk_n(2)],
+            "change_script comes first"
+        );
+        let output_sum: Amount = selection.outputs.iter().map(|output| output.value).sum();
+        let fee = Amount::from_sat(100_000) - output_sum;
+        assert!(fee >= min_fee, "fee {fee} must cover {min_fee}");
+    }
+
+    #[test]
+    fn change_split_ratios() {
+        let split = ChangeSplit::Ratios {
+            change_ratio: 1,
+            others: vec![(ScriptSource::from_script(spk_n(2)), 3)],
+        };
+        let (selection, _) = select_all_with_split(Amount::from_sat(100_000), split);
+        assert_eq!(selection.outputs.len(), 3);
+        let (a, b) = (selection.outputs[1].value, selection.outputs[2].value);
+        assert!(b.to_sat() - 3 * a.to_sat() < 4);
+    }
+
+    #[test]
+    fn change_split_respects_dust() {
+        let split = ChangeSplit::Ratios {
+            change_ratio: 1,
+            others: vec![(ScriptSource::from_script(spk_n(2)), 99)],
+        };
+        assert_eq!(
+            split.min_value(&ScriptSource::from_script(spk())),
+            spk().minimal_non_dust() * 100,
+            "smallest share must not be dust"
+        );
+        let (selection, _) = select_all_with_split(Amount::from_sat(20_000), split);
+        assert_eq!(selection.outputs.len(), 1, "change would be dust");
+
+        let candidates = InputCandidates::new([], [input(0, Amount::ONE_BTC)]);
+        let mut params = builder().build().unwrap();
+        params.change_split = Some(ChangeSplit::Ratios {
+            change_ratio: 0,
+            others: vec![(ScriptSource::from_script(spk_n(2)), 1)],
+        });
+        assert!(matches!(
+            Selector::new(&candidates, params),
+            Err(SelectorError::InvalidChangeSplit)
+        ));
+    }
+
+    fn select_with_subtract_fee(
+        inputs: Vec<Input>,
+        outputs: Vec<(ScriptBuf, Amount)>,
+        subtract_fee: SubtractFee,
+    ) -> (Selection, Amount, Amount) {
+        let candidates = InputCandidates::new([], inputs);
+        let params = builder()
+            .target_feerate(FeeRate::from_sat_per_vb_u32(2))
+            .add_outputs(outputs)
+            .subtract_fee(subtract_fee)
+            .build()
+            .unwrap();
+        let drain_weights = params.drain_weights();
+        let mut selector = Selector::new(&candidates, params).unwrap();
+        selector.select_until_target_met().unwrap();
+        let selection = selector.try_finalize().unwrap();
+        let drain_weights = if selection.change_indices.is_empty() {
+            DrainWeights::NONE
+        } else {
+            drain_weights
+        };
+        let weight = selector
+            .inner()
+            .weight(selector.target().outputs, drain_weights);
+        let min_fee = FeeRate::from_sat_per_vb_u32(2) * Weight::from_wu(weight);
+        let input_sum: Amount = selection
+            .inputs
+            .iter()
+            .map(|input| input.prev_txout().value)
+            .sum();
+        let output_sum: Amount = selection.outputs.iter().map(|output| output.value).sum();
+        (selection, input_sum - output_sum, min_fee)
+    }
+
+    #[test]
+    fn subtract_fee_from_single_output() {
+        let (selection, fee, min_fee) = select_with_subtract_fee(
+            vec![input(0, Amount::from_sat(100_000))],
+            vec![(spk_n(1), Amount::from_sat(50_000))],
+            SubtractFee::new([0], FeeSplit::Equal),
+        );
+        assert_eq!(selection.outputs.len(), 2);
+        assert_eq!(selection.change_indices, [1]);
+        assert_eq!(selection.outputs[1].value, Amount::from_sat(50_000));
+        assert_eq!(selection.outputs[0].value, Amount::from_sat(50_000) - fee);
+        assert!(fee >= min_fee, "fee {fee} must cover {min_fee}");
+    }
+
+    #[test]
+    fn subtract_fee_sweep() {
+        let (selection, fee, min_fee) = select_with_subtract_fee(
+            vec![
+                input(0, Amount::from_sat(30_000)),
+                input(1, Amount::from_sat(20_000)),
+            ],
+            vec![(spk_n(1), Amount::from_sat(50_000))],
+            SubtractFee::new([0], FeeSplit::Equal),
+        );
+        assert_eq!(selection.inputs.len(), 2);
+        assert_eq!(selection.outputs.len(), 1, "no change for a sweep");
+        assert_eq!(fee, min_fee);
+    }
+
+    #[test]
+    fn subtract_fee_proportional() {
+        let (selection, fee, _) = select_with_subtract_fee(
+            vec![input(0, Amount::from_sat(90_000))],
+            vec![
+                (spk_n(1), Amount::from_sat(30_000)),
+                (spk_n(2), Amount::from_sat(60_000)),
+            ],
+            SubtractFee::new([0, 1], FeeSplit::Proportional),
+        );
+        assert_eq!(selection.outputs.len(), 2);
+        let a = Amount::from_sat(30_000) - selection.outputs[0].value;
+        let b = Amount::from_sat(60_000) - selection.outputs[1].value;
+        assert_eq!(a + b, fee);
+        assert!(a.to_sat() * 2 - b.to_sat() < 3, "{a} and {b} must be 1:2");
+    }
+
+    #[test]
+    fn subtract_fee_respects_dust() {
+        let candidates = InputCandidates::new([], [input(0, Amount::from_sat(1_000))]);
+        let params = builder()
+            .add_output((spk_n(1), Amount::from_sat(1_000)))
+            .subtract_fee(SubtractFee::new([0], FeeSplit::Equal))
+            .target_feerate(FeeRate::from_sat_per_vb_u32(7))
+            .build()
+            .unwrap();
+        let mut selector = Selector::new(&candidates, params).unwrap();
+        selector.select_all();
+        assert!(selector.try_finalize().is_none(), "output would be dust");
+
+        for outputs in [vec![], vec![1], vec![0, 0]] {
+            let mut params = builder()
+                .add_output((spk_n(1), Amount::from_sat(1_000)))
+                .build()
+                .unwrap();
+            params.subtract_fee = Some(SubtractFee::new(outputs, FeeSplit::Equal));
+            assert!(matches!(
+                Selector::new(&candidates, params),
+                Err(SelectorError::InvalidSubtractFee)
+            ));
+        }
+    }
+
+    #[test]
+    fn subtract_fee_apply_is_atomic() {
+        let outputs = vec![
+            Output::with_script(spk_n(1), Amount::from_sat(1_000)),
+            Output::with_script(spk_n(2), Amount::from_sat(50_000)),
+        ];
+        let values = |outputs: &[Output]| outputs.iter().map(|o| o.value).collect::<Vec<_>>();
+
+        let mut changed = outputs.clone();
+        assert!(SubtractFee::new([1, 0], FeeSplit::Equal)
+            .apply(&mut changed, Amount::from_sat(2_000))
+            .is_none());
+        assert_eq!(values(&changed), values(&outputs), "output 0 would be dust");
+
+        assert!(SubtractFee::new([], FeeSplit::Equal)
+            .apply(&mut changed, Amount::from_sat(2_000))
+            .is_none());
+        assert!(SubtractFee::new([1, 2], FeeSplit::Equal)
+            .apply(&mut changed, Amount::from_sat(2_000))
+            .is_none());
+        assert_eq!(values(&changed), values(&outputs));
+
+        SubtractFee::new([1, 0], FeeSplit::Equal)
+            .apply(&mut changed, Amount::from_sat(501))
+            .unwrap();
+        assert_eq!(
+            values(&changed),
+            [Amount::from_sat(750), Amount::from_sat(49_749)]
+        );
+    }
+
+    #[test]
+    fn sweep_selects_all() {
+        let candidates = InputCandidates::new(
+            [input(0, Amount::from_sat(10_000))],
+            [
+                input(1, Amount::from_sat(20_000)),
+                input(2, Amount::from_sat(30_000)),
+            ],
+        )
+        .filter(crate::filter_outpoints([OutPoint::new(
+            Txid::all_zeros(),
+            1,
+        )]));
+        let params = builder()
+            .target_feerate(FeeRate::from_sat_per_vb_u32(2))
+            .sweep_to(ScriptSource::from_script(spk_n(1)))
+            .build()
+            .unwrap();
+        let drain_weights = params.drain_weights();
+        let selector = Selector::new(&candidates, params).unwrap();
+        assert!(selector.is_sweep());
+        let selection = selector.try_finalize().unwrap();
+        let inputs = selection
+            .inputs
+            .iter()
+            .map(|input| input.prev_outpoint().vout)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            inputs,
+            [0, 1],
+            "must select and filtered-out candidates are excluded"
+        );
+        assert_eq!(selection.outputs.len(), 1);
+        assert!(selection.change_indices.is_empty());
+        assert_eq!(selection.outputs[0].script_pubkey(), spk_n(1));
+        let weight = selector
+            .inner()
+            .weight(selector.target().outputs, drain_weights);
+        let fee = FeeRate::from_sat_per_vb_u32(2) * Weight::from_wu(weight);
+        assert_eq!(selection.outputs[0].value, Amount::from_sat(30_000) - fee);
+    }
+
+    #[test]
+    fn sweep_respects_dust() {
+        let candidates = InputCandidates::new([], [input(0, Amount::from_sat(1_000))]);
+        let params = builder()
+            .target_feerate(FeeRate::from_sat_per_vb_u32(7))
+            .sweep_to(ScriptSource::from_script(spk_n(1)))
+            .build()
+            .unwrap();
+        let selector = Selector::new(&candidates, params).unwrap();
+        assert!(
+            selector.try_finalize().is_none(),
+            "sweep output would be dust"
+        );
+    }
+}
@@ -0,0 +1,795 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Display;
+use core::str::FromStr;
+
+use bitcoin::bech32::primitives::decode::{CheckedHrpstring, CheckedHrpstringError};
+use bitcoin::bech32::{Bech32m, ByteIterExt, Fe32, Fe32IterExt, Hrp};
+use bitcoin::consensus::Encodable;
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::key::{Keypair, Parity, TapTweak, TweakedPublicKey};
+use bitcoin::psbt::{GetKey, GetKeyError, KeyRequest};
+use bitcoin::secp256k1::{self, PublicKey, Scalar, Secp256k1, SecretKey};
+use bitcoin::{NetworkKind, OutPoint, ScriptBuf};
+use miniscript::bitcoin;
+
+use crate::{Input, ScriptSource, Selection, Signer};
+
+const INPUTS_TAG: &str = "BIP0352/Inputs";
+const SHARED_SECRET_TAG: &str = "BIP0352/SharedSecret";
+/// BIP-341 NUMS point H, which has no known private key.
+const NUMS_H: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+/// A BIP-352 silent payment address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SilentPaymentAddress {
+    /// Network kind, which determines the human readable part (`sp` or `tsp`).
+    pub network: NetworkKind,
+    /// Scan public key.
+    pub scan: PublicKey,
+    /// Spend public key.
+    pub spend: PublicKey,
+}
+
+impl SilentPaymentAddress {
+    /// From the scan and spend public keys.
+    pub fn from_keys(network: NetworkKind, scan: PublicKey, spend: PublicKey) -> Self {
+        Self {
+            network,
+            scan,
+            spend,
+        }
+    }
+
+    /// P2TR script pubkey of the untweaked spend key.
+    ///
+    /// This only stands in for the real output (of the same weight) until the output is resolved
+    /// with [`Selection::resolve_silent_payments`]. It must never be broadcast.
+    pub fn placeholder_script(&self) -> ScriptBuf {
+        let (spend, _) = self.spend.x_only_public_key();
+        ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(spend))
+    }
+
+    fn hrp(&self) -> Hrp {
+        match self.network {
+            NetworkKind::Main => Hrp::parse_unchecked("sp"),
+            NetworkKind::Test => Hrp::parse_unchecked("tsp"),
+        }
+    }
+}
+
+impl Display for SilentPaymentAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let hrp = self.hrp();
+        let data = self
+            .scan
+            .serialize()
+            .into_iter()
+            .chain(self.spend.serialize())
+            .collect::<Vec<u8>>();
+        for c in data
+            .into_iter()
+            .bytes_to_fes()
+            .with_checksum::<Bech32m>(&hrp)
+            .with_witness_version(Fe32::Q)
+            .chars()
+        {
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+/// Occurs when parsing a [`SilentPaymentAddress`] fails.
+#[derive(Debug)]
+pub enum ParseSilentPaymentAddressError {
+    /// Invalid bech32m encoding.
+    Bech32(CheckedHrpstringError),
+    /// The human readable part is neither `sp` nor `tsp`.
+    UnknownHrp(String),
+    /// Missing or unsupported version.
+    InvalidVersion,
+    /// The payload is not two 33-byte public keys.
+    InvalidLength(usize),
+    /// Invalid public key.
+    InvalidKey(secp256k1::Error),
+}
+
+impl Display for ParseSilentPaymentAddressError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Bech32(e) => Display::fmt(e, f),
+            Self::UnknownHrp(hrp) => write!(f, "unknown silent payment hrp {}", hrp),
+            Self::InvalidVersion => write!(f, "unsupported silent payment version"),
+            Self::InvalidLength(len) => {
+                write!(f, "silent payment payload must be 66 bytes, got {}", len)
+            }
+            Self::InvalidKey(e) => Display::fmt(e, f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseSilentPaymentAddressError {}
+
+impl FromStr for SilentPaymentAddress {
+    type Err = ParseSilentPaymentAddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut checked =
+            CheckedHrpstring::new::<Bech32m>(s).map_err(ParseSilentPaymentAddressError::Bech32)?;
+        let network = match checked.hrp().to_lowercase().as_str() {
+            "sp" => NetworkKind::Main,
+            "tsp" => NetworkKind::Test,
+            hrp => return Err(ParseSilentPaymentAddressError::UnknownHrp(hrp.into())),
+        };
+        let version = checked
+            .remove_witness_version()
+            .ok_or(ParseSilentPaymentAddressError::InvalidVersion)?;
+        let data = checked.byte_iter().collect::<Vec<u8>>();
+        // Version 31 is reserved for a backwards incompatible change. Other future versions must
+        // begin with the version 0 payload.
+        let payload = match version.to_u8() {
+            0 if data.len() == 66 => &data[..],
+            0 => return Err(ParseSilentPaymentAddressError::InvalidLength(data.len())),
+            31 => return Err(ParseSilentPaymentAddressError::InvalidVersion),
+            _ if data.len() >= 66 => &data[..66],
+            _ => return Err(ParseSilentPaymentAddressError::InvalidLength(data.len())),
+        };
+        let scan = PublicKey::from_slice(&payload[..33])
+            .map_err(ParseSilentPaymentAddressError::InvalidKey)?;
+        let spend = PublicKey::from_slice(&payload[33..])
+            .map_err(ParseSilentPaymentAddressError::InvalidKey)?;
+        Ok(Self::from_keys(network, scan, spend))
+    }
+}
+
+/// Occurs when resolving silent payment outputs fails.
+#[derive(Debug)]
+pub enum SilentPaymentError {
+    /// None of the selected inputs are eligible for silent payments.
+    NoEligibleInputs,
+    /// The private key of an eligible input is not known to the signer.
+    MissingKey(OutPoint),
+    /// The signer failed to provide a key.
+    GetKey(GetKeyError),
+    /// The input keys sum to zero, or a tweak is out of range.
+    Secp(secp256k1::Error),
+    /// An input spends a segwit output newer than version 1, which BIP-352 forbids.
+    UnsupportedWitnessVersion(OutPoint),
+}
+
+impl Display for SilentPaymentError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NoEligibleInputs => write!(f, "no inputs are eligible for silent payments"),
+            Self::MissingKey(op) => write!(f, "missing private key of input {}", op),
+            Self::GetKey(e) => Display::fmt(e, f),
+            Self::Secp(e) => Display::fmt(e, f),
+            Self::UnsupportedWitnessVersion(op) => write!(
+                f,
+                "input {} spends a segwit version not supported by silent payments",
+                op
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SilentPaymentError {}
+
+fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    for data in data {
+        engine.input(data);
+    }
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+fn scalar(bytes: [u8; 32]) -> Result<Scalar, SilentPaymentError> {
+    Scalar::from_be_bytes(bytes)
+        .map_err(|_| SilentPaymentError::Secp(secp256k1::Error::InvalidTweak))
+}
+
+fn request_key<C: secp256k1::Signing>(
+    signer: &Signer,
+    requests: impl IntoIterator<Item = KeyRequest>,
+    secp: &Secp256k1<C>,
+) -> Result<Option<SecretKey>, SilentPaymentError> {
+    for request in requests {
+        if let Some(prv) = signer
+            .get_key(request, secp)
+            .map_err(SilentPaymentError::GetKey)?
+        {
+            return Ok(Some(prv.inner));
+        }
+    }
+    Ok(None)
+}
+
+/// Private key of a taproot output key, negated if the output key has an odd y-coordinate.
+fn taproot_secret_key(keypair: &Keypair) -> SecretKey {
+    let sk = SecretKey::from_keypair(keypair);
+    match keypair.x_only_public_key().1 {
+        Parity::Even => sk,
+        Parity::Odd => sk.negate(),
+    }
+}
+
+/// BIP-352 output scripts for `addresses`, in order, of a tx which spends `outpoints` and whose
+/// eligible inputs have the private keys `input_keys`.
+///
+/// Outputs to the same scan key are numbered in the order they appear in `addresses`.
+fn silent_payment_scripts<C: secp256k1::Signing + secp256k1::Verification>(
+    input_keys: &[SecretKey],
+    outpoints: impl IntoIterator<Item = OutPoint>,
+    addresses: &[SilentPaymentAddress],
+    secp: &Secp256k1<C>,
+) -> Result<Vec<ScriptBuf>, SilentPaymentError> {
+    let mut input_key = Option::<SecretKey>::None;
+    for &sk in input_keys {
+        input_key = Some(match input_key {
+            Some(acc) => acc
+                .add_tweak(&sk.into())
+                .map_err(SilentPaymentError::Secp)?,
+            None => sk,
+        });
+    }
+    let input_key = input_key.ok_or(SilentPaymentError::NoEligibleInputs)?;
+
+    let smallest_outpoint = outpoints
+        .into_iter()
+        .map(|outpoint| {
+            let mut bytes = Vec::with_capacity(36);
+            outpoint
+                .consensus_encode(&mut bytes)
+                .expect("in-memory writer");
+            bytes
+        })
+        .min()
+        .expect("has eligible input");
+    let input_hash = tagged_hash(
+        INPUTS_TAG,
+        &[&smallest_outpoint, &input_key.public_key(secp).serialize()],
+    );
+    let input_key = input_key
+        .mul_tweak(&scalar(input_hash)?)
+        .map_err(SilentPaymentError::Secp)?;
+
+    let mut counts = Vec::<(PublicKey, u32)>::new();
+    let mut scripts = Vec::with_capacity(addresses.len());
+    for address in addresses {
+        let k = match counts.iter_mut().find(|(scan, _)| *scan == address.scan) {
+            Some((_, k)) => {
+                *k += 1;
+                *k
+            }
+            None => {
+                counts.push((address.scan, 0));
+                0
+            }
+        };
+        let shared_secret = address
+            .scan
+            .mul_tweak(secp, &input_key.into())
+            .map_err(SilentPaymentError::Secp)?;
+        let t_k = tagged_hash(
+            SHARED_SECRET_TAG,
+            &[&shared_secret.serialize(), &k.to_be_bytes()],
+        );
+        let (output_key, _) = address
+            .spend
+            .add_exp_tweak(secp, &scalar(t_k)?)
+            .map_err(SilentPaymentError::Secp)?
+            .x_only_public_key();
+        scripts.push(ScriptBuf::new_p2tr_tweaked(
+            TweakedPublicKey::dangerous_assume_tweaked(output_key),
+        ));
+    }
+    Ok(scripts)
+}
+
+/// Private key of `input` as used by BIP-352, or `None` if the input is not eligible.
+fn input_secret_key<C: secp256k1::Signing + secp256k1::Verification>(
+    input: &Input,
+    signer: &Signer,
+    secp: &Secp256k1<C>,
+) -> Result<Option<SecretKey>, SilentPaymentError> {
+    let spk = &input.prev_txout().script_pubkey;
+    let psbt_input = match (input.psbt_input(), input.plan()) {
+        (Some(psbt_input), _) => psbt_input.clone(),
+        (None, Some(plan)) => {
+            let mut psbt_input = bitcoin::psbt::Input::default();
+            plan.update_psbt_input(&mut psbt_input);
+            psbt_input
+        }
+        (None, None) => unreachable!("input must either have finalized psbt input or plan"),
+    };
+    let missing_key = || SilentPaymentError::MissingKey(input.prev_outpoint());
+
+    if spk.is_p2tr() {
+        let internal_key = psbt_input.tap_internal_key.ok_or_else(missing_key)?;
+        // The receiver skips script path spends which reveal the NUMS internal key.
+        if internal_key.serialize() == NUMS_H {
+            return Ok(None);
+        }
+        let requests = psbt_input
+            .tap_key_origins
+            .get(&internal_key)
+            .map(|(_, origin)| KeyRequest::Bip32(origin.clone()))
+            .into_iter()
+            .chain([KeyRequest::XOnlyPubkey(internal_key)]);
+        let sk = request_key(signer, requests, secp)?.ok_or_else(missing_key)?;
+        let keypair = Keypair::from_secret_key(secp, &sk)
+            .tap_tweak(secp, psbt_input.tap_merkle_root)
+            .to_keypair();
+        let (output_key, _) = keypair.x_only_public_key();
+        if ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(output_key))
+            != *spk
+        {
+            return Err(missing_key());
+        }
+        return Ok(Some(taproot_secret_key(&keypair)));
+    }
+
+    let is_p2sh_p2wpkh = spk.is_p2sh()
+        && psbt_input
+            .redeem_script
+            .as_ref()
+            .map_or(false, |script| script.is_p2wpkh());
+    if !(spk.is_p2wpkh() || spk.is_p2pkh() || is_p2sh_p2wpkh) {
+        return Ok(None);
+    }
+    for (pk, origin) in &psbt_input.bip32_derivation {
+        // The receiver skips inputs with uncompressed keys.
+        if ScriptBuf::new_p2pkh(&bitcoin::PublicKey::new_uncompressed(*pk).pubkey_hash()) == *spk {
+            return Ok(None);
+        }
+        let pk = bitcoin::PublicKey::new(*pk);
+        let compressed = bitcoin::CompressedPublicKey(pk.inner);
+        let matches = ScriptBuf::new_p2wpkh(&compressed.wpubkey_hash()) == *spk
+            || ScriptBuf::new_p2pkh(&pk.pubkey_hash()) == *spk
+            || ScriptBuf::new_p2wpkh(&compressed.wpubkey_hash()).to_p2sh() == *spk;
+        if !matches {
+            continue;
+        }
+        let requests = [KeyRequest::Bip32(origin.clone()), KeyRequest::Pubkey(pk)];
+        if let Some(sk) = request_key(signer, requests, secp)? {
+            if sk.public_key(secp) == pk.inner {
+                return Ok(Some(sk));
+            }
+        }
+    }
+    Err(missing_key())
+}
+
+impl Selection {
+    /// Resolve every [`ScriptSource::SilentPayment`] output into its BIP-352 taproot output.
+    ///
+    /// Silent payment outputs are selected with a placeholder P2TR script of the same weight. The
+    /// real output key depends on the private keys and outpoints of the selected inputs, so this
+    /// must be called after selection and before [`create_psbt`](Self::create_psbt). Outputs to
+    /// the same scan key are numbered in the order they appear in [`outputs`](Self::outputs).
+    ///
+    /// # Errors
+    ///
+    /// - If `signer` does not know the private key of an eligible input.
+    /// - If none of the inputs are eligible.
+    /// - If any input spends a segwit output newer than version 1.
+    pub fn resolve_silent_payments<C: secp256k1::Signing + secp256k1::Verification>(
+        &mut self,
+        signer: &Signer,
+        secp: &Secp256k1<C>,
+    ) -> Result<(), SilentPaymentError> {
+        if !self
+            .outputs
+            .iter()
+            .any(|output| output.script_pubkey_source.silent_payment().is_some())
+        {
+            return Ok(());
+        }
+
+        // Future segwit versions may change how keys are revealed, so the receiver could not scan
+        // for the output.
+        if let Some(input) = self.inputs.iter().find(|input| {
+            input
+                .prev_txout()
+                .script_pubkey
+                .witness_version()
+                .map_or(false, |version| version.to_num() > 1)
+        }) {
+            return Err(SilentPaymentError::UnsupportedWitnessVersion(
+                input.prev_outpoint(),
+            ));
+        }
+
+        let mut input_keys = Vec::new();
+        for input in &self.inputs {
+            if let Some(sk) = input_secret_key(input, signer, secp)? {
+                input_keys.push(sk);
+            }
+        }
+        let addresses = self
+            .outputs
+            .iter()
+            .filter_map(|output| output.script_pubkey_source.silent_payment().copied())
+            .collect::<Vec<_>>();
+        let mut scripts = silent_payment_scripts(
+            &input_keys,
+            self.inputs.iter().map(Input::prev_outpoint),
+            &addresses,
+            secp,
+        )?
+        .into_iter();
+        for output in &mut self.outputs {
+            if output.script_pubkey_source.silent_payment().is_some() {
+                let script = scripts.next().expect("one script per address");
+                output.script_pubkey_source = ScriptSource::from_script(script);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use alloc::string::ToString;
+    use bdk_coin_select::DrainWeights;
+    use bitcoin::hex::DisplayHex;
+    use bitcoin::{Amount, FeeRate};
+
+    use miniscript::descriptor::KeyMap;
+    use miniscript::plan::Assets;
+    use miniscript::{Descriptor, ForEachKey};
+
+    use crate::test_utils::Wallet;
+    use crate::{
+        ChangePolicyType, CreatePsbtError, InputCandidates, Output, PsbtParams, Selector,
+        SelectorParams,
+    };
+
+    fn receiver_keys() -> (SecretKey, SecretKey) {
+        (
+            SecretKey::from_slice(&[1; 32]).unwrap(),
+            SecretKey::from_slice(&[2; 32]).unwrap(),
+        )
+    }
+
+    fn address() -> SilentPaymentAddress {
+        let secp = Secp256k1::new();
+        let (scan, spend) = receiver_keys();
+        SilentPaymentAddress::from_keys(
+            NetworkKind::Main,
+            scan.public_key(&secp),
+            spend.public_key(&secp),
+        )
+    }
+
+    #[test]
+    fn address_round_trip() {
+        let address = address();
+        let s = address.to_string();
+        assert!(s.starts_with("sp1q"), "{}", s);
+        assert_eq!(s.parse::<SilentPaymentAddress>().unwrap(), address);
+
+        let testnet = SilentPaymentAddress {
+            network: NetworkKind::Test,
+            ..address
+        };
+        let s = testnet.to_string();
+        assert!(s.starts_with("tsp1q"), "{}", s);
+        assert_eq!(s.parse::<SilentPaymentAddress>().unwrap(), testnet);
+
+        let res = "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0"
+            .parse::<SilentPaymentAddress>();
+        assert!(matches!(
+            res,
+            Err(ParseSilentPaymentAddressError::UnknownHrp(_))
+        ));
+    }
+
+    #[test]
+    fn resolve_silent_payments_matches_receiver() {
+        let secp = Secp256k1::new();
+        let tr = Wallet::new(bdk_testenv::utils::DESCRIPTORS[0]);
+        let wpkh = Wallet::new(bdk_testenv::utils::DESCRIPTORS[2]);
+        let inputs = [tr.input(1, 30_000), wpkh.input(2, 30_000)];
+
+        let mut selection = InputCandidates::new(inputs.clone(), [])
+            .into_selection(
+                |s: &mut Selector| s.select_until_target_met(),
+                SelectorParams::new(
+                    FeeRate::from_sat_per_vb_u32(2),
+                    vec![
+                        Output::with_silent_payment(address(), Amount::from_sat(20_000)),
+                        Output::with_script(wpkh.at(5).script_pubkey(), Amount::from_sat(10_000)),
+                        Output::with_silent_payment(address(), Amount::from_sat(15_000)),
+                    ],
+                    ScriptSource::from_descriptor(wpkh.at(1)),
+                    ChangePolicyType::NoDust,
+                    DrainWeights::TR_KEYSPEND,
+                ),
+            )
+            .unwrap();
+        assert!(matches!(
+            selection.create_psbt(PsbtParams::default()),
+            Err(CreatePsbtError::UnresolvedSilentPayment(0))
+        ));
+
+        let res = selection
+            .clone()
+            .resolve_silent_payments(&Signer(KeyMap::new()), &secp);
+        assert!(matches!(res, Err(SilentPaymentError::MissingKey(_))));
+
+        let mut keymap = tr.keymap.clone();
+        keymap.extend(wpkh.keymap.clone());
+        selection
+            .resolve_silent_payments(&Signer(keymap), &secp)
+            .unwrap();
+
+        // Scan as the receiver, who only knows the input public keys.
+        let tr_output_key = {
+            let spk = inputs[0].prev_txout().script_pubkey.as_bytes();
+            let xonly = secp256k1::XOnlyPublicKey::from_slice(&spk[2..]).unwrap();
+            PublicKey::from_x_only_public_key(xonly, Parity::Even)
+        };
+        let input_pubkey = tr_output_key.combine(&wpkh.public_key()).unwrap();
+        let smallest_outpoint = inputs
+            .iter()
+            .map(|input| {
+                let mut bytes = Vec::new();
+                input.prev_outpoint().consensus_encode(&mut bytes).unwrap();
+                bytes
+            })
+            .min()
+            .unwrap();
+        let input_hash = tagged_hash(INPUTS_TAG, &[&smallest_outpoint, &input_pubkey.serialize()]);
+        let (scan, spend) = receiver_keys();
+        let shared_secret = input_pubkey
+            .mul_tweak(
+                &secp,
+                &scan.mul_tweak(&scalar(input_hash).unwrap()).unwrap().into(),
+            )
+            .unwrap();
+        let expected = (0_u32..2)
+            .map(|k| {
+                let t_k = tagged_hash(
+                    SHARED_SECRET_TAG,
+                    &[&shared_secret.serialize(), &k.to_be_bytes()],
+                );
+                let (output_key, _) = spend
+                    .public_key(&secp)
+                    .add_exp_tweak(&secp, &scalar(t_k).unwrap())
+                    .unwrap()
+                    .x_only_public_key();
+                ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(output_key))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(selection.outputs[0].script_pubkey(), expected[0]);
+        assert_eq!(selection.outputs[2].script_pubkey(), expected[1]);
+        assert_ne!(expected[0], address().placeholder_script());
+        assert!(selection.create_psbt(PsbtParams::default()).is_ok());
+    }
+
+    // BIP-352 sending test vectors "Simple send: two inputs" and "Single recipient: taproot only
+    // inputs with even y-values".
+    const VECTOR_TXIDS: [&str; 2] = [
+        "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+        "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d",
+    ];
+    const VECTOR_ADDRESS: &str = "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv";
+
+    /// Input spending output 0 of `txid` with the `script` descriptor (e.g. `pkh`) of `sk`, and
+    /// the keymap to sign for it.
+    fn vector_input(script: &str, txid: &str, sk: bitcoin::PrivateKey) -> (Input, KeyMap) {
+        let secp = Secp256k1::new();
+        let (desc, keymap) =
+            Descriptor::parse_descriptor(&secp, &alloc::format!("{}({})", script, sk.to_wif()))
+                .unwrap();
+        let mut pks = Vec::new();
+        desc.for_each_key(|k| {
+            pks.push(k.clone());
+            true
+        });
+        let desc = desc.at_derivation_index(0).unwrap();
+        let plan = desc.clone().plan(&Assets::new().add(pks)).unwrap();
+        let txout = bitcoin::TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey: desc.script_pubkey(),
+        };
+        let outpoint = OutPoint::new(txid.parse().unwrap(), 0);
+        let input = Input::from_prev_txout(plan, outpoint, txout, None, false);
+        (input, keymap)
+    }
+
+    fn vector_key(sk: &str) -> bitcoin::PrivateKey {
+        bitcoin::PrivateKey::new(SecretKey::from_str(sk).unwrap(), NetworkKind::Main)
+    }
+
+    /// Resolve a payment to `address` which spends all `inputs`.
+    fn resolve(
+        inputs: Vec<Input>,
+        keymap: KeyMap,
+        address: SilentPaymentAddress,
+    ) -> Result<ScriptBuf, SilentPaymentError> {
+        let mut selection = InputCandidates::new(inputs, [])
+            .into_selection(
+                |s: &mut Selector| s.select_until_target_met(),
+                SelectorParams::new(
+                    FeeRate::from_sat_per_vb_u32(2),
+                    vec![Output::with_silent_payment(
+                        address,
+                        Amount::from_sat(20_000),
+                    )],
+                    ScriptSource::from_script(address.placeholder_script()),
+                    ChangePolicyType::NoDust,
+                    DrainWeights::TR_KEYSPEND,
+                ),
+            )
+            .unwrap();
+        selection.resolve_silent_payments(&Signer(keymap), &Secp256k1::new())?;
+        Ok(selection.outputs[0].script_pubkey())
+    }
+
+    #[test]
+    fn bip352_simple_send() {
+        let keys = [
+            "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+            "93f5ed907ad5b2bdbbdcb5d9116ebc0a4e1f92f910d5260237fa45a9408aad16",
+        ];
+        let address = VECTOR_ADDRESS.parse::<SilentPaymentAddress>().unwrap();
+        let expected = "3e9fce73d4e77a4809908e3c3a2e54ee147b9312dc5044a193d1fc85de46e3c1";
+
+        // The output only depends on the input keys, so P2WPKH inputs of the same keys give the
+        // same output as the P2PKH inputs of the test vector.
+        for script in ["pkh", "wpkh"] {
+            let mut inputs = Vec::new();
+            let mut keymap = KeyMap::new();
+            for (txid, sk) in VECTOR_TXIDS.iter().zip(keys) {
+                let (input, km) = vector_input(script, txid, vector_key(sk));
+                inputs.push(input);
+                keymap.extend(km);
+            }
+            let spk = resolve(inputs, keymap, address).unwrap();
+            assert_eq!(
+                spk.as_bytes()[2..].to_lower_hex_string(),
+                expected,
+                "{}",
+                script
+            );
+        }
+    }
+
+    #[test]
+    fn bip352_taproot_even_y() {
+        let secp = Secp256k1::new();
+        let input_keys = [
+            "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+            "fc8716a97a48ba9a05a98ae47b5cd201a25a7fd5d8b73c203c5f7b6b6b3b6ad7",
+        ]
+        .map(|sk| taproot_secret_key(&Keypair::from_seckey_str(&secp, sk).unwrap()));
+        let outpoints = VECTOR_TXIDS.map(|txid| OutPoint::new(txid.parse().unwrap(), 0));
+        let address = VECTOR_ADDRESS.parse::<SilentPaymentAddress>().unwrap();
+        let scripts = silent_payment_scripts(&input_keys, outpoints, &[address], &secp).unwrap();
+        assert_eq!(
+            scripts[0].as_bytes()[2..].to_lower_hex_string(),
+            "de88bea8e7ffc9ce1af30d1132f910323c505185aec8eae361670421e749a1fb"
+        );
+
+        // An odd y-coordinate negates the key.
+        let keypair = Keypair::from_seckey_slice(&secp, &[3; 32]).unwrap();
+        let (xonly, parity) = keypair.x_only_public_key();
+        let sk = taproot_secret_key(&keypair);
+        assert_eq!(
+            sk.public_key(&secp),
+            PublicKey::from_x_only_public_key(xonly, Parity::Even)
+        );
+        assert_eq!(
+            sk == SecretKey::from_keypair(&keypair),
+            parity == Parity::Even
+        );
+    }
+
+    #[test]
+    fn ineligible_inputs_are_skipped() {
+        let address = address();
+
+        // P2PKH with an uncompressed key
+        let mut sk = vector_key("eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1");
+        sk.compressed = false;
+        let (uncompressed, keymap) = vector_input("pkh", VECTOR_TXIDS[0], sk);
+        assert!(matches!(
+            resolve(vec![uncompressed.clone()], keymap.clone(), address),
+            Err(SilentPaymentError::NoEligibleInputs)
+        ));
+
+        // Taproot with the NUMS internal key
+        let nums = secp256k1::XOnlyPublicKey::from_slice(&NUMS_H).unwrap();
+        let psbt_input = bitcoin::psbt::Input {
+            witness_utxo: Some(bitcoin::TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: address.placeholder_script(),
+            }),
+            tap_internal_key: Some(nums),
+            ..Default::default()
+        };
+        let outpoint = OutPoint::new(VECTOR_TXIDS[1].parse().unwrap(), 0);
+        let nums_input = Input::from_psbt_input(
+            outpoint,
+            bitcoin::Sequence::ENABLE_RBF_NO_LOCKTIME,
+            psbt_input,
+            100,
+            None,
+            false,
+        )
+        .unwrap();
+        assert!(matches!(
+            resolve(vec![nums_input.clone()], KeyMap::new(), address),
+            Err(SilentPaymentError::NoEligibleInputs)
+        ));
+
+        // Skipped inputs still count towards the smallest outpoint.
+        let (eligible, keymap) = vector_input(
+            "wpkh",
+            VECTOR_TXIDS[1],
+            vector_key("93f5ed907ad5b2bdbbdcb5d9116ebc0a4e1f92f910d5260237fa45a9408aad16"),
+        );
+        let eligible_op = eligible.prev_outpoint();
+        let sk = vector_key("93f5ed907ad5b2bdbbdcb5d9116ebc0a4e1f92f910d5260237fa45a9408aad16");
+        let secp = Secp256k1::new();
+        let expected = silent_payment_scripts(
+            &[sk.inner],
+            [uncompressed.prev_outpoint(), eligible_op],
+            &[address],
+            &secp,
+        )
+        .unwrap();
+        assert_eq!(
+            resolve(vec![uncompressed, eligible], keymap, address).unwrap(),
+            expected[0]
+        );
+    }
+
+    #[test]
+    fn future_witness_version_is_rejected() {
+        let address = address();
+        let (eligible, keymap) = vector_input(
+            "wpkh",
+            VECTOR_TXIDS[0],
+            vector_key("eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1"),
+        );
+        let v2_program = bitcoin::script::Builder::new()
+            .push_opcode(bitcoin::opcodes::all::OP_PUSHNUM_2)
+            .push_slice([7; 32])
+            .into_script();
+        let psbt_input = bitcoin::psbt::Input {
+            witness_utxo: Some(bitcoin::TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: v2_program,
+            }),
+            ..Default::default()
+        };
+        let outpoint = OutPoint::new(VECTOR_TXIDS[1].parse().unwrap(), 0);
+        let v2_input = Input::from_psbt_input(
+            outpoint,
+            bitcoin::Sequence::ENABLE_RBF_NO_LOCKTIME,
+            psbt_input,
+            100,
+            None,
+            false,
+        )
+        .unwrap();
+        assert!(matches!(
+            resolve(vec![eligible, v2_input], keymap, address),
+            Err(SilentPaymentError::UnsupportedWitnessVersion(op)) if op == outpoint
+        ));
+    }
+}
@@ -0,0 +1,113 @@
+//! Fixtures shared by the unit tests.
+
+use alloc::vec::Vec;
+
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::{PublicKey, Secp256k1};
+use bitcoin::{
+    absolute, transaction, Amount, OutPoint, Psbt, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+    Txid, WPubkeyHash,
+};
+use miniscript::bitcoin;
+use miniscript::descriptor::KeyMap;
+use miniscript::plan::{Assets, Plan};
+use miniscript::{Descriptor, DescriptorPublicKey, ForEachKey};
+
+use crate::{DefiniteDescriptor, Input, ScriptSource, Signer, TxStatus};
+
+/// Public key of the secp256k1 generator.
+pub const PK: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+/// P2WPKH descriptor of [`PK`].
+pub fn descriptor() -> DefiniteDescriptor {
+    format!("wpkh({PK})").parse().unwrap()
+}
+
+/// Plan to spend [`descriptor`].
+pub fn plan() -> Plan {
+    let assets = Assets::new().add(PK.parse::<DescriptorPublicKey>().unwrap());
+    descriptor().plan(&assets).unwrap()
+}
+
+/// P2WPKH script pubkey made unique by `n`.
+pub fn spk(n: u8) -> ScriptBuf {
+    ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([n; 20]))
+}
+
+/// Confirmed status.
+pub fn confirmed() -> Option<TxStatus> {
+    Some(TxStatus::new(800_000, 1_700_000_000).unwrap())
+}
+
+/// Version 2 tx signalling RBF.
+pub fn tx(inputs: &[OutPoint], outputs: &[(ScriptBuf, u64)]) -> Transaction {
+    Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: inputs
+            .iter()
+            .map(|&previous_output| TxIn {
+                previous_output,
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                ..Default::default()
+            })
+            .collect(),
+        output: outputs
+            .iter()
+            .map(|(script_pubkey, value)| TxOut {
+                value: Amount::from_sat(*value),
+                script_pubkey: script_pubkey.clone(),
+            })
+            .collect(),
+    }
+}
+
+/// Wallet of a single descriptor with its private keys.
+pub struct Wallet {
+    pub desc: Descriptor<DescriptorPublicKey>,
+    pub keymap: KeyMap,
+}
+
+impl Wallet {
+    pub fn new(desc: &str) -> Self {
+        let (desc, keymap) = Descriptor::parse_descriptor(&Secp256k1::new(), desc).unwrap();
+        Self { desc, keymap }
+    }
+
+    pub fn at(&self, index: u32) -> DefiniteDescriptor {
+        self.desc.at_derivation_index(index).unwrap()
+    }
+
+    /// Public key of index 0.
+    pub fn public_key(&self) -> PublicKey {
+        let mut pk = None;
+        self.at(0).for_each_key(|k| {
+            pk = Some(k.derive_public_key(&Secp256k1::new()).unwrap().inner);
+            true
+        });
+        pk.unwrap()
+    }
+
+    /// Confirmed input of `value` paying to index 0.
+    pub fn input(&self, n: u8, value: u64) -> Input {
+        let prev_tx = tx(
+            &[OutPoint::new(Txid::from_byte_array([n; 32]), 0)],
+            &[(self.at(0).script_pubkey(), value)],
+        );
+        let mut pks = Vec::new();
+        self.desc.for_each_key(|k| {
+            pks.extend(k.clone().into_single_keys());
+            true
+        });
+        let plan = self.at(0).plan(&Assets::new().add(pks)).unwrap();
+        Input::from_prev_tx(plan, prev_tx, 0, confirmed()).unwrap()
+    }
+
+    pub fn change(&self) -> ScriptSource {
+        ScriptSource::from_descriptor(self.at(1))
+    }
+
+    pub fn sign(&self, psbt: &mut Psbt) {
+        let _ = psbt.sign(&Signer(self.keymap.clone()), &Secp256k1::new());
+    }
+}
@@ -0,0 +1,337 @@
+use core::fmt::Display;
+
+use bitcoin::{transaction, Txid};
+use miniscript::bitcoin;
+
+use crate::{Input, Selection};
+
+/// Version of a TRUC (Topologically Restricted Until Confirmation) tx, see BIP-431.
+pub const TRUC_VERSION: transaction::Version = transaction::Version(3);
+
+/// Maximum virtual size of a TRUC tx.
+pub const TRUC_MAX_VSIZE: u64 = 10_000;
+
+/// Maximum virtual size of a TRUC tx that has an unconfirmed parent.
+pub const TRUC_CHILD_MAX_VSIZE: u64 = 1_000;
+
+/// Occurs when a tx breaks the TRUC topology rules of BIP-431.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrucViolation {
+    /// The tx spends from more than one unconfirmed tx.
+    MultipleUnconfirmedParents,
+    /// The unconfirmed parent is not a TRUC tx (or the parent tx is unknown).
+    ParentNotTruc(Txid),
+    /// The unconfirmed parent has unconfirmed ancestors of its own.
+    ParentHasUnconfirmedAncestors(Txid),
+    /// The tx exceeds the maximum virtual size.
+    TooLarge {
+        /// Predicted virtual size of the tx.
+        vsize: u64,
+        /// Maximum virtual size allowed.
+        max_vsize: u64,
+    },
+}
+
+impl Display for TrucViolation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TrucViolation::MultipleUnconfirmedParents => {
+                write!(f, "TRUC tx cannot have more than one unconfirmed parent")
+            }
+            TrucViolation::ParentNotTruc(txid) => {
+                write!(f, "unconfirmed parent {} is not a TRUC tx", txid)
+            }
+            TrucViolation::ParentHasUnconfirmedAncestors(txid) => {
+                write!(f, "unconfirmed parent {} has unconfirmed ancestors", txid)
+            }
+            TrucViolation::TooLarge { vsize, max_vsize } => write!(
+                f,
+                "TRUC tx of {} vB exceeds the maximum of {} vB",
+                vsize, max_vsize
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TrucViolation {}
+
+/// Returns the unconfirmed parent of `input` if it can be spent by a TRUC tx.
+fn truc_parent(input: &Input) -> Result<Option<Txid>, TrucViolation> {
+    if input.status().is_some() {
+        return Ok(None);
+    }
+    let parent = input.prev_outpoint().txid;
+    if input.prev_tx().map(|tx| tx.version) != Some(TRUC_VERSION) {
+        return Err(TrucViolation::ParentNotTruc(parent));
+    }
+    if !input.ancestors().is_complete() || input.ancestors().txids().any(|txid| txid != parent) {
+        return Err(TrucViolation::ParentHasUnconfirmedAncestors(parent));
+    }
+    Ok(Some(parent))
+}
+
+/// Filter out inputs that cannot be spent by a TRUC tx with the given unconfirmed `parent`.
+///
+/// Confirmed inputs are always kept. Unconfirmed inputs are only kept if they are outputs of
+/// `parent` and `parent` is a TRUC tx with no unconfirmed ancestors. If `parent` is `None`, all
+/// unconfirmed inputs are filtered out.
+pub fn filter_truc(parent: Option<Txid>) -> impl Fn(&Input) -> bool {
+    move |input| match truc_parent(input) {
+        Ok(None) => true,
+        Ok(Some(txid)) => Some(txid) == parent,
+        Err(_) => false,
+    }
+}
+
+impl Selection {
+    /// Check that a TRUC tx created from this selection satisfies the rules of BIP-431.
+    ///
+    /// The tx may have at most one unconfirmed parent which must itself be a TRUC tx without
+    /// unconfirmed ancestors. The tx is limited to [`TRUC_CHILD_MAX_VSIZE`] if it has an
+    /// unconfirmed parent, and [`TRUC_MAX_VSIZE`] otherwise.
+    ///
+    /// [`Selector::try_finalize`](crate::Selector::try_finalize) runs this check for TRUC
+    /// selections, and [`create_psbt`](Selection::create_psbt) creates them with
+    /// [`TRUC_VERSION`].
+    pub fn check_truc(&self) -> Result<(), TrucViolation> {
+        let mut parent = Option::<Txid>::None;
+        for input in &self.inputs {
+            if let Some(txid) = truc_parent(input)? {
+                if matches!(parent, Some(parent) if parent != txid) {
+                    return Err(TrucViolation::MultipleUnconfirmedParents);
+                }
+                parent = Some(txid);
+            }
+        }
+        let max_vsize = match parent {
+            Some(_) => TRUC_CHILD_MAX_VSIZE,
+            None => TRUC_MAX_VSIZE,
+        };
+        let vsize = self.predicted_weight().to_vbytes_ceil();
+        if vsize > max_vsize {
+            return Err(TrucViolation::TooLarge { vsize, max_vsize });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use alloc::vec::Vec;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{absolute, Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut};
+
+    use crate::test_utils::{descriptor, plan, spk};
+    use crate::{
+        AncestorPackage, ChangePolicyType, InputCandidates, IntoSelectionError, Output, PsbtParams,
+        ScriptSource, Selector, SelectorParams, TxStatus,
+    };
+
+    /// Input spending output 0 of a tx of `version`, `n` is used to make the tx unique.
+    fn input(n: u8, version: i32, confirmed: bool) -> Input {
+        let prev_tx = Transaction {
+            version: transaction::Version(version),
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([n; 32]), 0),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: descriptor().script_pubkey(),
+            }],
+        };
+        let plan = plan();
+        let status = confirmed.then(|| TxStatus::new(800_000, 1_700_000_000).unwrap());
+        Input::from_prev_tx(plan, prev_tx, 0, status).unwrap()
+    }
+
+    fn selection(inputs: Vec<Input>, outputs: Vec<Output>) -> Selection {
+        Selection {
+            inputs,
+            outputs,
+            change_indices: vec![],
+            truc: true,
+        }
+    }
+
+    fn output() -> Output {
+        Output::with_script(spk(1), Amount::from_sat(50_000))
+    }
+
+    /// An output large enough to break the child size limit.
+    fn large_output() -> Output {
+        Output::with_script(ScriptBuf::from_bytes(vec![0x51; 1_000]), Amount::ZERO)
+    }
+
+    #[test]
+    fn check_truc_topology() {
+        let confirmed = input(1, 2, true);
+        let parent = input(2, 3, false);
+        let other_parent = input(3, 3, false);
+        let non_truc_parent = input(4, 2, false);
+
+        assert_eq!(
+            selection(vec![confirmed.clone(), parent.clone()], vec![output()]).check_truc(),
+            Ok(())
+        );
+        assert_eq!(
+            selection(
+                vec![confirmed.clone(), non_truc_parent.clone()],
+                vec![output()]
+            )
+            .check_truc(),
+            Err(TrucViolation::ParentNotTruc(
+                non_truc_parent.prev_outpoint().txid
+            ))
+        );
+        assert_eq!(
+            selection(vec![parent.clone(), other_parent], vec![output()]).check_truc(),
+            Err(TrucViolation::MultipleUnconfirmedParents)
+        );
+
+        let mut ancestors = AncestorPackage::default();
+        ancestors.insert(
+            parent.prev_outpoint().txid,
+            bitcoin::Weight::from_wu(400),
+            Amount::ZERO,
+        );
+        ancestors.insert(
+            Txid::from_byte_array([9; 32]),
+            bitcoin::Weight::from_wu(400),
+            Amount::ZERO,
+        );
+        let parent_with_ancestors = parent.with_ancestors(ancestors);
+        assert_eq!(
+            selection(vec![parent_with_ancestors.clone()], vec![output()]).check_truc(),
+            Err(TrucViolation::ParentHasUnconfirmedAncestors(
+                parent_with_ancestors.prev_outpoint().txid
+            ))
+        );
+    }
+
+    #[test]
+    fn check_truc_size() {
+        let confirmed = input(1, 2, true);
+        let parent = input(2, 3, false);
+
+        assert_eq!(
+            selection(vec![confirmed.clone()], vec![output(), large_output()]).check_truc(),
+            Ok(())
+        );
+        assert!(matches!(
+            selection(vec![confirmed, parent], vec![output(), large_output()]).check_truc(),
+            Err(TrucViolation::TooLarge {
+                max_vsize: TRUC_CHILD_MAX_VSIZE,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn filter_truc_candidates() {
+        let confirmed = input(1, 2, true);
+        let parent = input(2, 3, false);
+        let other_parent = input(3, 3, false);
+        let non_truc_parent = input(4, 2, false);
+        let candidates = InputCandidates::new(
+            [],
+            [
+                confirmed.clone(),
+                parent.clone(),
+                other_parent,
+                non_truc_parent,
+            ],
+        );
+
+        let kept = |parent: Option<Txid>| {
+            candidates
+                .clone()
+                .filter(filter_truc(parent))
+                .inputs()
+                .map(|input| input.prev_outpoint())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(kept(None), [confirmed.prev_outpoint()]);
+        assert_eq!(
+            kept(Some(parent.prev_outpoint().txid)),
+            [confirmed.prev_outpoint(), parent.prev_outpoint()]
+        );
+    }
+
+    #[test]
+    fn truc_selection() {
+        let candidates = InputCandidates::new([input(2, 3, false)], [input(1, 2, true)]);
+        let params = |outputs: Vec<Output>| {
+            let mut params = SelectorParams::new(
+                bitcoin::FeeRate::from_sat_per_vb_u32(1),
+                outputs,
+                ScriptSource::from_descriptor(descriptor()),
+                ChangePolicyType::NoDust,
+                bdk_coin_select::DrainWeights::TR_KEYSPEND,
+            );
+            params.truc = true;
+            params
+        };
+
+        let selection = candidates
+            .clone()
+            .into_selection(
+                |s: &mut Selector| s.select_until_target_met(),
+                params(vec![output()]),
+            )
+            .unwrap();
+        assert!(selection.truc);
+        let psbt = selection.create_psbt(PsbtParams::default()).unwrap();
+        assert_eq!(psbt.unsigned_tx.version, TRUC_VERSION);
+
+        let res = candidates.into_selection(
+            |s: &mut Selector| s.select_until_target_met(),
+            params(vec![output(), large_output()]),
+        );
+        assert!(matches!(
+            res,
+            Err(IntoSelectionError::Truc(TrucViolation::TooLarge { .. }))
+        ));
+    }
+
+    #[test]
+    fn truc_selection_respects_child_size_limit() {
+        let parent = input(2, 3, false);
+        let confirmed = input(1, 2, true);
+        let candidates = InputCandidates::new([], [parent, confirmed.clone()]);
+        let mut params = SelectorParams::new(
+            bitcoin::FeeRate::from_sat_per_vb_u32(1),
+            vec![output(), large_output()],
+            ScriptSource::from_descriptor(descriptor()),
+            ChangePolicyType::NoDust,
+            bdk_coin_select::DrainWeights::TR_KEYSPEND,
+        );
+        params.truc = true;
+        let mut selector = Selector::new(&candidates, params).unwrap();
+
+        // spending the unconfirmed parent would exceed the child size limit
+        selector.select_until_target_met().unwrap();
+        let selection = selector.try_finalize().unwrap();
+        assert_eq!(
+            selection
+                .inputs
+                .iter()
+                .map(Input::prev_outpoint)
+                .collect::<Vec<_>>(),
+            [confirmed.prev_outpoint()]
+        );
+        assert_eq!(selection.check_truc(), Ok(()));
+
+        selector.select_all();
+        assert_eq!(
+            selector.max_truc_weight(),
+            bitcoin::Weight::from_vb_unchecked(TRUC_CHILD_MAX_VSIZE)
+        );
+        assert!(selector.try_finalize().is_none());
+    }
+}
@@ -249,47 +249,6 @@
 //     }
 //
 //     #[test]
-//     fn test_build_tx_insane_fee() {
-//         let mut graph = init_graph(&get_single_sig_tr_xprv());
-//
-//         let recip = ScriptBuf::from_hex(SPK).unwrap();
-//         let mut builder = Builder::new();
-//         builder.add_output(recip, Amount::from_btc(0.01).unwrap());
-//
-//         let selection = graph
-//             .planned_utxos()
-//             .into_iter()
-//             .take(3)
-//             .collect::<Vec<_>>();
-//         assert_eq!(
-//             selection
-//                 .iter()
-//                 .map(|p| p.txout.value)
-//                 .sum::<Amount>()
-//                 .to_btc(),
-//             0.03
-//         );
-//         builder.add_inputs(selection);
-//
-//         let err = builder.build_tx().unwrap_err();
-//         assert!(matches!(err, Error::InsaneFee(..)));
-//     }
-//
-//     #[test]
-//     fn test_build_tx_negative_fee() {
-//         let mut graph = init_graph(&get_single_sig_tr_xprv());
-//
-//         let recip = ScriptBuf::from_hex(SPK).unwrap();
-//
-//         let mut builder = Builder::new();
-//         builder.add_output(recip, Amount::from_btc(0.02).unwrap());
-//         builder.add_inputs(graph.planned_utxos().into_iter().take(1));
-//
-//         let err = builder.build_tx().unwrap_err();
-//         assert!(matches!(err, Error::NegativeFee(..)));
-//     }
-//
-//     #[test]
 //     fn test_build_tx_add_data() {
 //         let mut graph = init_graph(&get_single_sig_tr_xprv());
 //